@@ -7,6 +7,8 @@ ckb-debugger --mode full --bin always_failure 2>&1 | grep "Run result: 1"
 
 ckb-debugger --mode fast --tx-file exec.json --cell-index 0 --cell-type input --script-group-type lock
 ckb-debugger --mode full --tx-file exec.json --cell-index 0 --cell-type input --script-group-type lock
+ckb-debugger --tx-file exec.json --cell-index 0 --cell-type input --script-group-type lock --snapshot-at-cycles 4000 -o /tmp/exec.snapshot
+ckb-debugger --mode full --tx-file exec.json --cell-index 0 --cell-type input --script-group-type lock --resume-from /tmp/exec.snapshot | grep 8566
 
 ckb-debugger --mode fast --bin fib
 ckb-debugger --mode full --bin fib --pprof /tmp/fib.pprof
@@ -14,12 +16,37 @@ ckb-debugger --mode full --bin fib --enable-overlapping-detection
 ckb-debugger --mode full --bin fib --enable-steplog
 ckb-debugger --mode fast --bin fib --max-cycles 100 2>&1 | grep "ExceededMaximumCycles"
 ckb-debugger --mode full --bin fib --max-cycles 100 2>&1 | grep "CyclesExceeded"
+printf "b fib\nc\nbt\nc\nq\n" | ckb-debugger --mode repl --bin fib | grep "fib.c:12:main"
+ckb-debugger --mode disasm --bin fib | grep "<fib>:"
+ckb-debugger --mode full --bin fib --annotate fib | grep "Function fib: 809 cycles"
+ckb-debugger --mode full --bin fib --pprof /tmp/fib.txt --pprof-lines && grep "fib.c:12:main; /code/fib.c:4:fib" /tmp/fib.txt
+ckb-debugger --mode full --tx-file spawn.json --cell-index 0 --cell-type input --script-group-type lock --pprof /tmp/spawn.pb --pprof-format proto
+ckb-debugger --mode profile-diff --profile-base /tmp/spawn.pb --profile-new /tmp/spawn.pb --profile-threshold 0 -o /tmp/spawn.diff
+ckb-debugger --mode profile-diff --profile-base /tmp/fib.txt --profile-new /tmp/spawn.pb --profile-threshold 5 | grep "Regression:"
+ckb-debugger --mode disasm --tx-file spawn.json --cell-index 0 --cell-type input --script-group-type lock | grep "macro-op fusions"
 
 ckb-debugger --mode full --bin file_operations | grep "Run result: 0"
 
 ckb-debugger --mode fast --tx-file mock_tx.json --cell-index 0 --cell-type input --script-group-type lock
 ckb-debugger --mode full --tx-file mock_tx.json --cell-index 0 --cell-type input --script-group-type lock
 ckb-debugger --mode full --tx-file mock_tx.json --cell-index 0 --cell-type input --script-group-type lock --bin always_failure 2>&1 | grep "Run result: 1"
+ckb-debugger --mode all --tx-file mock_tx.json | grep "All cycles"
+ckb-debugger --mode lint --tx-file mock_tx.json | grep "0 errors"
+ckb-debugger --mode verify --tx-file mock_tx.json | grep "accepted"
+echo '{"max_cycles": 2000000, "scripts": [{"cell_type": "input", "cell_index": 0, "script_group_type": "lock", "max_cycles": 1700000}]}' > /tmp/budget.json
+ckb-debugger --mode all --tx-file mock_tx.json --budget /tmp/budget.json | grep "ok: lock"
+echo '{"scripts": [{"cell_type": "input", "cell_index": 1, "script_group_type": "lock", "max_cycles": 1000000}]}' > /tmp/budget.json
+ckb-debugger --mode all --tx-file mock_tx.json --budget /tmp/budget.json | grep "exceeded: lock"
+ckb-debugger --mode all --tx-file mock_tx.json --chain mainnet --epoch 6000,1,10 --block-number 10000000 | grep "All cycles"
+ckb-debugger --mode full --tx-file mock_tx.json --cell-index 0 --cell-type input --script-group-type lock --output-format json | grep "\"exit_code\": 0"
+ckb-debugger --mode full --tx-file mock_tx.json --cell-index 0 --cell-type input --script-group-type lock --trace-syscalls - | grep "load_witness(offset=0, index=2, source=group_input) = 1 (INDEX_OUT_OF_BOUND)"
+ckb-debugger --mode full --tx-file spawn.json --cell-index 0 --cell-type input --script-group-type lock --trace-syscalls /tmp/spawn.syscalls --trace-syscalls-format json && grep "\"name\":\"spawn\"" /tmp/spawn.syscalls
+echo '{"faults": [{"syscall": "spawn", "error": "MAX_VMS_SPAWNED"}]}' > /tmp/faults.json
+ckb-debugger --mode full --tx-file spawn.json --cell-index 0 --cell-type input --script-group-type lock --faults /tmp/faults.json 2>&1 | grep "Run result: 8"
+ckb-debugger --mode full --tx-file spawn.json --cell-index 0 --cell-type input --script-group-type lock --faults /tmp/faults.json --output-format json | grep "error MAX_VMS_SPAWNED"
+ckb-debugger --mode full --bin fib --coverage /tmp/fib.lcov | grep "Coverage: lines 10 of 366" && grep "DA:13,0" /tmp/fib.lcov
+ckb-debugger --mode full --bin fib --coverage /tmp/fib.lcov --output-format json | grep "\"lines_found\": 366"
+ckb-debugger --mode full --tx-file exec.json --cell-index 0 --cell-type input --script-group-type lock --coverage /tmp/exec.xml --coverage-merge /tmp/fib.lcov --coverage-format cobertura && grep "fib.c" /tmp/exec.xml
 
 ckb-debugger --mode fast --bin out_of_memory 2>&1 | grep "MemOutOfBound"
 ckb-debugger --mode full --bin out_of_memory 2>&1 | grep "MemOutOfBound"
@@ -31,7 +58,15 @@ ckb-debugger --bin read_file --read-file read_file.c | grep "Run result: 0"
 
 ckb-debugger --mode fast --tx-file spawn.json --cell-index 0 --cell-type input --script-group-type lock
 ckb-debugger --mode full --tx-file spawn.json --cell-index 0 --cell-type input --script-group-type lock
+ckb-debugger --mode full --tx-file spawn.json --cell-index 0 --cell-type input --script-group-type lock --chain testnet 2>&1 | grep "InvalidVmVersion(2)"
 ckb-debugger --mode full --tx-file spawn.json --cell-index 0 --cell-type input --script-group-type lock --pid 0 --pprof /tmp/spawn.pprof
 ckb-debugger --mode full --tx-file spawn.json --cell-index 0 --cell-type input --script-group-type lock --pid 1 --pprof /tmp/spawn.pprof
+ckb-debugger --mode full --tx-file spawn.json --cell-index 0 --cell-type input --script-group-type lock --pid 1 --watch 0x3fffc8:16 | grep "New value"
+ckb-debugger --mode full --tx-file spawn.json --cell-index 0 --cell-type input --script-group-type lock --trace /tmp/spawn.trace --trace-function strcat | grep "Trace: 38 instructions"
+ckb-debugger --mode full --tx-file spawn.json --cell-index 0 --cell-type input --script-group-type lock --trace /tmp/spawn.trace --trace-function strcat --output-format json | grep "\"count\": 38"
+ckb-debugger --mode trace-dump --trace /tmp/spawn.trace --output-format json | grep "\"pid\":1"
+ckb-debugger --mode compare --tx-file spawn.json --cell-index 0 --cell-type input --script-group-type lock --bin-b spawn_caller_strcat | grep "No divergence"
+ckb-debugger --mode compare --tx-file spawn.json --cell-index 0 --cell-type input --script-group-type lock --bin-b always_failure | grep "Divergence: syscall 1 differs"
+ckb-debugger --tx-file spawn.json --cell-index 0 --cell-type input --script-group-type lock --snapshot-at-cycles 50000 -o /tmp/spawn.snapshot 2>&1 | grep "Vms with open fds can not be saved"
 
 ckb-debugger --mode full --bin timenow
@@ -1,15 +1,11 @@
-use ckb_chain_spec::consensus::ConsensusBuilder;
+use crate::budget::BudgetCheck;
+use crate::chain_env::ChainEnv;
+use crate::lint::LintIssue;
 use ckb_mock_tx_types::{MockTransaction, ReprMockTransaction, Resource};
-use ckb_script::{ScriptGroupType, TransactionScriptsVerifier, TxVerifyEnv};
-use ckb_types::{
-    core::cell::resolve_transaction,
-    core::hardfork::{HardForks, CKB2021, CKB2023},
-    core::{Cycle, EpochNumberWithFraction, HeaderView},
-    packed::Byte32,
-    prelude::*,
-};
+use ckb_script::{ScriptGroupType, TransactionScriptsVerifier};
+use ckb_types::{core::cell::resolve_transaction, core::Cycle, packed::Byte32, prelude::*, H256};
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 use std::sync::Arc;
 use wasm_bindgen::prelude::*;
 
@@ -18,15 +14,24 @@ pub fn run(
     script_group_type: &ScriptGroupType,
     script_hash: &Byte32,
     max_cycle: Cycle,
+) -> Result<Cycle, Box<dyn std::error::Error>> {
+    run_with_env(mock_tx, script_group_type, script_hash, max_cycle, &ChainEnv::default())
+}
+
+// Same as run, but scripts are verified in the given chain environment.
+pub fn run_with_env(
+    mock_tx: &MockTransaction,
+    script_group_type: &ScriptGroupType,
+    script_hash: &Byte32,
+    max_cycle: Cycle,
+    env: &ChainEnv,
 ) -> Result<Cycle, Box<dyn std::error::Error>> {
     let resource = Resource::from_mock_tx(mock_tx)?;
     let resolve_transaction =
         resolve_transaction(mock_tx.core_transaction(), &mut HashSet::new(), &resource, &resource)?;
-    let hardforks = HardForks { ckb2021: CKB2021::new_dev_default(), ckb2023: CKB2023::new_dev_default() };
-    let consensus = Arc::new(ConsensusBuilder::default().hardfork_switch(hardforks).build());
-    let epoch = EpochNumberWithFraction::new(0, 0, 1);
-    let header = HeaderView::new_advanced_builder().epoch(epoch.pack()).build();
-    let tx_env = Arc::new(TxVerifyEnv::new_commit(&header));
+    env.check()?;
+    let consensus = Arc::new(env.consensus()?);
+    let tx_env = Arc::new(env.tx_verify_env());
     let mut verifier = TransactionScriptsVerifier::new(
         Arc::new(resolve_transaction),
         resource.clone(),
@@ -58,6 +63,70 @@ impl From<Result<Cycle, String>> for JsonResult {
     }
 }
 
+// A superset of JsonResult, used by the command line to report a run in a machine-readable way.
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Debug, Default)]
+pub struct JsonReport {
+    pub cycle: Option<Cycle>,
+    pub error: Option<String>,
+    pub exit_code: Option<i8>,
+    pub script_group_type: Option<ScriptGroupType>,
+    pub script_hash: Option<H256>,
+    // Cycles consumed by each vm, indexed by vm id. Only available when the script is run by the debugger itself.
+    pub vm_cycles: BTreeMap<u64, Cycle>,
+    pub messages: Vec<String>,
+    pub stack_trace: Vec<String>,
+    // Results of every script group, only available in all mode.
+    pub groups: Vec<JsonGroupReport>,
+    // Problems found by the checks other than scripts, only available in verify mode.
+    pub issues: Vec<LintIssue>,
+    // Processes profiled in full mode, with the vm that spawns them.
+    pub processes: Vec<JsonProcessReport>,
+    // Limits of the cycle budget given by --budget, only available in all mode.
+    pub budget: Vec<BudgetCheck>,
+    // Faults injected by --faults, only available in full mode.
+    pub faults: Vec<String>,
+    // Files written by --trace, --trace-syscalls and --coverage, only available in full mode.
+    pub trace: Option<JsonTraceReport>,
+    pub syscall_trace: Option<JsonTraceReport>,
+    pub coverage: Option<JsonCoverageReport>,
+}
+
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub struct JsonTraceReport {
+    pub path: String,
+    // Instructions or syscalls recorded.
+    pub count: u64,
+}
+
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub struct JsonCoverageReport {
+    pub path: String,
+    pub lines_hit: usize,
+    pub lines_found: usize,
+    pub branches_hit: usize,
+    pub branches_found: usize,
+}
+
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub struct JsonProcessReport {
+    pub pid: u64,
+    pub parent: Option<u64>,
+    pub exit_code: Option<i8>,
+    pub cycle: Cycle,
+}
+
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub struct JsonGroupReport {
+    pub script_group_type: ScriptGroupType,
+    pub script_hash: H256,
+    pub input_indices: Vec<usize>,
+    pub output_indices: Vec<usize>,
+    pub exit_code: Option<i8>,
+    pub cycle: Cycle,
+    pub error: Option<String>,
+    pub messages: Vec<String>,
+}
+
 #[wasm_bindgen]
 pub fn run_json(mock_tx: &str, script_group_type: &str, script_hash: &str, max_cycle: &str) -> String {
     let result = || -> Result<Cycle, String> {
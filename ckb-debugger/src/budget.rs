@@ -0,0 +1,185 @@
+use crate::machine_analyzer::MachineAnalyzer;
+use crate::misc::HumanReadableCycles;
+use crate::script_groups::ScriptGroupResult;
+use ckb_script::{ScriptGroup, ScriptGroupType, TransactionScriptsVerifier};
+use ckb_traits::{CellDataProvider, ExtensionProvider, HeaderProvider};
+use ckb_types::core::Cycle;
+use ckb_types::prelude::Unpack;
+use ckb_types::H256;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// Cycle budget of a transaction, read from the file of --budget.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CycleBudget {
+    // Limit of the cycles of all script groups.
+    #[serde(default)]
+    pub max_cycles: Option<Cycle>,
+    #[serde(default)]
+    pub scripts: Vec<ScriptBudget>,
+}
+
+// Cycle budget of a script group. The group is selected by its script hash, or by a cell in the same way as
+// --cell-type, --cell-index and --script-group-type.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScriptBudget {
+    #[serde(default)]
+    pub script_hash: Option<H256>,
+    #[serde(default)]
+    pub cell_type: Option<String>,
+    #[serde(default)]
+    pub cell_index: Option<usize>,
+    #[serde(default)]
+    pub script_group_type: Option<ScriptGroupType>,
+    #[serde(default)]
+    pub max_cycles: Option<Cycle>,
+    // Limits of functions, including the functions they call. Functions are profiled by MachineProfile, which counts
+    // the cycles of instructions but not the cycles of syscalls.
+    #[serde(default)]
+    pub functions: BTreeMap<String, Cycle>,
+}
+
+impl ScriptBudget {
+    fn matches(&self, result: &ScriptGroupResult) -> Result<bool, String> {
+        if let Some(script_hash) = &self.script_hash {
+            let hash: H256 = result.script_hash.unpack();
+            return Ok(&hash == script_hash);
+        }
+        let (cell_type, cell_index, script_group_type) =
+            match (&self.cell_type, self.cell_index, self.script_group_type) {
+                (Some(a), Some(b), Some(c)) => (a, b, c),
+                _ => {
+                    return Err(String::from(
+                        "A script budget requires script_hash, or cell_type, cell_index and script_group_type",
+                    ))
+                }
+            };
+        let indices = match cell_type.as_str() {
+            "input" => &result.input_indices,
+            "output" => &result.output_indices,
+            _ => return Err(format!("Invalid cell type {}", cell_type)),
+        };
+        Ok(result.group_type == script_group_type && indices.contains(&cell_index))
+    }
+}
+
+// A limit of the budget and the cycles consumed. The script group is none for the limit of all script groups, and the
+// function is none for the limit of a whole script group.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BudgetCheck {
+    pub script_group_type: Option<ScriptGroupType>,
+    pub script_hash: Option<H256>,
+    pub function: Option<String>,
+    pub cycles: Cycle,
+    pub max_cycles: Cycle,
+}
+
+impl BudgetCheck {
+    pub fn exceeded(&self) -> bool {
+        self.cycles > self.max_cycles
+    }
+}
+
+impl std::fmt::Display for BudgetCheck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = if self.exceeded() { "exceeded" } else { "ok" };
+        let mut target = match (&self.script_group_type, &self.script_hash) {
+            (Some(group_type), Some(script_hash)) => {
+                format!("{} {:#x}", group_type.to_string().to_lowercase(), script_hash)
+            }
+            _ => String::from("transaction"),
+        };
+        if let Some(function) = &self.function {
+            target = format!("{} function {}", target, function);
+        }
+        write!(
+            f,
+            "{}: {}, cycles {} of budget {}",
+            status,
+            target,
+            HumanReadableCycles(self.cycles),
+            HumanReadableCycles(self.max_cycles)
+        )
+    }
+}
+
+// Cycles of the functions of a script group, in all the processes it spawns.
+fn profile_functions<DL>(
+    verifier: &TransactionScriptsVerifier<DL>,
+    group: &ScriptGroup,
+    max_cycles: Cycle,
+) -> Result<BTreeMap<String, Cycle>, Box<dyn std::error::Error>>
+where
+    DL: CellDataProvider + HeaderProvider + ExtensionProvider + Send + Sync + Clone + 'static,
+{
+    let mut machine = MachineAnalyzer::boot(verifier.create_scheduler(group)?, &[], max_cycles)?;
+    machine.run()?;
+    let mut functions = BTreeMap::new();
+    for process in machine.processes.values_mut() {
+        for (name, cycles) in process.profile.function_cycles() {
+            *functions.entry(name).or_default() += cycles;
+        }
+    }
+    Ok(functions)
+}
+
+// Check the results of run_script_groups against a budget. Script groups with function limits are run again by the
+// profiler. It is an error if a script budget selects no script group, or a function limited is not executed, which is
+// often a typo or a stripped program.
+pub fn check_budget<DL>(
+    verifier: &TransactionScriptsVerifier<DL>,
+    results: &[ScriptGroupResult],
+    budget: &CycleBudget,
+    max_cycles: Cycle,
+) -> Result<Vec<BudgetCheck>, Box<dyn std::error::Error>>
+where
+    DL: CellDataProvider + HeaderProvider + ExtensionProvider + Send + Sync + Clone + 'static,
+{
+    let mut checks = vec![];
+    if let Some(limit) = budget.max_cycles {
+        checks.push(BudgetCheck {
+            script_group_type: None,
+            script_hash: None,
+            function: None,
+            cycles: results.iter().map(|e| e.cycles).sum(),
+            max_cycles: limit,
+        });
+    }
+    for script in &budget.scripts {
+        let mut found = false;
+        for result in results {
+            if !script.matches(result)? {
+                continue;
+            }
+            found = true;
+            let check = |function: Option<String>, cycles: Cycle, max_cycles: Cycle| BudgetCheck {
+                script_group_type: Some(result.group_type),
+                script_hash: Some(result.script_hash.unpack()),
+                function,
+                cycles,
+                max_cycles,
+            };
+            if let Some(limit) = script.max_cycles {
+                checks.push(check(None, result.cycles, limit));
+            }
+            if script.functions.is_empty() {
+                continue;
+            }
+            let group = verifier
+                .find_script_group(result.group_type, &result.script_hash)
+                .ok_or_else(|| format!("Unable to find script group {:#x}", result.script_hash))?;
+            let functions = profile_functions(verifier, group, max_cycles)?;
+            for (name, limit) in &script.functions {
+                let cycles = functions.get(name).ok_or_else(|| {
+                    let group_type = result.group_type.to_string().to_lowercase();
+                    format!("Function {} is not executed by {} {:#x}", name, group_type, result.script_hash)
+                })?;
+                checks.push(check(Some(name.clone()), *cycles, *limit));
+            }
+        }
+        if !found {
+            return Err(format!("No script group matches the budget {}", serde_json::to_string(script)?).into());
+        }
+    }
+    Ok(checks)
+}
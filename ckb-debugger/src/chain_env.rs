@@ -0,0 +1,170 @@
+use ckb_chain_spec::consensus::{Consensus, ConsensusBuilder};
+use ckb_constant::hardfork::{mainnet, testnet};
+use ckb_script::{ScriptVersion, TxVerifyEnv};
+use ckb_types::core::hardfork::{HardForks, CKB2021, CKB2023};
+use ckb_types::core::{BlockNumber, EpochNumber, EpochNumberWithFraction, HeaderView};
+use ckb_types::prelude::Pack;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Chain {
+    Mainnet,
+    Testnet,
+    #[default]
+    Dev,
+}
+
+impl std::str::FromStr for Chain {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mainnet" => Ok(Chain::Mainnet),
+            "testnet" => Ok(Chain::Testnet),
+            "dev" => Ok(Chain::Dev),
+            _ => Err(format!("Unknown chain {}", s)),
+        }
+    }
+}
+
+// The chain environment that scripts are verified in: which hardfork features are enabled, and the block the
+// transaction is committed in. Can be loaded from a json file, e.g.
+//
+// {
+//     "chain": "mainnet",
+//     "epoch": 5500,
+//     "block_number": 7000000,
+//     "timestamp": 1650000000000
+// }
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChainEnv {
+    // Hardfork features of the chain.
+    pub chain: Chain,
+    // Custom hardfork epochs, overriding the ones of the chain. ckb2021 is the epoch that vm version 1 and syscalls 2
+    // are enabled (rfc 0032), ckb2023 is the epoch that vm version 2 and syscalls 3 are enabled (rfc 0049). The other
+    // features of the hardforks stay at the epochs of the chain.
+    pub ckb2021: Option<EpochNumber>,
+    pub ckb2023: Option<EpochNumber>,
+    // Epoch of the block header, in the form of number + index / length.
+    pub epoch: EpochNumber,
+    pub epoch_index: u64,
+    pub epoch_length: u64,
+    pub block_number: BlockNumber,
+    // Block timestamp in milliseconds.
+    pub timestamp: u64,
+}
+
+impl Default for ChainEnv {
+    fn default() -> Self {
+        Self {
+            chain: Chain::Dev,
+            ckb2021: None,
+            ckb2023: None,
+            epoch: 0,
+            epoch_index: 0,
+            epoch_length: 1,
+            block_number: 0,
+            timestamp: 0,
+        }
+    }
+}
+
+impl ChainEnv {
+    pub fn new(chain: Chain) -> Self {
+        Self { chain, ..Default::default() }
+    }
+
+    // The environment used by ckb-debugger before the chain environment was configurable: mainnet with vm version 1
+    // enabled at epoch 20 and vm version 2 at epoch 30, and the epoch chosen so that the given script version is the
+    // latest one.
+    pub fn with_script_version(script_version: ScriptVersion) -> Self {
+        let epoch = match script_version {
+            ScriptVersion::V0 => 15,
+            ScriptVersion::V1 => 25,
+            ScriptVersion::V2 => 35,
+        };
+        Self { chain: Chain::Mainnet, ckb2021: Some(20), ckb2023: Some(30), epoch, ..Default::default() }
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let data = std::fs::read_to_string(path)?;
+        let env: Self = serde_json::from_str(&data)?;
+        env.check()?;
+        Ok(env)
+    }
+
+    pub fn check(&self) -> Result<(), String> {
+        if self.epoch_length == 0 || self.epoch_index >= self.epoch_length {
+            return Err(format!("Invalid epoch {} {}/{}", self.epoch, self.epoch_index, self.epoch_length));
+        }
+        if self.epoch >= EpochNumberWithFraction::NUMBER_MAXIMUM_VALUE
+            || self.epoch_length >= EpochNumberWithFraction::LENGTH_MAXIMUM_VALUE
+        {
+            return Err(format!("Epoch {} {}/{} out of range", self.epoch, self.epoch_index, self.epoch_length));
+        }
+        Ok(())
+    }
+
+    pub fn hardforks(&self) -> Result<HardForks, String> {
+        let (ckb2021_base, rfc_0028_0032, ckb2023_base) = match self.chain {
+            Chain::Mainnet => (
+                mainnet::CKB2021_START_EPOCH,
+                mainnet::RFC0028_RFC0032_RFC0033_RFC0034_START_EPOCH,
+                mainnet::CKB2023_START_EPOCH,
+            ),
+            Chain::Testnet => (
+                testnet::CKB2021_START_EPOCH,
+                testnet::RFC0028_RFC0032_RFC0033_RFC0034_START_EPOCH,
+                testnet::CKB2023_START_EPOCH,
+            ),
+            Chain::Dev => (0, 0, 0),
+        };
+        let ckb2021 = CKB2021::new_builder()
+            .rfc_0028(rfc_0028_0032)
+            .rfc_0029(ckb2021_base)
+            .rfc_0030(ckb2021_base)
+            .rfc_0031(ckb2021_base)
+            .rfc_0032(self.ckb2021.unwrap_or(rfc_0028_0032))
+            .rfc_0036(ckb2021_base)
+            .rfc_0038(ckb2021_base)
+            .build()?;
+        let ckb2023 =
+            CKB2023::new_builder().rfc_0048(ckb2023_base).rfc_0049(self.ckb2023.unwrap_or(ckb2023_base)).build()?;
+        Ok(HardForks { ckb2021, ckb2023 })
+    }
+
+    pub fn consensus(&self) -> Result<Consensus, String> {
+        Ok(ConsensusBuilder::default().hardfork_switch(self.hardforks()?).build())
+    }
+
+    pub fn epoch(&self) -> EpochNumberWithFraction {
+        EpochNumberWithFraction::new(self.epoch, self.epoch_index, self.epoch_length)
+    }
+
+    // Header of the block that the transaction is committed in.
+    pub fn header(&self) -> HeaderView {
+        HeaderView::new_advanced_builder()
+            .epoch(self.epoch().pack())
+            .number(self.block_number.pack())
+            .timestamp(self.timestamp.pack())
+            .build()
+    }
+
+    pub fn tx_verify_env(&self) -> TxVerifyEnv {
+        TxVerifyEnv::new_commit(&self.header())
+    }
+
+    // The latest script version available in this environment.
+    pub fn script_version(&self) -> Result<ScriptVersion, String> {
+        let hardforks = self.hardforks()?;
+        if hardforks.ckb2023.is_vm_version_2_and_syscalls_3_enabled(self.epoch) {
+            Ok(ScriptVersion::V2)
+        } else if hardforks.ckb2021.is_vm_version_1_and_syscalls_2_enabled(self.epoch) {
+            Ok(ScriptVersion::V1)
+        } else {
+            Ok(ScriptVersion::V0)
+        }
+    }
+}
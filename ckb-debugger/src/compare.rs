@@ -0,0 +1,311 @@
+use crate::machine_analyzer::MachineAnalyzer;
+use crate::misc::HumanReadableCycles;
+use crate::syscall_info::{display_arg, syscall_info, syscall_name, PARTIAL_LOADING_SYSCALLS};
+use ckb_jsonrpc_types::JsonBytes;
+use ckb_traits::{CellDataProvider, ExtensionProvider, HeaderProvider};
+use ckb_vm::decoder::{build_decoder, Decoder};
+use ckb_vm::instructions::{extract_opcode, insts};
+use ckb_vm::machine::VERSION0;
+use ckb_vm::registers::{A0, A1, A2, A7};
+use ckb_vm::{CoreMachine, Error, Memory, SupportMachine, ISA_MOP};
+use serde::Serialize;
+use std::collections::VecDeque;
+
+// Limit of the data taken from a vm for a syscall, such as a debug message without the terminating zero.
+const MAX_DATA: u64 = 64 * 1024;
+
+// A syscall made by a vm, with the arguments which are values, the data passed to or loaded by it and its result.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct SyscallEvent {
+    pub pid: u64,
+    pub number: u64,
+    pub name: String,
+    pub args: Vec<(String, u64)>,
+    pub data: JsonBytes,
+    // Value of a0 when the vm runs again, or none if it does not, such as after exit.
+    pub ret: Option<u64>,
+    // Cycles consumed by all vms before the syscall.
+    pub cycles: u64,
+    #[serde(skip)]
+    pub backtrace: Vec<String>,
+}
+
+impl SyscallEvent {
+    // Syscalls are the same if they are made by the same vm with the same arguments, data and results, no matter the
+    // cycles and where they are called.
+    pub fn same(&self, other: &Self) -> bool {
+        self.pid == other.pid
+            && self.number == other.number
+            && self.args == other.args
+            && self.data == other.data
+            && self.ret == other.ret
+    }
+}
+
+impl std::fmt::Display for SyscallEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let args: Vec<String> =
+            self.args.iter().map(|(name, v)| format!("{}={}", name, display_arg(self.number, name, *v))).collect();
+        let ret = self.ret.map(|e| e.to_string()).unwrap_or(String::from("?"));
+        write!(f, "pid {} {}({}) = {}", self.pid, self.name, args.join(", "), ret)?;
+        if !self.data.is_empty() {
+            write!(f, ", {} bytes of data", self.data.len())?;
+        }
+        Ok(())
+    }
+}
+
+// A syscall waiting for the vm to run again, with the buffer, the address of its size and the size of the buffer if
+// the syscall stores data.
+struct PendingSyscall {
+    event: SyscallEvent,
+    buffer: Option<(u64, u64, u64)>,
+    done: bool,
+}
+
+// A machine of compare, run from syscall to syscall.
+struct CompareMachine<DL>
+where
+    DL: CellDataProvider + HeaderProvider + ExtensionProvider + Send + Sync + Clone + 'static,
+{
+    machine: MachineAnalyzer<DL>,
+    decoder: Decoder,
+    pending: VecDeque<PendingSyscall>,
+    result: Option<Result<i8, String>>,
+}
+
+impl<DL> CompareMachine<DL>
+where
+    DL: CellDataProvider + HeaderProvider + ExtensionProvider + Send + Sync + Clone + 'static,
+{
+    fn new(mut machine: MachineAnalyzer<DL>) -> Self {
+        let decoder = build_decoder::<u64>(machine.isa(), machine.version());
+        let mut result = None;
+        if machine.isa() & ISA_MOP != 0 && machine.version() == VERSION0 {
+            result = Some(Err(Error::InvalidVersion.to_string()));
+        }
+        machine.machine.set_running(true);
+        Self { machine, decoder, pending: VecDeque::new(), result }
+    }
+
+    fn backtrace(&mut self) -> Vec<String> {
+        let mut backtrace = vec![];
+        self.machine.display_stacktrace("", &mut backtrace);
+        String::from_utf8_lossy(&backtrace).lines().map(String::from).collect()
+    }
+
+    fn call(&mut self) -> PendingSyscall {
+        let registers = self.machine.registers().to_vec();
+        let number = registers[A7];
+        let args = match syscall_info(number) {
+            Some(info) => info.args.iter().map(|(idx, name)| (name.to_string(), registers[*idx])).collect(),
+            None => vec![],
+        };
+        let memory = self.machine.memory_mut();
+        let mut data = vec![];
+        let mut buffer = None;
+        match number {
+            2177 => {
+                for addr in registers[A0]..registers[A0].saturating_add(MAX_DATA) {
+                    match memory.load8(&addr) {
+                        Ok(0) | Err(_) => break,
+                        Ok(byte) => data.push(byte as u8),
+                    }
+                }
+            }
+            2605 => {
+                if let Ok(size) = memory.load64(&registers[A2]) {
+                    data = memory.load_bytes(registers[A1], size.min(MAX_DATA)).map(|e| e.to_vec()).unwrap_or_default();
+                }
+            }
+            2606 => buffer = memory.load64(&registers[A2]).ok().map(|e| (registers[A1], registers[A2], e)),
+            _ if PARTIAL_LOADING_SYSCALLS.contains(&number) => {
+                buffer = memory.load64(&registers[A1]).ok().map(|e| (registers[A0], registers[A1], e))
+            }
+            _ => {}
+        }
+        let event = SyscallEvent {
+            pid: self.machine.machine.id,
+            number,
+            name: syscall_name(number),
+            args,
+            data: JsonBytes::from_vec(data),
+            ret: None,
+            cycles: self.machine.machine.total_cycles(),
+            backtrace: self.backtrace(),
+        };
+        // Exit never returns.
+        PendingSyscall { event, buffer, done: number == 93 }
+    }
+
+    // The syscalls of the current vm are done, since it runs again.
+    fn ret(&mut self) {
+        let id = self.machine.machine.id;
+        let machine = &mut self.machine;
+        for e in self.pending.iter_mut().filter(|e| !e.done && e.event.pid == id) {
+            let ret = machine.registers()[A0];
+            if let (Some((addr, size_addr, size)), 0) = (e.buffer, ret) {
+                if let Ok(full_size) = machine.memory_mut().load64(&size_addr) {
+                    let size = size.min(full_size).min(MAX_DATA);
+                    let data = machine.memory_mut().load_bytes(addr, size).map(|e| e.to_vec()).unwrap_or_default();
+                    e.event.data = JsonBytes::from_vec(data);
+                }
+            }
+            // Debug returns nothing, a0 is still the address of the message.
+            if e.event.number != 2177 {
+                e.event.ret = Some(ret);
+            }
+            e.done = true;
+        }
+    }
+
+    // Run to the next syscall done, in the order of calls. Returns none when the run ends.
+    fn next(&mut self) -> Option<SyscallEvent> {
+        loop {
+            if self.pending.front().map(|e| e.done).unwrap_or(false) || self.result.is_some() {
+                return self.pending.pop_front().map(|e| e.event);
+            }
+            if !self.machine.machine.running() {
+                self.result = Some(Ok(self.machine.machine.exit_code()));
+                continue;
+            }
+            self.ret();
+            let pc = *self.machine.pc();
+            let ecall = self
+                .decoder
+                .decode(self.machine.memory_mut(), pc)
+                .map(|e| extract_opcode(e) == insts::OP_ECALL)
+                .unwrap_or(false);
+            if ecall {
+                let pending = self.call();
+                self.pending.push_back(pending);
+            }
+            if let Err(e) = self.machine.step(&mut self.decoder) {
+                self.result = Some(Err(e.to_string()));
+            }
+        }
+    }
+
+    fn finish(&mut self) -> CompareResult {
+        let (exit_code, error) = match &self.result {
+            Some(Ok(exit_code)) => (Some(*exit_code), None),
+            Some(Err(e)) => (None, Some(e.clone())),
+            None => (None, None),
+        };
+        CompareResult { exit_code, error, cycles: self.machine.machine.total_cycles() }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct CompareResult {
+    pub exit_code: Option<i8>,
+    pub error: Option<String>,
+    pub cycles: u64,
+}
+
+impl std::fmt::Display for CompareResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.exit_code, &self.error) {
+            (Some(exit_code), _) => write!(f, "exit code {}", exit_code)?,
+            (_, Some(error)) => write!(f, "error {}", error)?,
+            _ => write!(f, "not finished")?,
+        }
+        write!(f, ", cycles {}", HumanReadableCycles(self.cycles))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Divergence {
+    pub reason: String,
+    // Syscalls of both sides where they diverge, if the reason is a syscall.
+    pub a: Option<SyscallEvent>,
+    pub b: Option<SyscallEvent>,
+    // Backtraces of the syscalls, or where the runs end.
+    pub backtrace_a: Vec<String>,
+    pub backtrace_b: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct CompareReport {
+    // Number of syscalls which are the same in both runs.
+    pub syscalls: usize,
+    pub a: CompareResult,
+    pub b: CompareResult,
+    pub divergence: Option<Divergence>,
+}
+
+impl CompareReport {
+    pub fn display(&self, writer: &mut impl std::io::Write) -> Result<(), std::io::Error> {
+        writeln!(writer, "Same syscalls: {}", self.syscalls)?;
+        writeln!(writer, "Run a: {}", self.a)?;
+        writeln!(writer, "Run b: {}", self.b)?;
+        let divergence = match &self.divergence {
+            Some(divergence) => divergence,
+            None => return writeln!(writer, "No divergence"),
+        };
+        writeln!(writer, "Divergence: {}", divergence.reason)?;
+        for (side, event) in [("a", &divergence.a), ("b", &divergence.b)] {
+            match event {
+                Some(event) => writeln!(writer, "  {}: {}", side, event)?,
+                None if divergence.a.is_some() || divergence.b.is_some() => writeln!(writer, "  {}: no syscall", side)?,
+                None => {}
+            }
+        }
+        for (side, backtrace) in [("a", &divergence.backtrace_a), ("b", &divergence.backtrace_b)] {
+            writeln!(writer, "Backtrace of {}:", side)?;
+            for line in backtrace {
+                writeln!(writer, "  {}", line)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Run two machines of the same script group in lockstep, syscall by syscall, and find the first divergence in the
+// syscalls, the exit code or the cycles. Machines should debug all vms, so the syscalls of spawned vms are compared as
+// well.
+pub fn compare<DL>(a: MachineAnalyzer<DL>, b: MachineAnalyzer<DL>) -> CompareReport
+where
+    DL: CellDataProvider + HeaderProvider + ExtensionProvider + Send + Sync + Clone + 'static,
+{
+    let (mut a, mut b) = (CompareMachine::new(a), CompareMachine::new(b));
+    let mut syscalls = 0;
+    let mut divergence = None;
+    loop {
+        let (event_a, event_b) = (a.next(), b.next());
+        let same = match (&event_a, &event_b) {
+            (Some(x), Some(y)) => x.same(y),
+            (None, None) => break,
+            _ => false,
+        };
+        if !same {
+            divergence = Some(Divergence {
+                reason: format!("syscall {} differs", syscalls + 1),
+                backtrace_a: event_a.as_ref().map(|e| e.backtrace.clone()).unwrap_or_else(|| a.backtrace()),
+                backtrace_b: event_b.as_ref().map(|e| e.backtrace.clone()).unwrap_or_else(|| b.backtrace()),
+                a: event_a,
+                b: event_b,
+            });
+            break;
+        }
+        syscalls += 1;
+    }
+    let (result_a, result_b) = (a.finish(), b.finish());
+    if divergence.is_none() {
+        let reason = if (&result_a.exit_code, &result_a.error) != (&result_b.exit_code, &result_b.error) {
+            Some("exit code differs")
+        } else if result_a.cycles != result_b.cycles {
+            Some("cycles differ")
+        } else {
+            None
+        };
+        divergence = reason.map(|reason| Divergence {
+            reason: reason.to_string(),
+            a: None,
+            b: None,
+            backtrace_a: a.backtrace(),
+            backtrace_b: b.backtrace(),
+        });
+    }
+    CompareReport { syscalls, a: result_a, b: result_b, divergence }
+}
@@ -0,0 +1,224 @@
+use ckb_vm::instructions::{extract_opcode, insts, Instruction, Stype};
+use std::collections::BTreeMap;
+
+// Whether a conditional branch is taken with the registers, or none if the instruction is not a conditional branch.
+pub(crate) fn branch_taken(inst: Instruction, registers: &[u64]) -> Option<bool> {
+    let taken: fn(u64, u64) -> bool = match extract_opcode(inst) {
+        insts::OP_BEQ => |a, b| a == b,
+        insts::OP_BNE => |a, b| a != b,
+        insts::OP_BLT => |a, b| (a as i64) < (b as i64),
+        insts::OP_BGE => |a, b| (a as i64) >= (b as i64),
+        insts::OP_BLTU => |a, b| a < b,
+        insts::OP_BGEU => |a, b| a >= b,
+        _ => return None,
+    };
+    let inst = Stype(inst);
+    Some(taken(registers[inst.rs1()], registers[inst.rs2()]))
+}
+
+// Coverage of a source file.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FileCoverage {
+    // Execution count of every line with code.
+    pub lines: BTreeMap<u32, u64>,
+    // Times every branch is taken, indexed by the line, the block and the branch, or none if the block is never
+    // executed. Every conditional branch instruction of a line is a block, of the branch taken and the branch not
+    // taken, in the order of addresses.
+    pub branches: BTreeMap<(u32, u32, u32), Option<u64>>,
+}
+
+impl FileCoverage {
+    pub fn lines_hit(&self) -> usize {
+        self.lines.values().filter(|e| **e > 0).count()
+    }
+
+    pub fn branches_hit(&self) -> usize {
+        self.branches.values().filter(|e| e.unwrap_or(0) > 0).count()
+    }
+}
+
+// Line and branch coverage of source files, merged from processes and runs. It is read and written in the format of
+// lcov tracefiles, and written in the format of Cobertura.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Coverage {
+    pub files: BTreeMap<String, FileCoverage>,
+}
+
+impl Coverage {
+    pub fn merge(&mut self, other: Coverage) {
+        for (name, other) in other.files {
+            let file = self.files.entry(name).or_default();
+            for (line, count) in other.lines {
+                *file.lines.entry(line).or_default() += count;
+            }
+            for (branch, taken) in other.branches {
+                let e = file.branches.entry(branch).or_default();
+                *e = match (*e, taken) {
+                    (None, None) => None,
+                    (a, b) => Some(a.unwrap_or(0) + b.unwrap_or(0)),
+                };
+            }
+        }
+    }
+
+    pub fn lines_found(&self) -> usize {
+        self.files.values().map(|e| e.lines.len()).sum()
+    }
+
+    pub fn lines_hit(&self) -> usize {
+        self.files.values().map(|e| e.lines_hit()).sum()
+    }
+
+    pub fn branches_found(&self) -> usize {
+        self.files.values().map(|e| e.branches.len()).sum()
+    }
+
+    pub fn branches_hit(&self) -> usize {
+        self.files.values().map(|e| e.branches_hit()).sum()
+    }
+
+    // Coverage of an lcov tracefile. Records other than source files, lines and branches are ignored, and records of
+    // the same file are merged.
+    pub fn load_lcov(data: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut coverage = Coverage::default();
+        let mut file: Option<(String, FileCoverage)> = None;
+        let invalid = |line: &str| format!("Invalid lcov record {}", line);
+        for line in data.lines().map(|e| e.trim()).filter(|e| !e.is_empty()) {
+            let (key, value) = line.split_once(':').unwrap_or((line, ""));
+            if key == "SF" {
+                file = Some((value.to_string(), FileCoverage::default()));
+                continue;
+            }
+            if key == "end_of_record" {
+                if let Some((name, file)) = file.take() {
+                    coverage.merge(Coverage { files: BTreeMap::from([(name, file)]) });
+                }
+                continue;
+            }
+            let (_, e) = match (&mut file, key) {
+                (Some(e), "DA" | "BRDA") => e,
+                _ => continue,
+            };
+            let fields: Vec<&str> = value.split(',').collect();
+            if key == "DA" {
+                let (line_number, count) = match fields[..] {
+                    [a, b, ..] => (a.parse::<u32>()?, b.parse::<u64>()?),
+                    _ => return Err(invalid(line).into()),
+                };
+                *e.lines.entry(line_number).or_default() += count;
+            } else {
+                let (line_number, block, branch, taken) = match fields[..] {
+                    [a, b, c, d] => (a.parse::<u32>()?, b.parse::<u32>()?, c.parse::<u32>()?, d),
+                    _ => return Err(invalid(line).into()),
+                };
+                let taken = if taken == "-" { None } else { Some(taken.parse::<u64>()?) };
+                e.branches.insert((line_number, block, branch), taken);
+            }
+        }
+        if file.is_some() {
+            return Err("Missing end_of_record in lcov".into());
+        }
+        Ok(coverage)
+    }
+
+    pub fn display_lcov(&self, writer: &mut impl std::io::Write) -> Result<(), std::io::Error> {
+        writeln!(writer, "TN:")?;
+        for (name, file) in &self.files {
+            writeln!(writer, "SF:{}", name)?;
+            for ((line, block, branch), taken) in &file.branches {
+                let taken = taken.map(|e| e.to_string()).unwrap_or(String::from("-"));
+                writeln!(writer, "BRDA:{},{},{},{}", line, block, branch, taken)?;
+            }
+            writeln!(writer, "BRF:{}", file.branches.len())?;
+            writeln!(writer, "BRH:{}", file.branches_hit())?;
+            for (line, count) in &file.lines {
+                writeln!(writer, "DA:{},{}", line, count)?;
+            }
+            writeln!(writer, "LF:{}", file.lines.len())?;
+            writeln!(writer, "LH:{}", file.lines_hit())?;
+            writeln!(writer, "end_of_record")?;
+        }
+        writer.flush()
+    }
+
+    // Coverage in the XML format of Cobertura, with a package of every directory and a class of every file.
+    pub fn display_cobertura(&self, writer: &mut impl std::io::Write) -> Result<(), std::io::Error> {
+        let rate = |hit: usize, found: usize| if found == 0 { 1.0 } else { hit as f64 / found as f64 };
+        let escape = |s: &str| s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;");
+        let mut packages: BTreeMap<String, Vec<(&String, &FileCoverage)>> = BTreeMap::new();
+        for (name, file) in &self.files {
+            let dir = std::path::Path::new(name).parent().map(|e| e.to_string_lossy().to_string()).unwrap_or_default();
+            packages.entry(dir).or_default().push((name, file));
+        }
+        let timestamp = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+        writeln!(writer, r#"<?xml version="1.0" ?>"#)?;
+        writeln!(
+            writer,
+            "<coverage line-rate=\"{:.4}\" branch-rate=\"{:.4}\" lines-covered=\"{}\" lines-valid=\"{}\" \
+             branches-covered=\"{}\" branches-valid=\"{}\" complexity=\"0\" version=\"ckb-debugger\" timestamp=\"{}\">",
+            rate(self.lines_hit(), self.lines_found()),
+            rate(self.branches_hit(), self.branches_found()),
+            self.lines_hit(),
+            self.lines_found(),
+            self.branches_hit(),
+            self.branches_found(),
+            timestamp.as_millis()
+        )?;
+        writeln!(writer, "  <sources>")?;
+        writeln!(writer, "    <source>/</source>")?;
+        writeln!(writer, "  </sources>")?;
+        writeln!(writer, "  <packages>")?;
+        for (dir, files) in &packages {
+            let lines_hit = files.iter().map(|e| e.1.lines_hit()).sum();
+            let lines_found = files.iter().map(|e| e.1.lines.len()).sum();
+            let branches_hit = files.iter().map(|e| e.1.branches_hit()).sum();
+            let branches_found = files.iter().map(|e| e.1.branches.len()).sum();
+            writeln!(
+                writer,
+                r#"    <package name="{}" line-rate="{:.4}" branch-rate="{:.4}" complexity="0">"#,
+                escape(dir),
+                rate(lines_hit, lines_found),
+                rate(branches_hit, branches_found)
+            )?;
+            writeln!(writer, "      <classes>")?;
+            for (name, file) in files {
+                let class = std::path::Path::new(name).file_name().map(|e| e.to_string_lossy().to_string());
+                writeln!(
+                    writer,
+                    r#"        <class name="{}" filename="{}" line-rate="{:.4}" branch-rate="{:.4}" complexity="0">"#,
+                    escape(&class.unwrap_or_default()),
+                    escape(name.trim_start_matches('/')),
+                    rate(file.lines_hit(), file.lines.len()),
+                    rate(file.branches_hit(), file.branches.len())
+                )?;
+                writeln!(writer, "          <methods/>")?;
+                writeln!(writer, "          <lines>")?;
+                for (line, count) in &file.lines {
+                    let branches: Vec<&Option<u64>> =
+                        file.branches.range((*line, 0, 0)..=(*line, u32::MAX, u32::MAX)).map(|e| e.1).collect();
+                    if branches.is_empty() {
+                        writeln!(writer, r#"            <line number="{}" hits="{}" branch="false"/>"#, line, count)?;
+                        continue;
+                    }
+                    let hit = branches.iter().filter(|e| e.unwrap_or(0) > 0).count();
+                    writeln!(
+                        writer,
+                        r#"            <line number="{}" hits="{}" branch="true" condition-coverage="{}% ({}/{})"/>"#,
+                        line,
+                        count,
+                        hit * 100 / branches.len(),
+                        hit,
+                        branches.len()
+                    )?;
+                }
+                writeln!(writer, "          </lines>")?;
+                writeln!(writer, "        </class>")?;
+            }
+            writeln!(writer, "      </classes>")?;
+            writeln!(writer, "    </package>")?;
+        }
+        writeln!(writer, "  </packages>")?;
+        writeln!(writer, "</coverage>")?;
+        writer.flush()
+    }
+}
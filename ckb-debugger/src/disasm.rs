@@ -0,0 +1,118 @@
+use crate::machine_analyzer::{goblin_fun, SourceLines};
+use ckb_vm::cost_model::estimate_cycles;
+use ckb_vm::decoder::build_decoder;
+use ckb_vm::instructions::tagged::TaggedInstruction;
+use ckb_vm::instructions::{extract_opcode, instruction_length, instruction_opcode_name, insts, Instruction};
+use ckb_vm::{Bytes, FlatMemory, Memory, ISA_MOP};
+
+pub(crate) fn instruction_text(inst: Instruction) -> String {
+    match TaggedInstruction::try_from(inst) {
+        Ok(e) => e.to_string(),
+        Err(_) => instruction_opcode_name(extract_opcode(inst)).to_lowercase(),
+    }
+}
+
+pub(crate) fn is_mop(inst: Instruction) -> bool {
+    let opcode = extract_opcode(inst);
+    (insts::OP_WIDE_MUL..=insts::OP_CUSTOM_LOAD_IMM).contains(&opcode)
+        || opcode == insts::OP_FAR_JUMP_REL
+        || opcode == insts::OP_FAR_JUMP_ABS
+}
+
+// Start and end addresses of executable segments.
+pub(crate) type Segments = Vec<(u64, u64)>;
+
+// Executable segments of a program loaded into memory, with their start and end addresses.
+pub(crate) fn executable_segments(program: &Bytes) -> Result<(FlatMemory<u64>, Segments), Box<dyn std::error::Error>> {
+    let elf = goblin::elf::Elf::parse(program)?;
+    let mut memory = <FlatMemory<u64> as Memory>::new();
+    let mut segments = vec![];
+    for ph in &elf.program_headers {
+        if ph.p_type != goblin::elf::program_header::PT_LOAD || !ph.is_executable() {
+            continue;
+        }
+        let data = program.get(ph.p_offset as usize..(ph.p_offset + ph.p_filesz) as usize).ok_or("Invalid segment")?;
+        memory.store_bytes(ph.p_vaddr, data)?;
+        segments.push((ph.p_vaddr, ph.p_vaddr + ph.p_filesz));
+    }
+    Ok((memory, segments))
+}
+
+// Disassemble the executable segments of a program, decoded the same way as the vm of the given isa and version.
+// Every instruction comes with its cycles, and instructions fused by macro-op fusion are marked if the isa has it.
+pub fn disasm(
+    program: &Bytes,
+    isa: u8,
+    version: u32,
+    writer: &mut impl std::io::Write,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let elf = goblin::elf::Elf::parse(program)?;
+    let funs = goblin_fun(&elf);
+    // Programs without debug info are still disassembled.
+    let lines = SourceLines::new(program).ok();
+    let (mut memory, segments) = executable_segments(program)?;
+    let mut decoder = build_decoder::<u64>(isa & !ISA_MOP, version);
+    let mut mop_decoder = build_decoder::<u64>(isa, version);
+    let (mut count, mut cycles, mut mops) = (0u64, 0u64, 0u64);
+    for (start, end) in segments {
+        writeln!(writer, "Disassembly of segment {:#x}-{:#x}:", start, end)?;
+        let mut addr = start;
+        let mut last_location = None;
+        while addr < end {
+            if let Some(name) = funs.get(&addr) {
+                writeln!(writer)?;
+                writeln!(writer, "{:016x} <{}>:", addr, name)?;
+            }
+            let location = lines.as_ref().and_then(|e| e.location(addr));
+            if location.is_some() && location != last_location {
+                let (file, line) = location.clone().unwrap();
+                writeln!(writer, "{}:{}", file, line)?;
+            }
+            last_location = location;
+            let inst = match decoder.decode(&mut memory, addr) {
+                Ok(inst) => inst,
+                Err(_) => {
+                    let size = if memory.load8(&addr)? & 0x3 == 0x3 { 4 } else { 2 };
+                    let bits = memory.load_bytes(addr, size)?;
+                    writeln!(
+                        writer,
+                        "{:>8x}:\t{:<8}\t<invalid>",
+                        addr,
+                        hex::encode(bits.iter().rev().copied().collect::<Vec<_>>())
+                    )?;
+                    addr += size;
+                    continue;
+                }
+            };
+            let size = instruction_length(inst) as u64;
+            let bits = memory.load_bytes(addr, size)?;
+            let mut line = format!(
+                "{:>8x}:\t{:<8}\t{:<32}\t# {} cycles",
+                addr,
+                hex::encode(bits.iter().rev().copied().collect::<Vec<_>>()),
+                instruction_text(inst),
+                estimate_cycles(inst)
+            );
+            if isa & ISA_MOP != 0 {
+                if let Ok(fused) = mop_decoder.decode(&mut memory, addr) {
+                    if is_mop(fused) {
+                        mops += 1;
+                        line.push_str(&format!(
+                            ", fused into {} of {} bytes, {} cycles",
+                            instruction_opcode_name(extract_opcode(fused)).to_lowercase(),
+                            instruction_length(fused),
+                            estimate_cycles(fused)
+                        ));
+                    }
+                }
+            }
+            writeln!(writer, "{}", line)?;
+            count += 1;
+            cycles += estimate_cycles(inst);
+            addr += size;
+        }
+        writeln!(writer)?;
+    }
+    writeln!(writer, "Instructions: {}, cycles of all instructions: {}, macro-op fusions: {}", count, cycles, mops)?;
+    Ok(())
+}
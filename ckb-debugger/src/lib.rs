@@ -1,19 +1,69 @@
 mod api;
+mod budget;
+mod chain_env;
+mod compare;
+mod coverage;
+mod disasm;
+mod lint;
 mod machine_analyzer;
 mod machine_assign;
+mod machine_dap;
 mod machine_gdb;
+mod machine_gdb_agent;
+mod machine_history;
+mod machine_repl;
+mod machine_trace;
 mod misc;
+mod pprof;
+mod profile_diff;
+mod rpc_loader;
+mod script_groups;
+mod snapshot;
 mod syscall_all;
 mod syscall_elf_dumper;
+mod syscall_fault;
+mod syscall_info;
 #[cfg(target_family = "unix")]
 mod syscall_stdio;
+mod syscall_trace;
+mod tx_check;
 
-pub use api::{run, run_json};
-pub use machine_analyzer::{MachineAnalyzer, MachineOverlap, MachineProfile, MachineStepLog};
+pub use api::{
+    run, run_json, run_with_env, JsonCoverageReport, JsonGroupReport, JsonProcessReport, JsonReport, JsonTraceReport,
+};
+pub use budget::{check_budget, BudgetCheck, CycleBudget, ScriptBudget};
+pub use chain_env::{Chain, ChainEnv};
+pub use compare::{compare, CompareReport, CompareResult, Divergence, SyscallEvent};
+pub use coverage::{Coverage, FileCoverage};
+pub use disasm::disasm;
+pub use lint::{lint, pre_check, LintIssue, LintSeverity};
+pub use machine_analyzer::{
+    LineCycles, MachineAnalyzer, MachineOverlap, MachineProcess, MachineProfile, MachineStepLog, MachineWatch,
+    SourceLines, Watchpoint,
+};
 pub use machine_assign::MachineAssign;
-pub use machine_gdb::{GdbStubHandler, GdbStubHandlerEventLoop};
-pub use misc::{get_script_hash_by_index, pre_check, DummyResourceLoader, Embed, HumanReadableCycles};
+pub use machine_dap::{read_dap_message, spawn_dap_reader, DapServer};
+pub use machine_gdb::{Breakpoint, GdbStubHandler, GdbStubHandlerEventLoop, VmEvent};
+pub use machine_gdb_agent::{eval_agent_expr, parse_conditions, BreakpointConditions, GdbConnection};
+pub use machine_history::{Checkpoint, History, HistoryDelta, HistoryRecord};
+pub use machine_repl::Repl;
+pub use machine_trace::{
+    parse_trace_range, MachineTrace, TraceFilter, TraceReader, TraceRecord, TraceSearch, TraceWriter,
+};
+pub use misc::{get_script_hash_by_index, DummyResourceLoader, Embed, HumanReadableCycles};
+pub use pprof::PprofBuilder;
+pub use profile_diff::{load_folded, load_pprof, load_profile, FoldedStacks, FunctionDelta, ProfileDiff};
+pub use rpc_loader::RpcResourceLoader;
+pub use script_groups::{check_script_groups_cycles, run_script_groups, ScriptGroupResult};
+pub use snapshot::{ReprDataPieceId, ReprVmState, Snapshot, VmSnapshot};
 pub use syscall_all::{FileOperation, FileStream, Random, TimeNow};
 pub use syscall_elf_dumper::ElfDumper;
+pub use syscall_fault::{Fault, FaultConfig, FaultInjector, FaultValue};
+pub use syscall_info::{
+    display_arg, error_code, error_name, field_name, returns_error, source_name, syscall_info, syscall_name,
+    SyscallInfo, PARTIAL_LOADING_SYSCALLS, SYSCALLS,
+};
 #[cfg(target_family = "unix")]
 pub use syscall_stdio::Stdio;
+pub use syscall_trace::{SyscallRecord, SyscallTrace};
+pub use tx_check::{check_transaction, DAO_TYPE_HASH};
@@ -0,0 +1,313 @@
+use ckb_chain_spec::consensus::TYPE_ID_CODE_HASH;
+use ckb_hash::blake2b_256;
+use ckb_jsonrpc_types as json_types;
+use ckb_mock_tx_types::ReprMockTransaction;
+use ckb_types::core::{Capacity, ScriptHashType};
+use ckb_types::packed::{self, OutPoint, OutPointVec, WitnessArgs};
+use ckb_types::prelude::{Entity, Unpack};
+use ckb_types::H256;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LintSeverity {
+    // The transaction can still be run, but the result may not be what is expected.
+    Warning,
+    // The transaction will be rejected by a ckb node, or can not be run by the debugger.
+    Error,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct LintIssue {
+    pub severity: LintSeverity,
+    // JSON path of the problematic field in the mock transaction, e.g. $.tx.outputs[0].capacity.
+    pub path: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for LintIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let severity = match self.severity {
+            LintSeverity::Warning => "warning",
+            LintSeverity::Error => "error",
+        };
+        write!(f, "{}: {}: {}", severity, self.path, self.message)
+    }
+}
+
+struct Linter {
+    issues: Vec<LintIssue>,
+}
+
+impl Linter {
+    fn error(&mut self, path: String, message: String) {
+        self.issues.push(LintIssue { severity: LintSeverity::Error, path, message });
+    }
+
+    fn warning(&mut self, path: String, message: String) {
+        self.issues.push(LintIssue { severity: LintSeverity::Warning, path, message });
+    }
+}
+
+fn out_point_string(out_point: &json_types::OutPoint) -> String {
+    format!("{:#x}:{}", out_point.tx_hash, out_point.index.value())
+}
+
+// Check that mock_info is consistent with the transaction: every input, cell dep and header dep is provided in the
+// same order, and nothing else is.
+fn lint_mock_info(linter: &mut Linter, tx: &ReprMockTransaction) {
+    let mock_info = &tx.mock_info;
+
+    if mock_info.inputs.len() != tx.tx.inputs.len() {
+        linter.error(
+            String::from("$.mock_info.inputs"),
+            format!("has {} inputs but the transaction has {}", mock_info.inputs.len(), tx.tx.inputs.len()),
+        );
+    }
+    for (i, (mock_input, input)) in mock_info.inputs.iter().zip(tx.tx.inputs.iter()).enumerate() {
+        if &mock_input.input != input {
+            linter.error(format!("$.mock_info.inputs[{}].input", i), format!("mismatches $.tx.inputs[{}]", i));
+        }
+    }
+
+    // The cell deps that mock_info should provide: the cell deps of the transaction, and the cells referenced by its
+    // dep groups.
+    let mut expect_cell_deps: Vec<(String, json_types::CellDep)> = vec![];
+    for (i, cell_dep) in tx.tx.cell_deps.iter().enumerate() {
+        expect_cell_deps.push((format!("$.tx.cell_deps[{}]", i), cell_dep.clone()));
+        if cell_dep.dep_type != json_types::DepType::DepGroup {
+            continue;
+        }
+        let group = match mock_info.cell_deps.iter().find(|e| e.cell_dep == *cell_dep) {
+            Some(group) => group,
+            None => continue,
+        };
+        if let Ok(out_points) = OutPointVec::from_slice(group.data.as_bytes()) {
+            for (j, out_point) in out_points.into_iter().enumerate() {
+                let cell_dep = json_types::CellDep { out_point: out_point.into(), dep_type: json_types::DepType::Code };
+                expect_cell_deps.push((format!("$.tx.cell_deps[{}] (dep group item {})", i, j), cell_dep));
+            }
+        }
+    }
+    for (path, cell_dep) in &expect_cell_deps {
+        if !mock_info.cell_deps.iter().any(|e| e.cell_dep == *cell_dep) {
+            linter.error(
+                path.clone(),
+                format!("cell dep {} is missing in $.mock_info.cell_deps", out_point_string(&cell_dep.out_point)),
+            );
+        }
+    }
+    for (i, mock_cell_dep) in mock_info.cell_deps.iter().enumerate() {
+        if !expect_cell_deps.iter().any(|(_, e)| *e == mock_cell_dep.cell_dep) {
+            linter.error(
+                format!("$.mock_info.cell_deps[{}].cell_dep", i),
+                String::from("is not referenced by the transaction"),
+            );
+        }
+    }
+
+    if mock_info.header_deps.len() != tx.tx.header_deps.len() {
+        linter.error(
+            String::from("$.mock_info.header_deps"),
+            format!("has {} headers but the transaction has {}", mock_info.header_deps.len(), tx.tx.header_deps.len()),
+        );
+    }
+    for (i, (header, hash)) in mock_info.header_deps.iter().zip(tx.tx.header_deps.iter()).enumerate() {
+        if &header.hash != hash {
+            linter.error(format!("$.mock_info.header_deps[{}].hash", i), format!("mismatches $.tx.header_deps[{}]", i));
+        }
+    }
+}
+
+fn lint_duplicates(linter: &mut Linter, tx: &ReprMockTransaction) {
+    let mut seen: HashMap<&json_types::OutPoint, usize> = HashMap::new();
+    for (i, input) in tx.tx.inputs.iter().enumerate() {
+        if let Some(j) = seen.insert(&input.previous_output, i) {
+            linter.error(
+                format!("$.tx.inputs[{}].previous_output", i),
+                format!("duplicates $.tx.inputs[{}].previous_output", j),
+            );
+        }
+    }
+    let mut seen: HashMap<&json_types::CellDep, usize> = HashMap::new();
+    for (i, cell_dep) in tx.tx.cell_deps.iter().enumerate() {
+        if let Some(j) = seen.insert(cell_dep, i) {
+            linter.error(format!("$.tx.cell_deps[{}]", i), format!("duplicates $.tx.cell_deps[{}]", j));
+        }
+    }
+    let mut seen: HashMap<&H256, usize> = HashMap::new();
+    for (i, hash) in tx.tx.header_deps.iter().enumerate() {
+        if let Some(j) = seen.insert(hash, i) {
+            linter.error(format!("$.tx.header_deps[{}]", i), format!("duplicates $.tx.header_deps[{}]", j));
+        }
+    }
+    let mut seen: HashMap<&json_types::CellDep, usize> = HashMap::new();
+    for (i, mock_cell_dep) in tx.mock_info.cell_deps.iter().enumerate() {
+        if let Some(j) = seen.insert(&mock_cell_dep.cell_dep, i) {
+            linter.warning(
+                format!("$.mock_info.cell_deps[{}].cell_dep", i),
+                format!("duplicates $.mock_info.cell_deps[{}].cell_dep", j),
+            );
+        }
+    }
+}
+
+fn lint_dep_groups(linter: &mut Linter, tx: &ReprMockTransaction) {
+    for (i, mock_cell_dep) in tx.mock_info.cell_deps.iter().enumerate() {
+        if mock_cell_dep.cell_dep.dep_type != json_types::DepType::DepGroup {
+            continue;
+        }
+        if let Err(e) = OutPointVec::from_slice(mock_cell_dep.data.as_bytes()) {
+            linter.error(format!("$.mock_info.cell_deps[{}].data", i), format!("is not a valid OutPointVec: {}", e));
+        }
+    }
+}
+
+fn lint_headers(linter: &mut Linter, tx: &ReprMockTransaction) {
+    for (i, mock_input) in tx.mock_info.inputs.iter().enumerate() {
+        if let Some(header) = &mock_input.header {
+            if !tx.tx.header_deps.contains(header) {
+                linter.warning(
+                    format!("$.mock_info.inputs[{}].header", i),
+                    format!("block {:#x} is not in $.tx.header_deps, load_header of this input will fail", header),
+                );
+            }
+        }
+    }
+}
+
+fn lint_capacity(linter: &mut Linter, tx: &ReprMockTransaction) {
+    if tx.tx.outputs.len() != tx.tx.outputs_data.len() {
+        linter.error(
+            String::from("$.tx.outputs_data"),
+            format!("has {} items but there are {} outputs", tx.tx.outputs_data.len(), tx.tx.outputs.len()),
+        );
+    }
+    for (i, (output, data)) in tx.tx.outputs.iter().zip(tx.tx.outputs_data.iter()).enumerate() {
+        let output: packed::CellOutput = output.clone().into();
+        let capacity: Capacity = output.capacity().unpack();
+        let occupied = Capacity::bytes(data.len()).and_then(|data_capacity| output.occupied_capacity(data_capacity));
+        match occupied {
+            Ok(occupied) if occupied > capacity => linter.error(
+                format!("$.tx.outputs[{}].capacity", i),
+                format!("{} shannons is less than the occupied capacity {} shannons", capacity, occupied),
+            ),
+            Ok(_) => {}
+            Err(e) => linter.error(format!("$.tx.outputs[{}].capacity", i), format!("{}", e)),
+        }
+    }
+}
+
+fn lint_scripts(linter: &mut Linter, tx: &ReprMockTransaction) {
+    // The cells that scripts can be loaded from: code cell deps of the transaction, and the cells in its dep groups.
+    let mut dep_out_points: Vec<OutPoint> = vec![];
+    for cell_dep in &tx.tx.cell_deps {
+        let out_point: OutPoint = cell_dep.out_point.clone().into();
+        if cell_dep.dep_type == json_types::DepType::Code {
+            dep_out_points.push(out_point);
+            continue;
+        }
+        let group = tx.mock_info.cell_deps.iter().find(|e| e.cell_dep == *cell_dep);
+        if let Some(out_points) = group.and_then(|e| OutPointVec::from_slice(e.data.as_bytes()).ok()) {
+            dep_out_points.extend(out_points);
+        }
+    }
+    let mut data_hashes: HashSet<H256> = HashSet::new();
+    let mut type_hashes: HashMap<H256, HashSet<H256>> = HashMap::new();
+    for out_point in &dep_out_points {
+        let json_out_point: json_types::OutPoint = out_point.clone().into();
+        let mock_cell_dep = match tx.mock_info.cell_deps.iter().find(|e| e.cell_dep.out_point == json_out_point) {
+            Some(mock_cell_dep) => mock_cell_dep,
+            None => continue,
+        };
+        let data_hash = H256(blake2b_256(mock_cell_dep.data.as_bytes()));
+        data_hashes.insert(data_hash.clone());
+        if let Some(type_script) = &mock_cell_dep.output.type_ {
+            let type_script: packed::Script = type_script.clone().into();
+            type_hashes.entry(type_script.calc_script_hash().unpack()).or_default().insert(data_hash);
+        }
+    }
+
+    let mut scripts: Vec<(String, &json_types::Script)> = vec![];
+    for (i, mock_input) in tx.mock_info.inputs.iter().enumerate() {
+        scripts.push((format!("$.mock_info.inputs[{}].output.lock", i), &mock_input.output.lock));
+        if let Some(type_script) = &mock_input.output.type_ {
+            scripts.push((format!("$.mock_info.inputs[{}].output.type", i), type_script));
+        }
+    }
+    for (i, output) in tx.tx.outputs.iter().enumerate() {
+        if let Some(type_script) = &output.type_ {
+            scripts.push((format!("$.tx.outputs[{}].type", i), type_script));
+        }
+    }
+    for (path, script) in scripts {
+        let path = format!("{}.code_hash", path);
+        match ScriptHashType::from(script.hash_type.clone()) {
+            ScriptHashType::Type if script.code_hash == TYPE_ID_CODE_HASH => {}
+            ScriptHashType::Type => match type_hashes.get(&script.code_hash).map(|e| e.len()) {
+                None => linter.error(path, format!("no cell dep has type hash {:#x}", script.code_hash)),
+                Some(1) => {}
+                Some(_) => linter.error(
+                    path,
+                    format!("multiple cell deps with different data have type hash {:#x}", script.code_hash),
+                ),
+            },
+            _ => {
+                if !data_hashes.contains(&script.code_hash) {
+                    linter.error(path, format!("no cell dep has data hash {:#x}", script.code_hash));
+                }
+            }
+        }
+    }
+}
+
+fn lint_witnesses(linter: &mut Linter, tx: &ReprMockTransaction) {
+    // The first witness of a lock group is expected to be a WitnessArgs, which most lock scripts read the signature
+    // from.
+    let mut seen: HashSet<H256> = HashSet::new();
+    for (i, mock_input) in tx.mock_info.inputs.iter().enumerate() {
+        let lock: packed::Script = mock_input.output.lock.clone().into();
+        if !seen.insert(lock.calc_script_hash().unpack()) {
+            continue;
+        }
+        match tx.tx.witnesses.get(i) {
+            None => linter.warning(
+                format!("$.tx.witnesses[{}]", i),
+                format!("is missing for the lock group of $.mock_info.inputs[{}]", i),
+            ),
+            Some(witness) => {
+                if let Err(e) = WitnessArgs::from_slice(witness.as_bytes()) {
+                    linter.warning(
+                        format!("$.tx.witnesses[{}]", i),
+                        format!("is not a valid WitnessArgs for the lock group of $.mock_info.inputs[{}]: {}", i, e),
+                    );
+                }
+            }
+        }
+    }
+}
+
+// Check a mock transaction, and report every problem found.
+pub fn lint(tx: &ReprMockTransaction) -> Vec<LintIssue> {
+    let mut linter = Linter { issues: vec![] };
+    lint_mock_info(&mut linter, tx);
+    lint_duplicates(&mut linter, tx);
+    lint_dep_groups(&mut linter, tx);
+    lint_headers(&mut linter, tx);
+    lint_capacity(&mut linter, tx);
+    lint_scripts(&mut linter, tx);
+    lint_witnesses(&mut linter, tx);
+    linter.issues
+}
+
+// Check that mock_info is consistent with the transaction. All problems are reported in the error, one per line.
+pub fn pre_check(tx: &ReprMockTransaction) -> Result<(), String> {
+    let mut linter = Linter { issues: vec![] };
+    lint_mock_info(&mut linter, tx);
+    lint_dep_groups(&mut linter, tx);
+    if linter.issues.is_empty() {
+        return Ok(());
+    }
+    Err(linter.issues.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("\n"))
+}
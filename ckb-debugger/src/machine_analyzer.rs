@@ -1,14 +1,21 @@
+use crate::coverage::{branch_taken, Coverage};
+use crate::disasm::{executable_segments, is_mop};
 use crate::machine_assign::MachineAssign;
+use crate::machine_trace::MachineTrace;
+use crate::misc::HumanReadableCycles;
+use crate::pprof::PprofBuilder;
+use ckb_script::{Scheduler, ROOT_VM_ID};
 use ckb_traits::{CellDataProvider, ExtensionProvider, HeaderProvider};
 use ckb_vm::cost_model::estimate_cycles;
 use ckb_vm::decoder::{build_decoder, Decoder};
 use ckb_vm::instructions::instruction_length;
 use ckb_vm::machine::VERSION0;
 use ckb_vm::registers::{A0, SP};
-use ckb_vm::{Bytes, CoreMachine, Error, FlatMemory, Machine, Register, SupportMachine, WXorXMemory, ISA_MOP};
+use ckb_vm::{Bytes, CoreMachine, Error, FlatMemory, Machine, Memory, Register, SupportMachine, WXorXMemory, ISA_MOP};
 use std::borrow::Cow;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::Path;
 use std::rc::Rc;
 
 type Addr2LineEndianReader = addr2line::gimli::EndianReader<addr2line::gimli::RunTimeEndian, Rc<[u8]>>;
@@ -30,7 +37,7 @@ fn sprint_fun(frame_iter: &mut Addr2LineFrameIter) -> String {
     s
 }
 
-fn goblin_fun(elf: &goblin::elf::Elf) -> HashMap<u64, String> {
+pub(crate) fn goblin_fun(elf: &goblin::elf::Elf) -> HashMap<u64, String> {
     let mut map = HashMap::new();
     for sym in &elf.syms {
         if !sym.is_function() {
@@ -54,19 +61,87 @@ fn goblin_get_sym(elf: &goblin::elf::Elf, sym: &str) -> u64 {
     return 0;
 }
 
+// Source lines of a program, read from its debug info.
+pub struct SourceLines {
+    ctx: Addr2LineContext,
+    // The lowest address of every line.
+    addrs: BTreeMap<(String, u32), u64>,
+}
+
+impl SourceLines {
+    pub fn new(program: &Bytes) -> Result<Self, Box<dyn std::error::Error>> {
+        let object = addr2line::object::File::parse(program.as_ref())?;
+        let ctx = addr2line::Context::new(&object)?;
+        let mut addrs = BTreeMap::new();
+        for (addr, _, loc) in ctx.find_location_range(0, u64::MAX)? {
+            if let (Some(file), Some(line)) = (loc.file, loc.line) {
+                let e = addrs.entry((file.to_string(), line)).or_insert(addr);
+                *e = std::cmp::min(*e, addr);
+            }
+        }
+        Ok(Self { ctx, addrs })
+    }
+
+    pub fn location(&self, addr: u64) -> Option<(String, u32)> {
+        let loc = self.ctx.find_location(addr).ok()??;
+        Some((loc.file?.to_string(), loc.line.filter(|e| *e != 0)?))
+    }
+
+    // The file in the debug info for a path of the client. Programs are often built on another machine, so a file
+    // with the same name and the longest common path suffix is taken if none matches exactly.
+    pub fn file(&self, path: &str) -> Option<String> {
+        let files: BTreeSet<&str> = self.addrs.keys().map(|e| e.0.as_str()).collect();
+        if files.contains(path) {
+            return Some(path.to_string());
+        }
+        let common = |file: &str| {
+            Path::new(file)
+                .components()
+                .rev()
+                .zip(Path::new(path).components().rev())
+                .take_while(|(a, b)| a == b)
+                .count()
+        };
+        files.into_iter().filter(|e| common(e) > 0).max_by_key(|e| common(e)).map(String::from)
+    }
+
+    // Address of a line, or of the next line with code in the same file.
+    pub fn resolve(&self, file: &str, line: u32) -> Option<(u64, u32)> {
+        let ((f, l), addr) = self.addrs.range((file.to_string(), line)..).next()?;
+        if f != file {
+            return None;
+        }
+        Some((*addr, *l))
+    }
+}
+
 struct TrieNode {
     addr: u64,
     link: u64,
     pc: u64,
+    // Pc of the instruction that calls the function.
+    call: u64,
     parent: Option<Rc<RefCell<TrieNode>>>,
     childs: Vec<Rc<RefCell<TrieNode>>>,
     cycles: u64,
+    // Cycles of every pc executed in this call.
+    pcs: HashMap<u64, u64>,
     regs: [[u64; 32]; 2],
 }
 
 impl TrieNode {
     fn root() -> Self {
-        Self { addr: 0, link: 0, pc: 0, parent: None, childs: vec![], cycles: 0, regs: [[0; 32]; 2] }
+        Self {
+            addr: 0,
+            link: 0,
+            pc: 0,
+            call: 0,
+            parent: None,
+            childs: vec![],
+            cycles: 0,
+            pcs: HashMap::new(),
+            regs: [[0; 32]; 2],
+        }
     }
 }
 
@@ -104,12 +179,30 @@ impl Tags {
     }
 }
 
+// Cycles spent on a source line of a function, the line being 0 if unknown. The count of a line is the count of its
+// most executed instruction.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LineCycles {
+    pub func: String,
+    pub file: String,
+    pub line: u32,
+    pub count: u64,
+    pub cycles: u64,
+}
+
 pub struct MachineProfile {
+    program: Bytes,
     addrctx: Addr2LineContext,
     trie_root: Rc<RefCell<TrieNode>>,
     trie_node: Rc<RefCell<TrieNode>>,
     cache_tag: HashMap<u64, Tags>,
     cache_fun: HashMap<u64, String>,
+    // Execution count and cycles of every pc.
+    pcs: HashMap<u64, (u64, u64)>,
+    // Times every conditional branch is taken and not taken.
+    branches: HashMap<u64, (u64, u64)>,
+    // Length of every instruction fused by macro-op fusion.
+    fused: HashMap<u64, u64>,
 }
 
 impl MachineProfile {
@@ -120,11 +213,15 @@ impl MachineProfile {
         let elf = goblin::elf::Elf::parse(&program)?;
         trie_root.borrow_mut().addr = elf.entry;
         Ok(Self {
+            program: program.clone(),
             addrctx: ctx,
             trie_root: trie_root.clone(),
             trie_node: trie_root,
             cache_tag: HashMap::new(),
             cache_fun: goblin_fun(&elf),
+            pcs: HashMap::new(),
+            branches: HashMap::new(),
+            fused: HashMap::new(),
         })
     }
 
@@ -134,11 +231,15 @@ impl MachineProfile {
         let trie_root = Rc::new(RefCell::new(TrieNode::root()));
         let elf = goblin::elf::Elf::parse(&program)?;
         trie_root.borrow_mut().addr = elf.entry;
+        self.program = program.clone();
         self.addrctx = ctx;
         self.trie_root = trie_root.clone();
         self.trie_node = trie_root;
         self.cache_tag = HashMap::new();
         self.cache_fun = goblin_fun(&elf);
+        self.pcs = HashMap::new();
+        self.branches = HashMap::new();
+        self.fused = HashMap::new();
         Ok(())
     }
 
@@ -169,8 +270,210 @@ impl MachineProfile {
         writer.flush().unwrap();
     }
 
-    pub fn display_flamegraph(&mut self, writer: &mut impl std::io::Write) {
-        self.display_flamegraph_rec("", self.trie_root.clone(), writer);
+    // The prefix is prepended to the root frame.
+    pub fn display_flamegraph(&mut self, prefix: &str, writer: &mut impl std::io::Write) {
+        self.display_flamegraph_rec(prefix, self.trie_root.clone(), writer);
+    }
+
+    // Frame of a function at a line, in the form of file:line:func. The file and line are those of the pc, which may
+    // be inlined from another file.
+    fn line_frame(&mut self, func: &str, pc: u64) -> String {
+        let tag = self.get_tag(pc);
+        let line = if tag.line == 0xffffffff { String::from("??") } else { tag.line.to_string() };
+        format!("{}:{}:{}", tag.file, line, func)
+    }
+
+    fn display_flamegraph_lines_rec(
+        &mut self,
+        prefix: &str,
+        node: Rc<RefCell<TrieNode>>,
+        writer: &mut impl std::io::Write,
+    ) {
+        let func = self.get_tag(node.borrow().addr).func();
+        let mut lines: BTreeMap<String, u64> = BTreeMap::new();
+        let pcs: Vec<(u64, u64)> = node.borrow().pcs.iter().map(|(k, v)| (*k, *v)).collect();
+        for (pc, cycles) in pcs {
+            *lines.entry(self.line_frame(&func, pc)).or_default() += cycles;
+        }
+        for (frame, cycles) in lines {
+            writer.write_all(format!("{}{} {}\n", prefix, frame, cycles).as_bytes()).unwrap();
+        }
+        let childs = node.borrow().childs.clone();
+        for e in childs {
+            // The caller is at the line of the call, which is the instruction before the return address.
+            let frame = self.line_frame(&func, e.borrow().link - 1);
+            self.display_flamegraph_lines_rec(&format!("{}{}; ", prefix, frame), e, writer);
+        }
+        writer.flush().unwrap();
+    }
+
+    // Folded stacks with a frame for every source line, so that the cycles of a function are split by its lines.
+    pub fn display_flamegraph_lines(&mut self, prefix: &str, writer: &mut impl std::io::Write) {
+        self.display_flamegraph_lines_rec(prefix, self.trie_root.clone(), writer);
+    }
+
+    // Frames of a pc, the innermost inlined function first. A frame is the function name, the name before demangling,
+    // the file and the line.
+    fn pprof_frames(&mut self, pc: u64) -> Vec<(String, String, String, u32)> {
+        let mut frames = vec![];
+        if let Ok(mut frame_iter) = self.addrctx.find_frames(pc) {
+            while let Ok(Some(frame)) = frame_iter.next() {
+                let function = match frame.function {
+                    Some(function) => function,
+                    None => continue,
+                };
+                let system_name = function.raw_name().map(|e| e.to_string()).unwrap_or(String::from("??"));
+                let name = addr2line::demangle_auto(Cow::from(system_name.clone()), function.language).to_string();
+                let file = frame.location.as_ref().and_then(|e| e.file).unwrap_or("??").to_string();
+                let line = frame.location.as_ref().and_then(|e| e.line).unwrap_or(0);
+                frames.push((name, system_name, file, line));
+            }
+        }
+        if frames.is_empty() {
+            let tag = self.get_tag(pc);
+            let line = if tag.line == 0xffffffff { 0 } else { tag.line };
+            frames.push((tag.func(), tag.func(), tag.file.clone(), line));
+        }
+        frames
+    }
+
+    fn pprof_location(
+        &mut self,
+        builder: &mut PprofBuilder,
+        mapping_id: u64,
+        start_lines: &HashMap<String, u32>,
+        pc: u64,
+    ) -> u64 {
+        if let Some(id) = builder.location_id(mapping_id, pc) {
+            return id;
+        }
+        let lines: Vec<(u64, u32)> = self
+            .pprof_frames(pc)
+            .into_iter()
+            .map(|(name, system_name, file, line)| {
+                let start_line = start_lines.get(&name).copied().unwrap_or_default();
+                (builder.function(&name, &system_name, &file, start_line), line)
+            })
+            .collect();
+        builder.location(mapping_id, pc, &lines)
+    }
+
+    fn pprof_rec(
+        &mut self,
+        builder: &mut PprofBuilder,
+        mapping_id: u64,
+        start_lines: &HashMap<String, u32>,
+        node: Rc<RefCell<TrieNode>>,
+        callers: Vec<u64>,
+        labels: &[(&str, i64)],
+    ) {
+        let mut pcs: Vec<(u64, u64)> = node.borrow().pcs.iter().map(|(k, v)| (*k, *v)).collect();
+        pcs.sort();
+        for (pc, cycles) in pcs {
+            let mut locations = vec![self.pprof_location(builder, mapping_id, start_lines, pc)];
+            locations.extend(&callers);
+            builder.sample(locations, cycles, labels);
+        }
+        let childs = node.borrow().childs.clone();
+        for e in childs {
+            let call = e.borrow().call;
+            let mut locations = vec![self.pprof_location(builder, mapping_id, start_lines, call)];
+            locations.extend(&callers);
+            self.pprof_rec(builder, mapping_id, start_lines, e, locations, labels);
+        }
+    }
+
+    // Add the cycles of every pc to a pprof profile, with the call stack of the pc and the labels.
+    pub fn pprof(
+        &mut self,
+        builder: &mut PprofBuilder,
+        labels: &[(&str, i64)],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mapping_id = builder.mapping(&self.program)?;
+        // The start line of a function is the line of its entry.
+        let mut start_lines = HashMap::new();
+        let addrs: Vec<u64> = self.cache_fun.keys().copied().collect();
+        for addr in addrs {
+            let tag = self.get_tag(addr);
+            if tag.line != 0xffffffff {
+                start_lines.insert(tag.func, tag.line);
+            }
+        }
+        self.pprof_rec(builder, mapping_id, &start_lines, self.trie_root.clone(), vec![], labels);
+        Ok(())
+    }
+
+    // Cycles of all executed source lines, the hottest first.
+    pub fn lines(&mut self) -> Vec<LineCycles> {
+        let mut lines: HashMap<(String, String, u32), LineCycles> = HashMap::new();
+        let pcs: Vec<(u64, (u64, u64))> = self.pcs.iter().map(|(k, v)| (*k, *v)).collect();
+        for (pc, (count, cycles)) in pcs {
+            let tag = self.get_tag(pc);
+            let line = if tag.line == 0xffffffff { 0 } else { tag.line };
+            let func = tag.func();
+            let e = lines.entry((func.clone(), tag.file.clone(), line)).or_insert_with(|| LineCycles {
+                func,
+                file: tag.file.clone(),
+                line,
+                ..Default::default()
+            });
+            e.count = std::cmp::max(e.count, count);
+            e.cycles += cycles;
+        }
+        let mut lines: Vec<LineCycles> = lines.into_values().collect();
+        lines.sort_by(|a, b| {
+            b.cycles.cmp(&a.cycles).then_with(|| (&a.func, &a.file, a.line).cmp(&(&b.func, &b.file, b.line)))
+        });
+        lines
+    }
+
+    // Source lines of a function with their share of the cycles of the function and their execution count, like perf
+    // annotate. Lines without cycles are printed as well if the source file can be read. Returns false if the function
+    // is never executed.
+    pub fn display_annotate(&mut self, func: &str, writer: &mut impl std::io::Write) -> bool {
+        let all = self.pcs.values().map(|e| e.1).sum::<u64>();
+        let mut lines: Vec<LineCycles> = self.lines().into_iter().filter(|e| e.func == func).collect();
+        if lines.is_empty() {
+            return false;
+        }
+        let sum = lines.iter().map(|e| e.cycles).sum::<u64>();
+        lines.sort_by(|a, b| (&a.file, a.line).cmp(&(&b.file, b.line)));
+        let share = |cycles: u64, total: u64| cycles as f64 * 100.0 / std::cmp::max(total, 1) as f64;
+        writeln!(writer, "Function {}: {} cycles, {:.2}% of all", func, sum, share(sum, all)).unwrap();
+        writeln!(writer, "{:>8} {:>10} {:>10}  Source", "Percent", "Cycles", "Count").unwrap();
+        let mut i = 0;
+        while i < lines.len() {
+            let file = lines[i].file.clone();
+            let j = i + lines[i..].iter().take_while(|e| e.file == file).count();
+            writeln!(writer, "{}", file).unwrap();
+            let source = std::fs::read_to_string(&file).ok();
+            let source: Vec<&str> = source.as_deref().map(|e| e.lines().collect()).unwrap_or_default();
+            let mut sampled = lines[i..j].iter().peekable();
+            let first = lines[i].line;
+            let last = lines[j - 1].line;
+            for line in first..=last {
+                let text = source.get((line as usize).wrapping_sub(1)).copied();
+                match sampled.next_if(|e| e.line == line) {
+                    Some(e) => writeln!(
+                        writer,
+                        "{:>7.2}% {:>10} {:>10}  {:>5}: {}",
+                        share(e.cycles, sum),
+                        e.cycles,
+                        e.count,
+                        if line == 0 { String::from("??") } else { line.to_string() },
+                        text.unwrap_or_default()
+                    )
+                    .unwrap(),
+                    None if text.is_some() => {
+                        writeln!(writer, "{:>8} {:>10} {:>10}  {:>5}: {}", "", "", "", line, text.unwrap()).unwrap()
+                    }
+                    None => {}
+                }
+            }
+            i = j;
+        }
+        writer.flush().unwrap();
+        true
     }
 
     pub fn display_stacktrace(&mut self, prefix: &str, writer: &mut impl std::io::Write) {
@@ -192,6 +495,101 @@ impl MachineProfile {
         writer.flush().unwrap();
     }
 
+    fn function_cycles_rec(
+        &mut self,
+        node: Rc<RefCell<TrieNode>>,
+        stack: &mut Vec<String>,
+        functions: &mut BTreeMap<String, u64>,
+    ) -> u64 {
+        let func = self.get_tag(node.borrow().addr).func();
+        stack.push(func.clone());
+        let mut cycles = node.borrow().cycles;
+        let childs = node.borrow().childs.clone();
+        for e in childs {
+            cycles += self.function_cycles_rec(e, stack, functions);
+        }
+        stack.pop();
+        if !stack.contains(&func) {
+            *functions.entry(func).or_default() += cycles;
+        }
+        cycles
+    }
+
+    // Cycles of every function, including the functions it calls. Cycles of recursive calls are counted once.
+    pub fn function_cycles(&mut self) -> BTreeMap<String, u64> {
+        let mut functions = BTreeMap::new();
+        self.function_cycles_rec(self.trie_root.clone(), &mut vec![], &mut functions);
+        functions
+    }
+
+    // Coverage of every line and every conditional branch of the executable segments, the program being decoded the
+    // same way as the vm of the given isa and version. Instructions fused by macro-op fusion are executed along with
+    // the first one.
+    pub fn coverage(&mut self, isa: u8, version: u32) -> Result<Coverage, Box<dyn std::error::Error>> {
+        let (mut memory, segments) = executable_segments(&self.program)?;
+        let mut decoder = build_decoder::<u64>(isa & !ISA_MOP, version);
+        let fused: BTreeMap<u64, u64> = self.fused.iter().map(|(k, v)| (*k, *v)).collect();
+        let count = |pcs: &HashMap<u64, (u64, u64)>, addr: u64| match pcs.get(&addr) {
+            Some(e) => e.0,
+            None => match fused.range(..addr).next_back() {
+                Some((pc, length)) if pc + length > addr => pcs[pc].0,
+                _ => 0,
+            },
+        };
+        let mut coverage = Coverage::default();
+        let mut blocks: HashMap<(String, u32), u32> = HashMap::new();
+        for (start, end) in segments {
+            let mut addr = start;
+            while addr < end {
+                let inst = match decoder.decode(&mut memory, addr) {
+                    Ok(inst) => inst,
+                    Err(_) => {
+                        addr += if memory.load8(&addr)? & 0x3 == 0x3 { 4 } else { 2 };
+                        continue;
+                    }
+                };
+                let location = self.addrctx.find_location(addr)?;
+                let (file, line) = match location.map(|e| (e.file, e.line)) {
+                    Some((Some(file), Some(line))) if line != 0 => (file.to_string(), line),
+                    _ => {
+                        addr += instruction_length(inst) as u64;
+                        continue;
+                    }
+                };
+                let count = count(&self.pcs, addr);
+                let e = coverage.files.entry(file.clone()).or_default();
+                let lines = e.lines.entry(line).or_default();
+                *lines = std::cmp::max(*lines, count);
+                if branch_taken(inst, &[0; 32]).is_some() {
+                    let block = blocks.entry((file, line)).or_default();
+                    let (taken, not_taken) = self.branches.get(&addr).copied().unwrap_or_default();
+                    e.branches.insert((line, *block, 0), (count > 0).then_some(taken));
+                    e.branches.insert((line, *block, 1), (count > 0).then_some(not_taken));
+                    *block += 1;
+                }
+                addr += instruction_length(inst) as u64;
+            }
+        }
+        Ok(coverage)
+    }
+
+    // Return addresses of the calls on the stack, innermost first.
+    pub fn frames(&self) -> Vec<u64> {
+        let mut frames = vec![];
+        let mut frame = self.trie_node.clone();
+        loop {
+            let parent = frame.borrow().parent.clone();
+            match parent {
+                Some(p) => {
+                    frames.push(frame.borrow().link);
+                    frame = p;
+                }
+                None => break,
+            }
+        }
+        frames
+    }
+
     pub fn step<DL>(&mut self, decoder: &mut Decoder, machine: &mut MachineAssign<DL>) -> Result<(), Error>
     where
         DL: CellDataProvider + HeaderProvider + ExtensionProvider + Send + Sync + Clone + 'static,
@@ -202,6 +600,21 @@ impl MachineProfile {
         let cycles = estimate_cycles(inst);
         self.trie_node.borrow_mut().cycles += cycles;
         self.trie_node.borrow_mut().pc = pc;
+        *self.trie_node.borrow_mut().pcs.entry(pc).or_default() += cycles;
+        let e = self.pcs.entry(pc).or_default();
+        e.0 += 1;
+        e.1 += cycles;
+        if let Some(taken) = branch_taken(inst, machine.registers()) {
+            let e = self.branches.entry(pc).or_default();
+            if taken {
+                e.0 += 1;
+            } else {
+                e.1 += 1;
+            }
+        }
+        if is_mop(inst) {
+            self.fused.insert(pc, instruction_length(inst) as u64);
+        }
 
         let call = |s: &mut Self, addr: u64, link: u64| {
             let mut regs = [[0; 32]; 2];
@@ -212,9 +625,11 @@ impl MachineProfile {
                 addr: addr,
                 link: link,
                 pc: pc,
+                call: pc,
                 parent: Some(s.trie_node.clone()),
                 childs: vec![],
                 cycles: 0,
+                pcs: HashMap::new(),
                 regs: regs,
             }));
             s.trie_node.borrow_mut().childs.push(chd.clone());
@@ -406,17 +821,110 @@ impl MachineStepLog {
     }
 }
 
+// A range of memory watched by MachineWatch.
+#[derive(Clone, Debug)]
+pub struct Watchpoint {
+    pub name: String,
+    pub addr: u64,
+    pub len: u64,
+    value: Option<Vec<u8>>,
+}
+
+// Watch memory of a vm without gdb. The watched memory is compared after each instruction, so changes made by syscalls
+// are found as well.
+pub struct MachineWatch {
+    pub id: u64,
+    pub watchpoints: Vec<Watchpoint>,
+}
+
+impl MachineWatch {
+    // Watchpoints are in the form of <addr|symbol>[:len]. Symbols are looked up in the program of the watched vm, and
+    // the length defaults to the size of the symbol, or 8 bytes.
+    pub fn new(id: u64, program: &Bytes, specs: &[&str]) -> Result<Self, Box<dyn std::error::Error>> {
+        let elf = goblin::elf::Elf::parse(program)?;
+        let mut watchpoints = vec![];
+        for spec in specs {
+            let (name, len) = match spec.rsplit_once(':') {
+                Some((name, len)) => (name, Some(len.parse::<u64>()?)),
+                None => (*spec, None),
+            };
+            let (addr, size) = if let Some(hex) = name.strip_prefix("0x") {
+                (u64::from_str_radix(hex, 16)?, 0)
+            } else if let Ok(addr) = name.parse::<u64>() {
+                (addr, 0)
+            } else {
+                elf.syms
+                    .iter()
+                    .find(|e| matches!(elf.strtab.get(e.st_name), Some(Ok(r)) if r == name))
+                    .map(|e| (e.st_value, e.st_size))
+                    .ok_or_else(|| format!("Unable to find symbol {}", name))?
+            };
+            let len = len.unwrap_or(if size == 0 { 8 } else { size });
+            if len == 0 {
+                return Err(format!("Invalid watchpoint {}", spec).into());
+            }
+            watchpoints.push(Watchpoint { name: name.to_string(), addr, len, value: None });
+        }
+        Ok(Self { id, watchpoints })
+    }
+
+    // Returns the index, old and new value of the changed watchpoints. The first check of a watchpoint only reads its
+    // value.
+    pub fn step<DL>(&mut self, machine: &mut MachineAssign<DL>) -> Vec<(usize, Vec<u8>, Vec<u8>)>
+    where
+        DL: CellDataProvider + HeaderProvider + ExtensionProvider + Send + Sync + Clone + 'static,
+    {
+        let mut changes = vec![];
+        if machine.id != self.id {
+            return changes;
+        }
+        for (i, e) in self.watchpoints.iter_mut().enumerate() {
+            let value = match machine.memory_mut().load_bytes(e.addr, e.len) {
+                Ok(value) => value.to_vec(),
+                Err(_) => continue,
+            };
+            match e.value.replace(value.clone()) {
+                Some(old) if old != value => changes.push((i, old, value)),
+                _ => {}
+            }
+        }
+        changes
+    }
+
+    // Values up to 8 bytes are printed as little endian integers, longer ones as bytes.
+    pub fn display_value(value: &[u8]) -> String {
+        if value.len() > 8 {
+            return hex::encode(value);
+        }
+        let n = value.iter().rev().fold(0u64, |acc, e| (acc << 8) | *e as u64);
+        format!("{:#x} ({})", n, n)
+    }
+}
+
+// A vm of the scheduler, and the analyzers of it.
+pub struct MachineProcess {
+    pub parent: Option<u64>,
+    pub profile: MachineProfile,
+    pub overlap: MachineOverlap,
+}
+
 pub struct MachineAnalyzer<DL>
 where
     DL: CellDataProvider + HeaderProvider + ExtensionProvider + Send + Sync + Clone + 'static,
 {
+    // Coverage is collected if it is some, which keeps the coverage of the programs replaced by exec.
+    pub coverage: Option<Coverage>,
     pub enable_overlap: u8,
     pub enable_profile: u8,
     pub enable_steplog: u8,
+    pub enable_trace: u8,
+    pub enable_watch: u8,
     pub machine: MachineAssign<DL>,
-    pub profile: MachineProfile,
-    pub overlap: MachineOverlap,
+    // Processes indexed by vm id. Only the debugged vm is here, unless all vms are debugged.
+    pub processes: BTreeMap<u64, MachineProcess>,
     pub steplog: MachineStepLog,
+    pub trace: Option<MachineTrace>,
+    pub watch: Option<MachineWatch>,
 }
 
 impl<DL> CoreMachine for MachineAnalyzer<DL>
@@ -495,7 +1003,33 @@ where
         overlap: MachineOverlap,
         steplog: MachineStepLog,
     ) -> Self {
-        Self { enable_overlap: 0, enable_profile: 1, enable_steplog: 0, machine, profile, overlap, steplog }
+        let mut processes = BTreeMap::new();
+        processes.insert(machine.id, MachineProcess { parent: None, profile, overlap });
+        Self {
+            coverage: None,
+            enable_overlap: 0,
+            enable_profile: 1,
+            enable_steplog: 0,
+            enable_trace: 0,
+            enable_watch: 0,
+            machine,
+            processes,
+            steplog,
+            trace: None,
+            watch: None,
+        }
+    }
+
+    // Analyze all vms of a scheduler from the start, booting the root vm of its program with the arguments.
+    pub fn boot(scheduler: Scheduler<DL>, args: &[Bytes], max_cycles: u64) -> Result<Self, Box<dyn std::error::Error>> {
+        let program = scheduler.tx_data.program.clone();
+        let mut machine = MachineAssign::new(ROOT_VM_ID, args, scheduler)?;
+        machine.expand_cycles = max_cycles;
+        machine.all_vms = true;
+        machine.wait()?;
+        let profile = MachineProfile::new(&program)?;
+        let overlap = MachineOverlap::new(&program)?;
+        Ok(Self::new(machine, profile, overlap, MachineStepLog::new()))
     }
 
     pub fn run(&mut self) -> Result<i8, Error> {
@@ -505,21 +1039,193 @@ where
         let mut decoder = build_decoder::<u64>(self.isa(), self.version());
         self.machine.set_running(true);
         while self.machine.running() {
-            if self.machine.reset_signal() {
+            self.step(&mut decoder)?;
+        }
+        Ok(self.machine.exit_code())
+    }
+
+    // Execute an instruction of the current vm, and analyze it with the enabled analyzers.
+    pub fn step(&mut self, decoder: &mut Decoder) -> Result<(), Error> {
+        let id = self.machine.id;
+        let process = self.processes.get_mut(&id).ok_or_else(|| Error::Unexpected(format!("Unknown vm {}", id)))?;
+        if self.machine.reset_signal() {
+            decoder.reset_instructions_cache();
+            if let Some(coverage) = self.coverage.as_mut() {
+                let replaced = process
+                    .profile
+                    .coverage(self.machine.isa(), self.machine.version())
+                    .map_err(|e| Error::External(e.to_string()))?;
+                coverage.merge(replaced);
+            }
+            process.profile = MachineProfile::new(&self.machine.code()).unwrap();
+            if let Some(trace) = self.trace.as_mut() {
+                trace.reset(id);
+            }
+        }
+        if self.enable_profile > 0 && self.enable_overlap > 0 {
+            process.overlap.step(decoder, &mut self.machine, &process.profile)?;
+        }
+        if self.enable_profile > 0 {
+            process.profile.step(decoder, &mut self.machine)?;
+        }
+        if self.enable_steplog > 0 {
+            self.steplog.step(&mut self.machine)?;
+        }
+        if let (true, Some(trace)) = (self.enable_trace > 0, self.trace.as_mut()) {
+            trace.before(decoder, &mut self.machine)?;
+        }
+        if self.machine.syscall_trace.is_some() {
+            let pc = *self.machine.pc();
+            let inst = decoder.decode(self.machine.memory_mut(), pc)?;
+            if ckb_vm::instructions::extract_opcode(inst) == ckb_vm::instructions::insts::OP_ECALL {
+                let mut backtrace = vec![];
+                process.profile.display_stacktrace("", &mut backtrace);
+                let backtrace = String::from_utf8_lossy(&backtrace).lines().map(String::from).collect();
+                self.machine.syscall_trace.as_mut().unwrap().backtrace = backtrace;
+            }
+        }
+        let next_vm_id = self.machine.scheduler.next_vm_id;
+        self.machine.step(decoder)?;
+        if let (true, Some(trace)) = (self.enable_trace > 0, self.trace.as_mut()) {
+            trace.after(&mut self.machine)?;
+        }
+        if self.enable_watch > 0 {
+            self.check_watch(id);
+        }
+        if self.machine.all_vms {
+            for child in next_vm_id..self.machine.scheduler.next_vm_id {
+                self.spawn(id, child)?;
+            }
+            // The instruction cache is indexed by pc, which is not shared between vms.
+            if self.machine.id != id {
                 decoder.reset_instructions_cache();
-                self.profile = MachineProfile::new(&self.machine.code()).unwrap();
             }
-            if self.enable_profile > 0 && self.enable_overlap > 0 {
-                self.overlap.step(&mut decoder, &mut self.machine, &self.profile)?;
+        }
+        Ok(())
+    }
+
+    // Print the changed watchpoints, with the stack trace of the vm that executed the last instruction.
+    fn check_watch(&mut self, id: u64) {
+        let watch = match self.watch.as_mut() {
+            Some(watch) => watch,
+            None => return,
+        };
+        for (i, old, new) in watch.step(&mut self.machine) {
+            let e = &watch.watchpoints[i];
+            println!("Watchpoint {} ({:#x}, {} bytes) changed in pid {}", e.name, e.addr, e.len, id);
+            println!("  Old value: {}", MachineWatch::display_value(&old));
+            println!("  New value: {}", MachineWatch::display_value(&new));
+            if let Some(process) = self.processes.get_mut(&id) {
+                process.profile.display_stacktrace("  ", &mut std::io::stdout());
             }
-            if self.enable_profile > 0 {
-                self.profile.step(&mut decoder, &mut self.machine)?;
+        }
+    }
+
+    fn spawn(&mut self, parent: u64, id: u64) -> Result<(), Error> {
+        let code = match self.machine.scheduler.instantiated.get(&id) {
+            Some((_, vm)) => vm.machine.code().clone(),
+            None => return Err(Error::Unexpected(format!("Unable to find vm {}", id))),
+        };
+        let profile = MachineProfile::new(&code).map_err(|e| Error::External(e.to_string()))?;
+        let overlap = MachineOverlap::new(&code).map_err(|e| Error::External(e.to_string()))?;
+        self.processes.insert(id, MachineProcess { parent: Some(parent), profile, overlap });
+        Ok(())
+    }
+
+    // Exit code of a process, or None if it has not exited.
+    pub fn exit_code_of(&self, id: u64) -> Option<i8> {
+        if id == ROOT_VM_ID {
+            return if self.machine.id == ROOT_VM_ID && !self.machine.running() {
+                Some(self.machine.exit_code())
+            } else {
+                None
+            };
+        }
+        self.machine.exit_codes.get(&id).copied()
+    }
+
+    // Folded stacks of all processes. When there are more than one process, root frames are prefixed with the vm id.
+    pub fn display_flamegraph(&mut self, writer: &mut impl std::io::Write) {
+        let prefix = self.processes.len() > 1;
+        for (id, process) in self.processes.iter_mut() {
+            let prefix = if prefix { format!("[pid {}] ", id) } else { String::new() };
+            process.profile.display_flamegraph(&prefix, writer);
+        }
+    }
+
+    // Folded stacks of all processes split by source lines, see MachineProfile::display_flamegraph_lines.
+    pub fn display_flamegraph_lines(&mut self, writer: &mut impl std::io::Write) {
+        let prefix = self.processes.len() > 1;
+        for (id, process) in self.processes.iter_mut() {
+            let prefix = if prefix { format!("[pid {}] ", id) } else { String::new() };
+            process.profile.display_flamegraph_lines(&prefix, writer);
+        }
+    }
+
+    // Pprof profile of all processes, the samples of each process labeled with its vm id as pid.
+    pub fn pprof(&mut self) -> Result<ckb_vm_pprof_protos::profile::Profile, Box<dyn std::error::Error>> {
+        let mut builder = PprofBuilder::new();
+        for (id, process) in self.processes.iter_mut() {
+            process.profile.pprof(&mut builder, &[("pid", *id as i64)])?;
+        }
+        Ok(builder.build())
+    }
+
+    // Annotated source of a function in every process that executes it. Returns false if no process executes it.
+    pub fn display_annotate(&mut self, func: &str, writer: &mut impl std::io::Write) -> bool {
+        let prefix = self.processes.len() > 1;
+        let mut found = false;
+        for (id, process) in self.processes.iter_mut() {
+            if prefix && process.profile.lines().iter().any(|e| e.func == func) {
+                writeln!(writer, "[pid {}]", id).unwrap();
+            }
+            found |= process.profile.display_annotate(func, writer);
+        }
+        found
+    }
+
+    // Coverage of all processes, including the programs replaced by exec.
+    pub fn coverage(&mut self) -> Result<Coverage, Box<dyn std::error::Error>> {
+        let (isa, version) = (self.machine.isa(), self.machine.version());
+        let mut coverage = self.coverage.clone().unwrap_or_default();
+        for process in self.processes.values_mut() {
+            coverage.merge(process.profile.coverage(isa, version)?);
+        }
+        Ok(coverage)
+    }
+
+    // Stack trace of the current process.
+    pub fn display_stacktrace(&mut self, prefix: &str, writer: &mut impl std::io::Write) {
+        if let Some(process) = self.processes.get_mut(&self.machine.id) {
+            process.profile.display_stacktrace(prefix, writer);
+        }
+    }
+
+    fn display_spawn_tree_rec(&self, id: u64, depth: usize, writer: &mut impl std::io::Write) {
+        let exit_code = self.exit_code_of(id).map(|e| e.to_string()).unwrap_or(String::from("-"));
+        let cycles = self.machine.vm_cycles.get(&id).copied().unwrap_or_default();
+        let line = format!(
+            "{}pid {}: exit code {}, cycles {}\n",
+            "  ".repeat(depth),
+            id,
+            exit_code,
+            HumanReadableCycles(cycles)
+        );
+        writer.write_all(line.as_bytes()).unwrap();
+        for (child, process) in &self.processes {
+            if process.parent == Some(id) {
+                self.display_spawn_tree_rec(*child, depth + 1, writer);
             }
-            if self.enable_steplog > 0 {
-                self.steplog.step(&mut self.machine)?;
+        }
+    }
+
+    // Processes indented under the process that spawns them, with their exit codes and cycles.
+    pub fn display_spawn_tree(&self, writer: &mut impl std::io::Write) {
+        for (id, process) in &self.processes {
+            if process.parent.is_none() {
+                self.display_spawn_tree_rec(*id, 0, writer);
             }
-            self.machine.step(&mut decoder)?;
         }
-        Ok(self.machine.exit_code())
+        writer.flush().unwrap();
     }
 }
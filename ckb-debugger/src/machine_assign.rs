@@ -1,13 +1,16 @@
-use ckb_script::{DataPieceId, RunMode, Scheduler, ROOT_VM_ID};
+use crate::syscall_fault::FaultInjector;
+use crate::syscall_trace::SyscallTrace;
+use ckb_script::{DataPieceId, Scheduler, VmState, ROOT_VM_ID};
 use ckb_traits::{CellDataProvider, ExtensionProvider, HeaderProvider};
 use ckb_vm::cost_model::estimate_cycles;
 use ckb_vm::decoder::Decoder;
 use ckb_vm::instructions::execute;
 use ckb_vm::machine::Pause;
-use ckb_vm::registers::A7;
+use ckb_vm::registers::{A0, A7};
 use ckb_vm::{
     Bytes, CoreMachine, DefaultCoreMachine, Error, FlatMemory, Machine, SupportMachine, Syscalls, WXorXMemory,
 };
+use std::collections::BTreeMap;
 
 pub struct MachineAssign<DL>
 where
@@ -17,6 +20,19 @@ where
     pub scheduler: Scheduler<DL>,
     pub expand_cycles: u64,
     pub expand_syscalls: Vec<Box<(dyn Syscalls<DefaultCoreMachine<u64, WXorXMemory<FlatMemory<u64>>>>)>>,
+    // Cycles consumed by each vm, indexed by vm id.
+    pub vm_cycles: BTreeMap<u64, u64>,
+    // Debug all vms. Instead of running the other vms to completion, the machine switches to the next vm to run when
+    // the current one yields or exits, so every instruction of every vm goes through step.
+    pub all_vms: bool,
+    // Trace of the syscalls of the debugged vms.
+    pub syscall_trace: Option<SyscallTrace>,
+    // Faults injected into the syscalls of the debugged vms.
+    pub faults: Option<FaultInjector>,
+    // Exit codes of the terminated vms, indexed by vm id. The scheduler forgets an exit code once the vm is waited
+    // for, so they are kept here.
+    pub exit_codes: BTreeMap<u64, i8>,
+    yielded: bool,
 }
 
 impl<DL> CoreMachine for MachineAssign<DL>
@@ -126,26 +142,29 @@ where
     DL: CellDataProvider + HeaderProvider + ExtensionProvider + Send + Sync + Clone + 'static,
 {
     fn ecall(&mut self) -> Result<(), Error> {
-        let dm = &mut self.scheduler.instantiated.get_mut(&self.id).unwrap().1.machine;
-        for i in 0..self.expand_syscalls.len() {
-            if self.expand_syscalls[i].ecall(dm.inner_mut())? {
-                return Ok(());
-            }
+        if self.syscall_trace.is_none() && self.faults.is_none() {
+            return self.syscall();
         }
+        let cycles = self.total_cycles();
         let dm = &mut self.scheduler.instantiated.get_mut(&self.id).unwrap().1.machine;
-        let result = dm.ecall();
-        let cycles = dm.cycles();
-        if result == Err(Error::Yield) {
-            dm.set_cycles(0);
-            self.scheduler.iterate_process_results(self.id, Err(Error::Yield), cycles)?;
-            self.scheduler.consumed_cycles_add(self.scheduler.current_iteration_cycles)?;
-            self.wait()?;
-            return Ok(());
+        if let Some(trace) = self.syscall_trace.as_mut() {
+            trace.call(self.id, cycles, dm)?;
         }
-        if dm.registers()[A7] == 93 {
-            dm.set_cycles(0);
-            self.scheduler.consumed_cycles_add(cycles)?;
-            return Ok(());
+        let fault = match self.faults.as_mut() {
+            Some(faults) => faults.call(self.id, dm)?,
+            None => None,
+        };
+        let result = match fault {
+            // The syscall fails without being made.
+            Some(code) => {
+                dm.set_register(A0, code);
+                Ok(())
+            }
+            None => self.syscall(),
+        };
+        // Syscalls yielding to the scheduler are done when the vm runs again, see wait.
+        if !self.yielded {
+            self.ret_syscalls()?;
         }
         result
     }
@@ -170,8 +189,74 @@ impl<DL> MachineAssign<DL>
 where
     DL: CellDataProvider + HeaderProvider + ExtensionProvider + Send + Sync + Clone + 'static,
 {
+    fn syscall(&mut self) -> Result<(), Error> {
+        let dm = &mut self.scheduler.instantiated.get_mut(&self.id).unwrap().1.machine;
+        for i in 0..self.expand_syscalls.len() {
+            if self.expand_syscalls[i].ecall(dm.inner_mut())? {
+                return Ok(());
+            }
+        }
+        let dm = &mut self.scheduler.instantiated.get_mut(&self.id).unwrap().1.machine;
+        let result = dm.ecall();
+        let cycles = dm.cycles();
+        if result == Err(Error::Yield) {
+            dm.set_cycles(0);
+            *self.vm_cycles.entry(self.id).or_default() += cycles;
+            self.scheduler.iterate_process_results(self.id, Err(Error::Yield), cycles)?;
+            self.scheduler.consumed_cycles_add(self.scheduler.current_iteration_cycles)?;
+            if self.all_vms {
+                self.expand_cycles = self
+                    .expand_cycles
+                    .checked_sub(self.scheduler.current_iteration_cycles)
+                    .ok_or(Error::CyclesExceeded)?;
+                self.yielded = true;
+                return Ok(());
+            }
+            self.wait()?;
+            return Ok(());
+        }
+        if dm.registers()[A7] == 93 {
+            dm.set_cycles(0);
+            *self.vm_cycles.entry(self.id).or_default() += cycles;
+            self.scheduler.consumed_cycles_add(cycles)?;
+            if self.all_vms {
+                self.expand_cycles = self.expand_cycles.checked_sub(cycles).ok_or(Error::CyclesExceeded)?;
+            }
+            return Ok(());
+        }
+        result
+    }
+
+    // The traced or injected syscalls of the current vm are done, since it runs again.
+    fn ret_syscalls(&mut self) -> Result<(), Error> {
+        if self.syscall_trace.is_none() && self.faults.is_none() {
+            return Ok(());
+        }
+        let dm = &mut self.scheduler.instantiated.get_mut(&self.id).unwrap().1.machine;
+        if let Some(faults) = self.faults.as_mut() {
+            faults.ret(self.id, dm)?;
+        }
+        if let Some(trace) = self.syscall_trace.as_mut() {
+            trace.ret(self.id, dm)?;
+        }
+        Ok(())
+    }
+
     pub fn new(id: u64, args: &[Bytes], scheduler: Scheduler<DL>) -> Result<Self, Error> {
-        let mut r = Self { id: id, scheduler: scheduler, expand_cycles: u64::MAX, expand_syscalls: vec![] };
+        let mut r = Self {
+            id: id,
+            scheduler: scheduler,
+            expand_cycles: u64::MAX,
+            expand_syscalls: vec![],
+            vm_cycles: BTreeMap::new(),
+            all_vms: false,
+            syscall_trace: None,
+            faults: None,
+            exit_codes: BTreeMap::new(),
+            yielded: false,
+        };
+        // A resumed scheduler still knows the vms terminated but not waited for.
+        r.exit_codes = r.scheduler.terminated_vms.clone();
         if r.scheduler.states.is_empty() {
             assert_eq!(r.scheduler.boot_vm(&DataPieceId::Program, 0, u64::MAX, args)?, ROOT_VM_ID);
         }
@@ -183,6 +268,11 @@ where
         root_vm.1.machine.exit_code()
     }
 
+    // Cycles consumed by all vms so far, including the ones of the current vm not yet charged to the scheduler.
+    pub fn total_cycles(&self) -> u64 {
+        self.scheduler.consumed_cycles() + self.scheduler.current_iteration_cycles + self.cycles()
+    }
+
     pub fn step(&mut self, decoder: &mut Decoder) -> Result<(), Error> {
         let instruction = {
             let pc = *self.pc();
@@ -191,34 +281,82 @@ where
         };
         let cycles = estimate_cycles(instruction);
         self.add_cycles(cycles)?;
-        execute(instruction, self)
+        execute(instruction, self)?;
+        if self.all_vms {
+            self.switch()?;
+        }
+        Ok(())
     }
 
+    // Switch to the next vm to run if the current one has yielded or exited. It is done after the instruction is
+    // executed, since the pc of the current vm is committed at the end of the instruction.
+    pub fn switch(&mut self) -> Result<(), Error> {
+        if self.id != ROOT_VM_ID && !self.running() {
+            let exit_code = self.scheduler.instantiated[&self.id].1.machine.exit_code();
+            self.process_results(self.id, Ok(exit_code), 0)?;
+            self.scheduler.consumed_cycles_add(self.scheduler.current_iteration_cycles)?;
+            self.expand_cycles =
+                self.expand_cycles.checked_sub(self.scheduler.current_iteration_cycles).ok_or(Error::CyclesExceeded)?;
+        } else if !std::mem::take(&mut self.yielded) {
+            return Ok(());
+        }
+        self.wait()
+    }
+
+    // Run the vms other than the current one until the current one becomes runnable again. If all vms are debugged,
+    // switch to the next runnable vm instead.
     pub fn wait(&mut self) -> Result<(), Error> {
         loop {
             self.scheduler.current_iteration_cycles = 0;
             let im = self.scheduler.iterate_prepare_machine(Pause::new(), self.expand_cycles)?;
             let id = im.0;
-            let vm = im.1;
             if self.id == id {
                 break;
             }
-            let result = vm.run();
-            let cycles = vm.machine.cycles();
-            vm.machine.set_cycles(0);
-            self.scheduler.iterate_process_results(id, result, cycles)?;
-            self.scheduler.consumed_cycles_add(self.scheduler.current_iteration_cycles)?;
-            self.expand_cycles = self.expand_cycles.checked_sub(self.scheduler.current_iteration_cycles).unwrap();
+            if self.all_vms {
+                self.id = id;
+                self.set_running(true);
+                break;
+            }
+            self.iterate(id)?;
         }
-        Ok(())
+        self.ret_syscalls()
     }
 
     pub fn done(&mut self) -> Result<(), Error> {
         let dm = &mut self.scheduler.instantiated.get_mut(&self.id).unwrap().1.machine;
         let dmexit = dm.exit_code();
-        self.scheduler.iterate_process_results(self.id, Ok(dmexit), 0)?;
+        self.process_results(self.id, Ok(dmexit), 0)?;
+        self.scheduler.consumed_cycles_add(self.scheduler.current_iteration_cycles)?;
+        while self.scheduler.states[&ROOT_VM_ID] != VmState::Terminated {
+            self.scheduler.current_iteration_cycles = 0;
+            let id = self.scheduler.iterate_prepare_machine(Pause::new(), self.expand_cycles)?.0;
+            self.iterate(id)?;
+        }
+        Ok(())
+    }
+
+    // Update the scheduler with the result of a vm, and keep its exit code if it terminates.
+    fn process_results(&mut self, id: u64, result: Result<i8, Error>, cycles: u64) -> Result<(), Error> {
+        let exit_code = result.as_ref().ok().copied();
+        self.scheduler.iterate_process_results(id, result, cycles)?;
+        if let Some(exit_code) = exit_code {
+            self.exit_codes.insert(id, exit_code);
+        }
+        Ok(())
+    }
+
+    // Run a prepared vm until it yields or terminates, and account for its cycles.
+    fn iterate(&mut self, id: u64) -> Result<(), Error> {
+        let vm = &mut self.scheduler.instantiated.get_mut(&id).unwrap().1;
+        let result = vm.run();
+        let cycles = vm.machine.cycles();
+        vm.machine.set_cycles(0);
+        *self.vm_cycles.entry(id).or_default() += cycles;
+        self.process_results(id, result, cycles)?;
         self.scheduler.consumed_cycles_add(self.scheduler.current_iteration_cycles)?;
-        self.scheduler.run(RunMode::LimitCycles(self.expand_cycles))?;
-        return Ok(());
+        self.expand_cycles =
+            self.expand_cycles.checked_sub(self.scheduler.current_iteration_cycles).ok_or(Error::CyclesExceeded)?;
+        Ok(())
     }
 }
@@ -0,0 +1,470 @@
+use crate::machine_analyzer::{MachineProfile, SourceLines};
+use crate::machine_assign::MachineAssign;
+use ckb_script::ROOT_VM_ID;
+use ckb_traits::{CellDataProvider, ExtensionProvider, HeaderProvider};
+use ckb_types::packed::Byte32;
+use ckb_vm::decoder::{build_decoder, Decoder};
+use ckb_vm::registers::{REGISTER_ABI_NAMES, SP};
+use ckb_vm::{Bytes, CoreMachine, Error, Memory, SupportMachine};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
+
+// Variables references of the scopes. Both of them show the current vm, whichever frame is selected.
+const SCOPE_REGISTERS: u64 = 1;
+const SCOPE_MEMORY: u64 = 2;
+// Number of 64 bits words from the stack pointer shown in the memory scope.
+const SCOPE_MEMORY_WORDS: u64 = 32;
+
+// Read a message in the form of a Content-Length header and a json body. Returns None at the end of the stream.
+pub fn read_dap_message(reader: &mut impl BufRead) -> std::io::Result<Option<Value>> {
+    let mut len = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            if len.is_some() {
+                break;
+            }
+            continue;
+        }
+        if let Some(data) = line.strip_prefix("Content-Length:") {
+            len = Some(
+                data.trim().parse::<usize>().map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?,
+            );
+        }
+    }
+    let mut body = vec![0; len.unwrap()];
+    reader.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+// Read the messages in a thread, so requests such as pause are received while the program is running.
+pub fn spawn_dap_reader(mut reader: impl BufRead + Send + 'static) -> Receiver<Value> {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        while let Ok(Some(message)) = read_dap_message(&mut reader) {
+            if sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Resume {
+    Continue,
+    Instruction,
+    StepIn,
+    Next,
+    StepOut,
+}
+
+// Debug adapter protocol server, see https://microsoft.github.io/debug-adapter-protocol/specification. The debugged
+// vm is exposed as a single thread.
+pub struct DapServer<DL>
+where
+    DL: CellDataProvider + HeaderProvider + ExtensionProvider + Send + Sync + Clone + 'static,
+{
+    pub machine: MachineAssign<DL>,
+    // Script log messages, sent to the client as output events.
+    pub messages: Arc<Mutex<Vec<(Byte32, String)>>>,
+    decoder: Decoder,
+    profile: MachineProfile,
+    lines: SourceLines,
+    // Lines of the source breakpoints, indexed by the path of the client.
+    source_breakpoints: BTreeMap<String, Vec<u32>>,
+    breakpoints: BTreeSet<u64>,
+    // Paths of the client for the files in the debug info.
+    paths: HashMap<String, String>,
+    writer: Box<dyn Write>,
+    pending: VecDeque<Value>,
+    seq: u64,
+    sent_messages: usize,
+    stop_on_entry: bool,
+    launched: bool,
+    configured: bool,
+    started: bool,
+    exited: bool,
+    disconnected: bool,
+}
+
+impl<DL> DapServer<DL>
+where
+    DL: CellDataProvider + HeaderProvider + ExtensionProvider + Send + Sync + Clone + 'static,
+{
+    pub fn new(
+        machine: MachineAssign<DL>,
+        program: &Bytes,
+        messages: Arc<Mutex<Vec<(Byte32, String)>>>,
+        writer: Box<dyn Write>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let decoder = build_decoder::<u64>(machine.isa(), machine.version());
+        let sent_messages = messages.lock().unwrap().len();
+        Ok(Self {
+            machine,
+            messages,
+            decoder,
+            profile: MachineProfile::new(program)?,
+            lines: SourceLines::new(program)?,
+            source_breakpoints: BTreeMap::new(),
+            breakpoints: BTreeSet::new(),
+            paths: HashMap::new(),
+            writer,
+            pending: VecDeque::new(),
+            seq: 0,
+            sent_messages,
+            stop_on_entry: false,
+            launched: false,
+            configured: false,
+            started: false,
+            exited: false,
+            disconnected: false,
+        })
+    }
+
+    // Serve the requests until the client disconnects or the stream ends.
+    pub fn serve(&mut self, requests: Receiver<Value>) -> Result<(), Box<dyn std::error::Error>> {
+        self.machine.set_running(true);
+        while !self.disconnected {
+            let request = match self.pending.pop_front() {
+                Some(request) => request,
+                None => match requests.recv() {
+                    Ok(request) => request,
+                    Err(_) => break,
+                },
+            };
+            self.handle(&request, &requests)?;
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, mut message: Value) -> std::io::Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        let body = serde_json::to_vec(&message)?;
+        self.writer.write_all(format!("Content-Length: {}\r\n\r\n", body.len()).as_bytes())?;
+        self.writer.write_all(&body)?;
+        self.writer.flush()
+    }
+
+    fn respond(&mut self, request: &Value, body: Value) -> std::io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "success": true,
+            "command": request["command"],
+            "body": body,
+        }))
+    }
+
+    fn respond_error(&mut self, request: &Value, message: &str) -> std::io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "success": false,
+            "command": request["command"],
+            "message": message,
+        }))
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> std::io::Result<()> {
+        self.send(json!({"type": "event", "event": event, "body": body}))
+    }
+
+    fn output(&mut self, category: &str, output: String) -> std::io::Result<()> {
+        self.event("output", json!({"category": category, "output": output}))
+    }
+
+    fn stopped(&mut self, reason: &str) -> std::io::Result<()> {
+        self.event("stopped", json!({"reason": reason, "threadId": 1, "allThreadsStopped": true}))
+    }
+
+    fn flush_messages(&mut self) -> std::io::Result<()> {
+        let messages: Vec<String> = {
+            let messages = self.messages.lock().unwrap();
+            messages[self.sent_messages..].iter().map(|e| e.1.clone()).collect()
+        };
+        self.sent_messages += messages.len();
+        for e in messages {
+            self.output("stdout", format!("{}\n", e))?;
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, request: &Value, requests: &Receiver<Value>) -> Result<(), Box<dyn std::error::Error>> {
+        let args = &request["arguments"];
+        match request["command"].as_str().unwrap_or_default() {
+            "initialize" => {
+                self.respond(
+                    request,
+                    json!({
+                        "supportsConfigurationDoneRequest": true,
+                        "supportsSteppingGranularity": true,
+                        "supportsTerminateRequest": true,
+                    }),
+                )?;
+                self.event("initialized", json!({}))?;
+            }
+            "launch" => {
+                self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+                self.launched = true;
+                self.respond(request, json!({}))?;
+                self.start(requests)?;
+            }
+            "configurationDone" => {
+                self.configured = true;
+                self.respond(request, json!({}))?;
+                self.start(requests)?;
+            }
+            "setBreakpoints" => {
+                let path = args["source"]["path"].as_str().unwrap_or_default().to_string();
+                let lines: Vec<u32> = args["breakpoints"]
+                    .as_array()
+                    .map(|e| e.iter().filter_map(|e| e["line"].as_u64()).map(|e| e as u32).collect())
+                    .unwrap_or_default();
+                let breakpoints: Vec<Value> = lines
+                    .iter()
+                    .map(|line| match self.resolve(&path, *line) {
+                        Some((_, line)) => json!({"verified": true, "line": line}),
+                        None => json!({"verified": false, "line": line, "message": "No code at this line"}),
+                    })
+                    .collect();
+                self.source_breakpoints.insert(path, lines);
+                self.reset_breakpoints();
+                self.respond(request, json!({ "breakpoints": breakpoints }))?;
+            }
+            "setExceptionBreakpoints" => self.respond(request, json!({}))?,
+            "threads" => {
+                let name = format!("pid {}", self.machine.id);
+                self.respond(request, json!({"threads": [{"id": 1, "name": name}]}))?;
+            }
+            "stackTrace" => {
+                let frames = self.stack_frames();
+                let total = frames.len();
+                self.respond(request, json!({"stackFrames": frames, "totalFrames": total}))?;
+            }
+            "scopes" => self.respond(
+                request,
+                json!({"scopes": [
+                    {"name": "Registers", "variablesReference": SCOPE_REGISTERS, "expensive": false},
+                    {"name": "Memory", "variablesReference": SCOPE_MEMORY, "expensive": false},
+                ]}),
+            )?,
+            "variables" => {
+                let variables = self.variables(args["variablesReference"].as_u64().unwrap_or_default());
+                self.respond(request, json!({ "variables": variables }))?;
+            }
+            command @ ("continue" | "next" | "stepIn" | "stepOut") => {
+                if self.exited {
+                    self.respond_error(request, "The program has exited")?;
+                    return Ok(());
+                }
+                let instruction = args["granularity"].as_str() == Some("instruction");
+                let resume = match command {
+                    "continue" => Resume::Continue,
+                    "stepOut" => Resume::StepOut,
+                    _ if instruction => Resume::Instruction,
+                    "next" => Resume::Next,
+                    _ => Resume::StepIn,
+                };
+                if resume == Resume::Continue {
+                    self.respond(request, json!({"allThreadsContinued": true}))?;
+                } else {
+                    self.respond(request, json!({}))?;
+                }
+                self.resume(resume, requests)?;
+            }
+            "pause" => self.respond(request, json!({}))?,
+            "disconnect" | "terminate" => {
+                self.respond(request, json!({}))?;
+                self.disconnected = true;
+            }
+            command => self.respond_error(request, &format!("Unsupported request {}", command))?,
+        }
+        Ok(())
+    }
+
+    fn resolve(&mut self, path: &str, line: u32) -> Option<(u64, u32)> {
+        let file = self.lines.file(path)?;
+        self.paths.insert(file.clone(), path.to_string());
+        self.lines.resolve(&file, line)
+    }
+
+    fn reset_breakpoints(&mut self) {
+        self.breakpoints.clear();
+        for (path, lines) in self.source_breakpoints.clone() {
+            for line in lines {
+                if let Some((addr, _)) = self.resolve(&path, line) {
+                    self.breakpoints.insert(addr);
+                }
+            }
+        }
+    }
+
+    // Execution starts once the client has sent both the launch request and the configuration.
+    fn start(&mut self, requests: &Receiver<Value>) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.launched || !self.configured || self.started {
+            return Ok(());
+        }
+        self.started = true;
+        if self.stop_on_entry {
+            self.stopped("entry")?;
+            return Ok(());
+        }
+        self.resume(Resume::Continue, requests)
+    }
+
+    fn step(&mut self) -> Result<(), Error> {
+        if self.machine.reset_signal() {
+            // The vm is replaced by exec, so the lines and breakpoints are of the new program.
+            self.decoder.reset_instructions_cache();
+            let code = self.machine.code().clone();
+            self.profile = MachineProfile::new(&code).map_err(|e| Error::External(e.to_string()))?;
+            self.lines = SourceLines::new(&code).map_err(|e| Error::External(e.to_string()))?;
+            self.reset_breakpoints();
+        }
+        self.profile.step(&mut self.decoder, &mut self.machine)?;
+        self.machine.step(&mut self.decoder)
+    }
+
+    // Run until the resume condition is met, a breakpoint is hit, the client pauses or the program exits. Requests
+    // about the stopped state are delayed until execution stops.
+    fn resume(&mut self, resume: Resume, requests: &Receiver<Value>) -> Result<(), Box<dyn std::error::Error>> {
+        let start = self.lines.location(*self.machine.pc());
+        let depth = self.profile.frames().len();
+        let mut executed = 0u64;
+        while self.machine.running() {
+            if let Err(e) = self.step() {
+                self.flush_messages()?;
+                self.output("stderr", format!("{}\n", e))?;
+                return self.terminate(None);
+            }
+            self.flush_messages()?;
+            if !self.machine.running() {
+                break;
+            }
+            let pc = *self.machine.pc();
+            if self.breakpoints.contains(&pc) {
+                self.stopped("breakpoint")?;
+                return Ok(());
+            }
+            let location = self.lines.location(pc);
+            let current = self.profile.frames().len();
+            let done = match resume {
+                Resume::Continue => false,
+                Resume::Instruction => true,
+                Resume::StepIn => location.is_some() && location != start,
+                Resume::Next => location.is_some() && (current < depth || (current == depth && location != start)),
+                Resume::StepOut => location.is_some() && current < depth,
+            };
+            if done {
+                self.stopped("step")?;
+                return Ok(());
+            }
+            executed += 1;
+            if executed % 1024 != 0 {
+                continue;
+            }
+            while let Ok(request) = requests.try_recv() {
+                match request["command"].as_str().unwrap_or_default() {
+                    "pause" => {
+                        self.respond(&request, json!({}))?;
+                        self.stopped("pause")?;
+                        return Ok(());
+                    }
+                    "disconnect" | "terminate" => {
+                        self.respond(&request, json!({}))?;
+                        self.disconnected = true;
+                        return Ok(());
+                    }
+                    "threads" | "setBreakpoints" | "setExceptionBreakpoints" => self.handle(&request, requests)?,
+                    _ => self.pending.push_back(request),
+                }
+            }
+        }
+        let exit_code = if self.machine.id == ROOT_VM_ID {
+            self.machine.exit_code()
+        } else {
+            let exit_code = self.machine.scheduler.instantiated[&self.machine.id].1.machine.exit_code();
+            if let Err(e) = self.machine.done() {
+                self.output("stderr", format!("{}\n", e))?;
+            }
+            exit_code
+        };
+        self.flush_messages()?;
+        self.output("console", format!("All cycles: {}\n", self.machine.scheduler.consumed_cycles()))?;
+        self.terminate(Some(exit_code))
+    }
+
+    fn terminate(&mut self, exit_code: Option<i8>) -> Result<(), Box<dyn std::error::Error>> {
+        self.exited = true;
+        self.event("exited", json!({"exitCode": exit_code.map(i64::from).unwrap_or(-1)}))?;
+        self.event("terminated", json!({}))?;
+        Ok(())
+    }
+
+    fn stack_frames(&mut self) -> Vec<Value> {
+        let pc = *self.machine.pc();
+        // Return addresses point after the call, the call itself is used to find the source line.
+        let addrs: Vec<(u64, u64)> = std::iter::once((pc, pc))
+            .chain(self.profile.frames().into_iter().map(|e| (e, e.wrapping_sub(1))))
+            .collect();
+        let mut frames = vec![];
+        for (i, (addr, probe)) in addrs.into_iter().enumerate() {
+            let name = self.profile.get_tag(probe).func();
+            let mut frame = json!({
+                "id": i,
+                "name": name,
+                "line": 0,
+                "column": 0,
+                "instructionPointerReference": format!("{:#x}", addr),
+            });
+            if let Some((file, line)) = self.lines.location(probe) {
+                let path = self.paths.get(&file).cloned().unwrap_or(file);
+                let name = Path::new(&path).file_name().map(|e| e.to_string_lossy().to_string()).unwrap_or_default();
+                frame["source"] = json!({"name": name, "path": path});
+                frame["line"] = json!(line);
+                frame["column"] = json!(1);
+            }
+            frames.push(frame);
+        }
+        frames
+    }
+
+    fn variables(&mut self, reference: u64) -> Vec<Value> {
+        let variable = |name: String, value: String| json!({"name": name, "value": value, "variablesReference": 0});
+        match reference {
+            SCOPE_REGISTERS => std::iter::once(variable(String::from("pc"), format!("{:#x}", self.machine.pc())))
+                .chain(
+                    self.machine
+                        .registers()
+                        .iter()
+                        .enumerate()
+                        .map(|(i, e)| variable(REGISTER_ABI_NAMES[i].to_string(), format!("{:#x}", e))),
+                )
+                .collect(),
+            SCOPE_MEMORY => {
+                let sp = self.machine.registers()[SP];
+                (0..SCOPE_MEMORY_WORDS)
+                    .map(|i| sp.wrapping_add(i * 8))
+                    .map(|addr| {
+                        let value = match self.machine.memory_mut().load64(&addr) {
+                            Ok(value) => format!("{:#018x}", value),
+                            Err(_) => String::from("??"),
+                        };
+                        variable(format!("{:#x}", addr), value)
+                    })
+                    .collect()
+            }
+            _ => vec![],
+        }
+    }
+}
@@ -1,4 +1,8 @@
+use crate::machine_analyzer::MachineProfile;
 use crate::machine_assign::MachineAssign;
+use crate::machine_gdb_agent::{eval_agent_expr, BreakpointConditions, GdbConnection};
+use crate::machine_history::{Checkpoint, History, HistoryDelta, HistoryRecord};
+use ckb_script::{Scheduler, VmState};
 use ckb_traits::{CellDataProvider, ExtensionProvider, HeaderProvider};
 use ckb_vm::cost_model::estimate_cycles;
 use ckb_vm::{
@@ -7,45 +11,61 @@ use ckb_vm::{
     instructions::{execute, extract_opcode, insts},
     machine::{CoreMachine, Machine, SupportMachine},
     registers::A7,
-    Error, Memory, Register,
+    snapshot2::DataSource,
+    Error, Memory, Register, RISCV_GENERAL_REGISTER_NUMBER,
 };
 use gdbstub::{
     arch::Arch,
-    common::Signal,
+    common::{Signal, Tid},
     conn::{Connection, ConnectionExt},
     stub::{
         run_blocking::{BlockingEventLoop, Event, WaitForStopReasonError},
-        SingleThreadStopReason,
+        MultiThreadStopReason,
     },
     target::{
         ext::{
             base::{
-                single_register_access::{SingleRegisterAccess, SingleRegisterAccessOps},
-                singlethread::{
-                    SingleThreadBase, SingleThreadRangeStepping, SingleThreadRangeSteppingOps, SingleThreadResume,
-                    SingleThreadResumeOps, SingleThreadSingleStep, SingleThreadSingleStepOps,
+                multithread::{
+                    MultiThreadBase, MultiThreadRangeStepping, MultiThreadRangeSteppingOps, MultiThreadResume,
+                    MultiThreadResumeOps, MultiThreadSingleStep, MultiThreadSingleStepOps,
                 },
+                reverse_exec::{ReplayLogPosition, ReverseCont, ReverseContOps, ReverseStep, ReverseStepOps},
+                single_register_access::{SingleRegisterAccess, SingleRegisterAccessOps},
                 BaseOps,
             },
             breakpoints::{
                 Breakpoints, BreakpointsOps, HwWatchpoint, HwWatchpointOps, SwBreakpoint, SwBreakpointOps, WatchKind,
             },
             catch_syscalls::{CatchSyscallPosition, CatchSyscalls, CatchSyscallsOps, SyscallNumbers},
+            monitor_cmd::{outputln, ConsoleOutput, MonitorCmd, MonitorCmdOps},
+            thread_extra_info::{ThreadExtraInfo, ThreadExtraInfoOps},
         },
         Target, TargetError, TargetResult,
     },
 };
 use gdbstub_arch::riscv::reg::id::RiscvRegId;
 use gdbstub_arch::riscv::Riscv64;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 use std::fmt::Debug;
 use std::marker::PhantomData;
 
+// Each vm of the scheduler is exposed as a gdb thread. Gdb thread ids start from 1, so the vm of id n is the thread
+// n + 1.
+fn tid_of(id: u64) -> Tid {
+    Tid::new(id as usize + 1).unwrap()
+}
+
+fn id_of(tid: Tid) -> u64 {
+    tid.get() as u64 - 1
+}
+
 #[derive(Debug, Clone)]
 pub enum ExecMode {
-    Step,
+    Step(u64),
     Continue,
-    RangeStep(u64, u64),
+    RangeStep(u64, u64, u64),
+    ReverseStep(u64),
+    ReverseContinue,
 }
 
 pub enum FilteredSyscalls {
@@ -64,31 +84,50 @@ impl FilteredSyscalls {
     }
 }
 
+#[derive(Debug, Clone, Default)]
+pub struct Breakpoint {
+    // Agent expressions evaluated when the breakpoint is hit, it stops if any of them is true.
+    pub conditions: Vec<Vec<u8>>,
+    pub hits: u64,
+    // Number of hits to skip before stopping.
+    pub ignore: u64,
+}
+
 pub struct GdbStubHandler<DL>
 where
     DL: CellDataProvider + HeaderProvider + ExtensionProvider + Send + Sync + Clone + 'static,
 {
     pub machine: MachineAssign<DL>,
+    // Recorded execution for reverse debugging, disabled if the window is zero.
+    pub history: History,
     exec_mode: ExecMode,
     decoder: Decoder,
-    breakpoints: Vec<u64>,
+    breakpoints: BTreeMap<u64, Breakpoint>,
+    conditions: BreakpointConditions,
     catch_syscalls: FilteredSyscalls,
-    watchpoints: Vec<(u64, WatchKind)>,
-    memory_writes: Vec<u64>,
-    memory_reads: Vec<u64>,
+    watchpoints: Vec<(u64, u64, WatchKind)>,
+    // Address and size of the memory accessed by the instruction being executed.
+    memory_writes: Vec<(u64, u64)>,
+    memory_reads: Vec<(u64, u64)>,
+    // Changes made by the instruction being executed, if it is recorded.
+    delta: Option<HistoryDelta>,
+    // Call stack of the debugged vm, only meaningful if a single vm is debugged.
+    pub profile: Option<MachineProfile>,
 }
 
 // Note a lot of code in this file is copied over from
 // https://github.com/daniel5151/gdbstub/blob/36f166e1aabe47ea2f0508207372e4a302fbac87/examples/armv4t/emu.rs
 #[derive(Debug, Clone, PartialEq, Eq)]
-enum VmEvent {
+pub enum VmEvent {
     IncomingData,
-    DoneStep,
+    DoneStep(u64),
     Exited(u8),
+    Spawn(u64),
     Break,
     WatchWrite(u64),
     WatchRead(u64),
     CatchSyscall(u64),
+    ReplayBegin,
     Error(Error),
 }
 
@@ -100,20 +139,239 @@ where
         let decoder = build_decoder::<u64>(machine.isa(), machine.version());
         Self {
             machine,
+            history: History::default(),
             decoder,
             exec_mode: ExecMode::Continue,
-            breakpoints: vec![],
+            breakpoints: BTreeMap::new(),
+            conditions: Default::default(),
             catch_syscalls: FilteredSyscalls::None,
             watchpoints: vec![],
             memory_writes: vec![],
             memory_reads: vec![],
+            delta: None,
+            profile: None,
         }
     }
 
+    // Wrap the connection to gdb, so breakpoint conditions are passed to the handler.
+    pub fn connection(&self, inner: Box<dyn ConnectionExt<Error = std::io::Error>>) -> GdbConnection {
+        GdbConnection::new(inner, self.conditions.clone())
+    }
+
+    pub fn breakpoint_addrs(&self) -> Vec<u64> {
+        self.breakpoints.keys().copied().collect()
+    }
+
+    // Whether to stop at a breakpoint on the current pc. Conditions are evaluated in the stub, so gdb is not involved
+    // until they are true. Hits are only counted when executing forward.
+    fn breakpoint_hit(&mut self, forward: bool) -> bool {
+        let breakpoint = match self.breakpoints.get_mut(self.machine.pc()) {
+            Some(breakpoint) => breakpoint,
+            None => return false,
+        };
+        // A condition which can not be evaluated, e.g. reading invalid memory, stops the execution as well.
+        if !breakpoint.conditions.is_empty()
+            && !breakpoint.conditions.iter().any(|e| eval_agent_expr(e, &mut self.machine).map_or(true, |v| v != 0))
+        {
+            return false;
+        }
+        if !forward {
+            return true;
+        }
+        breakpoint.hits += 1;
+        if breakpoint.ignore > 0 {
+            breakpoint.ignore -= 1;
+            return false;
+        }
+        true
+    }
+
+    // The first watchpoint overlapping the accessed memory, reported with the kind of the access, so an access
+    // watchpoint tells whether it is a read or a write.
+    fn watch_hit(&self, writes: &[(u64, u64)], reads: &[(u64, u64)]) -> Option<VmEvent> {
+        let overlap = |accesses: &[(u64, u64)], start: u64, end: u64| {
+            accesses
+                .iter()
+                .find(|(addr, size)| *addr < end && start < addr.saturating_add(*size))
+                .map(|(addr, _)| std::cmp::max(*addr, start))
+        };
+        for (addr, len, kind) in &self.watchpoints {
+            let end = addr.saturating_add(std::cmp::max(*len, 1));
+            if *kind != WatchKind::Read {
+                if let Some(a) = overlap(writes, *addr, end) {
+                    return Some(VmEvent::WatchWrite(a));
+                }
+            }
+            if *kind != WatchKind::Write {
+                if let Some(a) = overlap(reads, *addr, end) {
+                    return Some(VmEvent::WatchRead(a));
+                }
+            }
+        }
+        None
+    }
+
     fn clear_memory_ops(&mut self) {
         self.memory_writes.clear();
         self.memory_reads.clear();
     }
+
+    // Registers and pc of a vm. A suspended vm is read from its snapshot.
+    fn thread_registers(&self, id: u64) -> Result<([u64; RISCV_GENERAL_REGISTER_NUMBER], u64), Error> {
+        let scheduler = &self.machine.scheduler;
+        if let Some((_, vm)) = scheduler.instantiated.get(&id) {
+            let mut registers = [0; RISCV_GENERAL_REGISTER_NUMBER];
+            registers.copy_from_slice(vm.machine.registers());
+            return Ok((registers, *vm.machine.pc()));
+        }
+        match scheduler.suspended.get(&id) {
+            Some(snapshot) => Ok((snapshot.registers, snapshot.pc)),
+            None => Err(Error::External(format!("Invalid thread id: {}", id + 1))),
+        }
+    }
+
+    fn thread_set_register(&mut self, id: u64, reg_id: RiscvRegId<u64>, value: u64) -> Result<(), Error> {
+        let scheduler = &mut self.machine.scheduler;
+        if let Some((_, vm)) = scheduler.instantiated.get_mut(&id) {
+            match reg_id {
+                RiscvRegId::Pc => {
+                    vm.machine.update_pc(value);
+                    vm.machine.commit_pc();
+                }
+                RiscvRegId::Gpr(idx) => vm.machine.set_register(idx as usize, value),
+                _ => return Err(Error::External(format!("Invalid register id: {:?}", reg_id))),
+            }
+            return Ok(());
+        }
+        let snapshot = scheduler
+            .suspended
+            .get_mut(&id)
+            .ok_or_else(|| Error::External(format!("Invalid thread id: {}", id + 1)))?;
+        match reg_id {
+            RiscvRegId::Pc => snapshot.pc = value,
+            RiscvRegId::Gpr(idx) => snapshot.registers[idx as usize] = value,
+            _ => return Err(Error::External(format!("Invalid register id: {:?}", reg_id))),
+        }
+        Ok(())
+    }
+
+    // Read a byte from the memory of a vm. The memory of a suspended vm is made of the dirty pages in its snapshot and
+    // the pages loaded from the transaction, everything else is zero.
+    fn thread_load8(&mut self, id: u64, addr: u64) -> Result<u8, Error> {
+        let memory_size = self.machine.memory().memory_size() as u64;
+        let scheduler = &mut self.machine.scheduler;
+        if let Some((_, vm)) = scheduler.instantiated.get_mut(&id) {
+            return Ok(vm.machine.memory_mut().load8(&addr)?.to_u8());
+        }
+        let snapshot =
+            scheduler.suspended.get(&id).ok_or_else(|| Error::External(format!("Invalid thread id: {}", id + 1)))?;
+        if addr >= memory_size {
+            return Err(Error::MemOutOfBound);
+        }
+        for (address, _, content) in &snapshot.dirty_pages {
+            if (*address..*address + content.len() as u64).contains(&addr) {
+                return Ok(content[(addr - address) as usize]);
+            }
+        }
+        for (address, _, source, offset, length) in &snapshot.pages_from_source {
+            if (*address..*address + length).contains(&addr) {
+                let data = scheduler
+                    .tx_data
+                    .load_data(source, offset + addr - address, 1)
+                    .ok_or(Error::SnapshotDataLoadError)?
+                    .0;
+                return Ok(data.first().copied().unwrap_or(0));
+            }
+        }
+        Ok(0)
+    }
+
+    fn record_store(&mut self, addr: u64, size: u64) -> Result<(), Error> {
+        self.memory_writes.push((addr, size));
+        if self.delta.is_some() {
+            let data = self.machine.memory_mut().load_bytes(addr, size)?.to_vec();
+            self.delta.as_mut().unwrap().memory.push((addr, data));
+        }
+        Ok(())
+    }
+
+    // Undo a recorded instruction. A delta is always made by the current vm, since vms are only switched by syscalls.
+    fn undo(&mut self, record: HistoryRecord) -> Result<(), Error> {
+        match record {
+            HistoryRecord::Delta(delta) => {
+                for (addr, data) in delta.memory.iter().rev() {
+                    self.machine.memory_mut().store_bytes(*addr, data)?;
+                }
+                for (idx, value) in delta.registers {
+                    self.machine.set_register(idx, value);
+                }
+                self.machine.update_pc(delta.pc);
+                self.machine.commit_pc();
+                self.machine.set_cycles(delta.cycles);
+                self.machine.memory_mut().set_lr(&delta.load_reservation_address);
+            }
+            HistoryRecord::Checkpoint(checkpoint) => {
+                let scheduler = &self.machine.scheduler;
+                let scheduler = Scheduler::new(
+                    scheduler.tx_data.clone(),
+                    scheduler.script_version,
+                    scheduler.syscalls_generator.clone(),
+                );
+                let mut machine = checkpoint.resume(scheduler)?;
+                machine.expand_syscalls = std::mem::take(&mut self.machine.expand_syscalls);
+                machine.all_vms = self.machine.all_vms;
+                machine.set_running(true);
+                self.machine = machine;
+                self.decoder.reset_instructions_cache();
+            }
+        }
+        Ok(())
+    }
+
+    // Watched memory of a vm, used to find out whether a checkpoint changes it.
+    fn watched_memory(&mut self, id: u64) -> Vec<(u64, Option<u8>)> {
+        let mut data = vec![];
+        for (addr, len, kind) in self.watchpoints.clone() {
+            if kind != WatchKind::Read {
+                data.extend((addr..addr + len).map(|e| (e, self.thread_load8(id, e).ok())));
+            }
+        }
+        data
+    }
+
+    // Undo the last recorded instruction, and report the event to stop at when executing backward.
+    fn reverse_step(&mut self) -> Option<VmEvent> {
+        let record = match self.history.pop() {
+            Some(record) => record,
+            None => return Some(VmEvent::ReplayBegin),
+        };
+        let mut event = None;
+        match &record {
+            HistoryRecord::Delta(delta) => {
+                let writes: Vec<(u64, u64)> = delta.memory.iter().map(|(a, d)| (*a, d.len() as u64)).collect();
+                event = self.watch_hit(&writes, &delta.reads);
+                if let Err(e) = self.undo(record) {
+                    return Some(VmEvent::Error(e));
+                }
+            }
+            HistoryRecord::Checkpoint(checkpoint) => {
+                let id = checkpoint.id;
+                let watched = self.watched_memory(id);
+                if let Err(e) = self.undo(record) {
+                    return Some(VmEvent::Error(e));
+                }
+                if let Some((addr, _)) =
+                    watched.iter().zip(self.watched_memory(id)).find(|(a, b)| **a != *b).map(|e| e.1)
+                {
+                    event = Some(VmEvent::WatchWrite(addr));
+                }
+            }
+        }
+        if event.is_none() && self.breakpoint_hit(false) {
+            event = Some(VmEvent::Break);
+        }
+        event
+    }
 }
 
 impl<DL> GdbStubHandler<DL>
@@ -121,6 +379,7 @@ where
     DL: CellDataProvider + HeaderProvider + ExtensionProvider + Send + Sync + Clone + 'static,
 {
     pub fn run_till_exited(mut self) -> Result<(i8, u64), Error> {
+        self.history = History::default();
         while self.machine.running() {
             self.step_inner()?;
         }
@@ -140,21 +399,72 @@ where
             let memory = self.machine.memory_mut();
             self.decoder.decode(memory, pc)?
         };
+        if self.history.enabled() {
+            let opcode = extract_opcode(instruction);
+            if opcode == insts::OP_ECALL || opcode == insts::OP_EBREAK {
+                self.history.push(HistoryRecord::Checkpoint(Box::new(Checkpoint::new(&mut self.machine)?)));
+            } else {
+                self.delta = Some(HistoryDelta {
+                    id: self.machine.id,
+                    pc: *self.machine.pc(),
+                    cycles: self.machine.cycles(),
+                    load_reservation_address: *self.machine.memory().lr(),
+                    registers: self.machine.registers().iter().cloned().enumerate().collect(),
+                    ..Default::default()
+                });
+            }
+        }
+        if let Some(profile) = self.profile.as_mut() {
+            profile.step(&mut self.decoder, &mut self.machine)?;
+        }
         let cycles = estimate_cycles(instruction);
         self.machine.add_cycles(cycles)?;
         self.clear_memory_ops();
-        execute(instruction, self)
+        let result = execute(instruction, self);
+        if let Some(mut delta) = self.delta.take() {
+            let registers = self.machine.registers();
+            delta.registers.retain(|(idx, value)| registers[*idx] != *value);
+            delta.reads = self.memory_reads.clone();
+            self.history.push(HistoryRecord::Delta(delta));
+        }
+        result?;
+        if self.machine.all_vms {
+            let id = self.machine.id;
+            self.machine.switch()?;
+            if self.machine.id != id {
+                self.decoder.reset_instructions_cache();
+            }
+        }
+        Ok(())
     }
 
-    fn step(&mut self) -> Option<VmEvent> {
+    // Execute an instruction, and report the event to stop at if any.
+    pub fn step(&mut self) -> Option<VmEvent> {
         if self.machine.reset_signal() {
-            self.decoder.reset_instructions_cache()
+            self.decoder.reset_instructions_cache();
+            if let Some(profile) = self.profile.as_mut() {
+                if let Err(e) = profile.reset(self.machine.code()) {
+                    return Some(VmEvent::Error(Error::External(e.to_string())));
+                }
+            }
         }
         if !self.machine.running() {
             return Some(VmEvent::Exited(self.machine.exit_code() as u8));
         }
+        let next_vm_id = self.machine.scheduler.next_vm_id;
+        // Syscalls write memory without going through the handler, so the watched memory is compared instead.
+        let watched = match self.next_opcode() {
+            Some(insts::OP_ECALL) if self.watchpoints.iter().any(|e| e.2 != WatchKind::Read) => {
+                Some((self.machine.id, self.watched_memory(self.machine.id)))
+            }
+            _ => None,
+        };
         match self.step_inner() {
             Ok(_) => {
+                // A spawned vm is the next one to run, stop at its first instruction so it can be followed.
+                if self.machine.all_vms && self.machine.scheduler.next_vm_id != next_vm_id {
+                    return Some(VmEvent::Spawn(self.machine.id));
+                }
                 if let Some(opcode) = self.next_opcode() {
                     if opcode == insts::OP_ECALL {
                         let number = self.machine.registers()[A7].clone();
@@ -163,16 +473,17 @@ where
                         }
                     }
                 }
-                if self.breakpoints.contains(self.machine.pc()) {
+                if self.breakpoint_hit(true) {
                     return Some(VmEvent::Break);
                 }
-                if !self.memory_writes.is_empty() {
-                    return Some(VmEvent::WatchWrite(self.memory_writes.pop().unwrap()));
-                }
-                if !self.memory_reads.is_empty() {
-                    return Some(VmEvent::WatchRead(self.memory_reads.pop().unwrap()));
+                if let Some((id, watched)) = watched {
+                    if let Some((addr, _)) =
+                        watched.iter().zip(self.watched_memory(id)).find(|(a, b)| **a != *b).map(|e| e.1)
+                    {
+                        return Some(VmEvent::WatchWrite(addr));
+                    }
                 }
-                None
+                self.watch_hit(&self.memory_writes, &self.memory_reads)
             }
             Err(e) => Some(VmEvent::Error(e)),
         }
@@ -183,21 +494,29 @@ where
             return VmEvent::IncomingData;
         }
         match self.exec_mode.clone() {
-            ExecMode::Step => self.step().unwrap_or(VmEvent::DoneStep),
+            // Other vms may run before the stepped vm is scheduled again.
+            ExecMode::Step(id) => {
+                let mut executed_cycles = 0;
+                loop {
+                    let stepped = self.machine.id == id;
+                    if let Some(event) = self.step() {
+                        break event;
+                    }
+                    if stepped {
+                        break VmEvent::DoneStep(id);
+                    }
+
+                    executed_cycles += 1;
+                    if executed_cycles % 1024 == 0 && poll_incoming_data() {
+                        break VmEvent::IncomingData;
+                    }
+                }
+            }
             ExecMode::Continue => {
                 let mut executed_cycles = 0;
                 loop {
                     if let Some(event) = self.step() {
-                        let mut continue_step = true;
-                        match event {
-                            VmEvent::DoneStep | VmEvent::Exited(_) | VmEvent::Break | VmEvent::Error(_) => {
-                                continue_step = false;
-                            }
-                            _ => {}
-                        };
-                        if !continue_step {
-                            break event;
-                        }
+                        break event;
                     }
 
                     executed_cycles += 1;
@@ -206,15 +525,46 @@ where
                     }
                 }
             }
-            ExecMode::RangeStep(start, end) => {
+            ExecMode::RangeStep(id, start, end) => {
                 let mut executed_cycles = 0;
                 loop {
                     if let Some(event) = self.step() {
                         break event;
                     }
 
-                    if !(start.to_u64()..end.to_u64()).contains(&self.machine.pc().to_u64()) {
-                        break VmEvent::DoneStep;
+                    if self.machine.id == id && !(start.to_u64()..end.to_u64()).contains(&self.machine.pc().to_u64()) {
+                        break VmEvent::DoneStep(id);
+                    }
+
+                    executed_cycles += 1;
+                    if executed_cycles % 1024 == 0 && poll_incoming_data() {
+                        break VmEvent::IncomingData;
+                    }
+                }
+            }
+            ExecMode::ReverseStep(id) => {
+                let mut executed_cycles = 0;
+                loop {
+                    let stepped = self.history.last_id() == Some(id);
+                    if let Some(event) = self.reverse_step() {
+                        break event;
+                    }
+                    // Stop at the spawn syscall if the stepped vm is undone to before it is spawned.
+                    if stepped || !self.machine.scheduler.states.contains_key(&id) {
+                        break VmEvent::DoneStep(self.machine.id);
+                    }
+
+                    executed_cycles += 1;
+                    if executed_cycles % 1024 == 0 && poll_incoming_data() {
+                        break VmEvent::IncomingData;
+                    }
+                }
+            }
+            ExecMode::ReverseContinue => {
+                let mut executed_cycles = 0;
+                loop {
+                    if let Some(event) = self.reverse_step() {
+                        break event;
                     }
 
                     executed_cycles += 1;
@@ -235,7 +585,7 @@ where
     type Error = Error;
 
     fn base_ops(&mut self) -> BaseOps<Self::Arch, Self::Error> {
-        BaseOps::SingleThread(self)
+        BaseOps::MultiThread(self)
     }
 
     fn support_breakpoints(&mut self) -> Option<BreakpointsOps<'_, Self>> {
@@ -245,130 +595,214 @@ where
     fn support_catch_syscalls(&mut self) -> Option<CatchSyscallsOps<'_, Self>> {
         Some(self)
     }
+
+    fn support_monitor_cmd(&mut self) -> Option<MonitorCmdOps<'_, Self>> {
+        Some(self)
+    }
 }
 
-impl<DL> SingleThreadBase for GdbStubHandler<DL>
+impl<DL> MultiThreadBase for GdbStubHandler<DL>
 where
     DL: CellDataProvider + HeaderProvider + ExtensionProvider + Send + Sync + Clone + 'static,
 {
-    fn read_registers(&mut self, regs: &mut <Self::Arch as Arch>::Registers) -> TargetResult<(), Self> {
-        for (i, val) in self.machine.registers().iter().enumerate() {
-            regs.x[i] = val.clone().to_u64();
-        }
-        regs.pc = self.machine.pc().clone().to_u64();
+    fn read_registers(&mut self, regs: &mut <Self::Arch as Arch>::Registers, tid: Tid) -> TargetResult<(), Self> {
+        let (registers, pc) = self.thread_registers(id_of(tid)).map_err(TargetError::Fatal)?;
+        regs.x = registers;
+        regs.pc = pc;
         Ok(())
     }
 
-    fn write_registers(&mut self, regs: &<Self::Arch as Arch>::Registers) -> TargetResult<(), Self> {
-        regs.x.iter().enumerate().for_each(|(i, val)| {
-            self.machine.set_register(i, val.clone());
-        });
-        self.machine.update_pc(regs.pc.clone());
-        self.machine.commit_pc();
-        Ok(())
+    fn write_registers(&mut self, regs: &<Self::Arch as Arch>::Registers, tid: Tid) -> TargetResult<(), Self> {
+        for (i, val) in regs.x.iter().enumerate() {
+            self.thread_set_register(id_of(tid), RiscvRegId::Gpr(i as u8), *val).map_err(TargetError::Fatal)?;
+        }
+        self.thread_set_register(id_of(tid), RiscvRegId::Pc, regs.pc).map_err(TargetError::Fatal)
     }
 
-    fn read_addrs(&mut self, start_addr: <Self::Arch as Arch>::Usize, data: &mut [u8]) -> TargetResult<usize, Self> {
+    fn read_addrs(
+        &mut self,
+        start_addr: <Self::Arch as Arch>::Usize,
+        data: &mut [u8],
+        tid: Tid,
+    ) -> TargetResult<usize, Self> {
         for i in 0..data.len() {
-            data[i] =
-                self.machine.memory_mut().load8(&(start_addr.to_u64() + i as u64)).map_err(TargetError::Fatal)?.to_u8();
+            data[i] = self.thread_load8(id_of(tid), start_addr.to_u64() + i as u64).map_err(TargetError::Fatal)?;
         }
         Ok(data.len())
     }
 
-    fn write_addrs(&mut self, start_addr: <Self::Arch as Arch>::Usize, data: &[u8]) -> TargetResult<(), Self> {
-        self.machine.memory_mut().store_bytes(start_addr.to_u64(), data).map_err(TargetError::Fatal)
+    fn write_addrs(
+        &mut self,
+        start_addr: <Self::Arch as Arch>::Usize,
+        data: &[u8],
+        tid: Tid,
+    ) -> TargetResult<(), Self> {
+        // The memory of a suspended vm is kept in its snapshot and can not be written.
+        match self.machine.scheduler.instantiated.get_mut(&id_of(tid)) {
+            Some((_, vm)) => vm.machine.memory_mut().store_bytes(start_addr.to_u64(), data).map_err(TargetError::Fatal),
+            None => Err(TargetError::NonFatal),
+        }
     }
 
-    fn support_single_register_access(&mut self) -> Option<SingleRegisterAccessOps<'_, (), Self>> {
+    fn list_active_threads(&mut self, thread_is_active: &mut dyn FnMut(Tid)) -> Result<(), Self::Error> {
+        for (id, state) in &self.machine.scheduler.states {
+            if *state != VmState::Terminated {
+                thread_is_active(tid_of(*id));
+            }
+        }
+        Ok(())
+    }
+
+    fn support_single_register_access(&mut self) -> Option<SingleRegisterAccessOps<'_, Tid, Self>> {
+        Some(self)
+    }
+
+    fn support_resume(&mut self) -> Option<MultiThreadResumeOps<'_, Self>> {
         Some(self)
     }
 
-    fn support_resume(&mut self) -> Option<SingleThreadResumeOps<'_, Self>> {
+    fn support_thread_extra_info(&mut self) -> Option<ThreadExtraInfoOps<'_, Self>> {
         Some(self)
     }
 }
 
-impl<DL> SingleRegisterAccess<()> for GdbStubHandler<DL>
+impl<DL> SingleRegisterAccess<Tid> for GdbStubHandler<DL>
 where
     DL: CellDataProvider + HeaderProvider + ExtensionProvider + Send + Sync + Clone + 'static,
 {
     fn read_register(
         &mut self,
-        _tid: (),
+        tid: Tid,
         reg_id: <Self::Arch as Arch>::RegId,
         buf: &mut [u8],
     ) -> TargetResult<usize, Self> {
+        let (registers, pc) = self.thread_registers(id_of(tid)).map_err(TargetError::Fatal)?;
         let value = match reg_id {
-            RiscvRegId::Pc => self.machine.pc(),
-            RiscvRegId::Gpr(idx) => &self.machine.registers()[idx as usize],
+            RiscvRegId::Pc => pc,
+            RiscvRegId::Gpr(idx) => registers[idx as usize],
             _ => return Err(TargetError::Fatal(Error::External(format!("Invalid register id: {:?}", reg_id)))),
         };
-        buf.copy_from_slice(&value.to_u64().to_le_bytes()[0..(u64::BITS as usize / 8)]);
+        buf.copy_from_slice(&value.to_le_bytes()[0..(u64::BITS as usize / 8)]);
         Ok(buf.len())
     }
 
-    fn write_register(&mut self, _tid: (), reg_id: <Self::Arch as Arch>::RegId, val: &[u8]) -> TargetResult<(), Self> {
+    fn write_register(&mut self, tid: Tid, reg_id: <Self::Arch as Arch>::RegId, val: &[u8]) -> TargetResult<(), Self> {
         let mut u64_buf = [0u8; 8];
         u64_buf[0..val.len()].copy_from_slice(val);
         let v = u64::from_le_bytes(u64_buf);
-        match reg_id {
-            RiscvRegId::Pc => {
-                self.machine.update_pc(v);
-                self.machine.commit_pc();
-            }
-            RiscvRegId::Gpr(idx) => {
-                self.machine.set_register(idx as usize, v);
-            }
-            _ => return Err(TargetError::Fatal(Error::External(format!("Invalid register id: {:?}", reg_id)))),
-        };
+        self.thread_set_register(id_of(tid), reg_id, v).map_err(TargetError::Fatal)
+    }
+}
 
-        Ok(())
+impl<DL> ThreadExtraInfo for GdbStubHandler<DL>
+where
+    DL: CellDataProvider + HeaderProvider + ExtensionProvider + Send + Sync + Clone + 'static,
+{
+    fn thread_extra_info(&self, tid: Tid, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let id = id_of(tid);
+        let state = match self.machine.scheduler.states.get(&id) {
+            Some(VmState::Runnable) if self.machine.id == id => String::from("running"),
+            Some(VmState::Runnable) => String::from("runnable"),
+            Some(VmState::Terminated) => String::from("terminated"),
+            Some(VmState::Wait { target_vm_id, .. }) => format!("waiting for pid {}", target_vm_id),
+            Some(VmState::WaitForWrite(_)) => String::from("waiting for write"),
+            Some(VmState::WaitForRead(_)) => String::from("waiting for read"),
+            None => String::from("unknown"),
+        };
+        let info = format!("pid {}, {}", id, state);
+        let size = std::cmp::min(info.len(), buf.len());
+        buf[..size].copy_from_slice(&info.as_bytes()[..size]);
+        Ok(size)
     }
 }
 
 // This is only for setting execution modes, the actual execution shall live within
 // BlockingEventLoop trait impl.
-impl<DL> SingleThreadResume for GdbStubHandler<DL>
+impl<DL> MultiThreadResume for GdbStubHandler<DL>
 where
     DL: CellDataProvider + HeaderProvider + ExtensionProvider + Send + Sync + Clone + 'static,
 {
-    fn resume(&mut self, signal: Option<Signal>) -> Result<(), Self::Error> {
+    fn resume(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    // Vms are scheduled by the scheduler, so all threads continue unless one of them is stepped.
+    fn clear_resume_actions(&mut self) -> Result<(), Self::Error> {
+        self.exec_mode = ExecMode::Continue;
+        Ok(())
+    }
+
+    fn set_resume_action_continue(&mut self, _tid: Tid, signal: Option<Signal>) -> Result<(), Self::Error> {
         if signal.is_some() {
             return Err(Error::External("no support for continuing with signal".to_string()));
         }
-        self.exec_mode = ExecMode::Continue;
         Ok(())
     }
 
-    fn support_single_step(&mut self) -> Option<SingleThreadSingleStepOps<'_, Self>> {
+    fn support_single_step(&mut self) -> Option<MultiThreadSingleStepOps<'_, Self>> {
         Some(self)
     }
 
-    fn support_range_step(&mut self) -> Option<SingleThreadRangeSteppingOps<'_, Self>> {
+    fn support_range_step(&mut self) -> Option<MultiThreadRangeSteppingOps<'_, Self>> {
         Some(self)
     }
+
+    fn support_reverse_step(&mut self) -> Option<ReverseStepOps<'_, Tid, Self>> {
+        if self.history.enabled() {
+            Some(self)
+        } else {
+            None
+        }
+    }
+
+    fn support_reverse_cont(&mut self) -> Option<ReverseContOps<'_, Tid, Self>> {
+        if self.history.enabled() {
+            Some(self)
+        } else {
+            None
+        }
+    }
+}
+
+impl<DL> ReverseStep<Tid> for GdbStubHandler<DL>
+where
+    DL: CellDataProvider + HeaderProvider + ExtensionProvider + Send + Sync + Clone + 'static,
+{
+    fn reverse_step(&mut self, tid: Tid) -> Result<(), Self::Error> {
+        self.exec_mode = ExecMode::ReverseStep(id_of(tid));
+        Ok(())
+    }
+}
+
+impl<DL> ReverseCont<Tid> for GdbStubHandler<DL>
+where
+    DL: CellDataProvider + HeaderProvider + ExtensionProvider + Send + Sync + Clone + 'static,
+{
+    fn reverse_cont(&mut self) -> Result<(), Self::Error> {
+        self.exec_mode = ExecMode::ReverseContinue;
+        Ok(())
+    }
 }
 
-impl<DL> SingleThreadRangeStepping for GdbStubHandler<DL>
+impl<DL> MultiThreadRangeStepping for GdbStubHandler<DL>
 where
     DL: CellDataProvider + HeaderProvider + ExtensionProvider + Send + Sync + Clone + 'static,
 {
-    fn resume_range_step(&mut self, start: u64, end: u64) -> Result<(), Self::Error> {
-        self.exec_mode = ExecMode::RangeStep(start, end);
+    fn set_resume_action_range_step(&mut self, tid: Tid, start: u64, end: u64) -> Result<(), Self::Error> {
+        self.exec_mode = ExecMode::RangeStep(id_of(tid), start, end);
         Ok(())
     }
 }
 
-impl<DL> SingleThreadSingleStep for GdbStubHandler<DL>
+impl<DL> MultiThreadSingleStep for GdbStubHandler<DL>
 where
     DL: CellDataProvider + HeaderProvider + ExtensionProvider + Send + Sync + Clone + 'static,
 {
-    fn step(&mut self, signal: Option<Signal>) -> Result<(), Self::Error> {
+    fn set_resume_action_step(&mut self, tid: Tid, signal: Option<Signal>) -> Result<(), Self::Error> {
         if signal.is_some() {
             return Err(Error::External("no support for stepping with signal".to_string()));
         }
-        self.exec_mode = ExecMode::Step;
+        self.exec_mode = ExecMode::Step(id_of(tid));
         Ok(())
     }
 }
@@ -395,7 +829,9 @@ where
         addr: <Self::Arch as Arch>::Usize,
         _kind: <Self::Arch as Arch>::BreakpointKind,
     ) -> TargetResult<bool, Self> {
-        self.breakpoints.push(addr);
+        // Gdb inserts the breakpoint again when its conditions are changed.
+        let conditions = self.conditions.lock().unwrap().remove(&addr).unwrap_or_default();
+        self.breakpoints.entry(addr).or_default().conditions = conditions;
         Ok(true)
     }
 
@@ -404,12 +840,7 @@ where
         addr: <Self::Arch as Arch>::Usize,
         _kind: <Self::Arch as Arch>::BreakpointKind,
     ) -> TargetResult<bool, Self> {
-        match self.breakpoints.iter().position(|x| *x == addr) {
-            None => return Ok(false),
-            Some(pos) => self.breakpoints.remove(pos),
-        };
-
-        Ok(true)
+        Ok(self.breakpoints.remove(&addr).is_some())
     }
 }
 
@@ -420,28 +851,68 @@ where
     fn add_hw_watchpoint(
         &mut self,
         addr: <Self::Arch as Arch>::Usize,
-        _len: <Self::Arch as Arch>::Usize,
+        len: <Self::Arch as Arch>::Usize,
         kind: WatchKind,
     ) -> TargetResult<bool, Self> {
-        self.watchpoints.push((addr, kind));
+        self.watchpoints.push((addr, len, kind));
         Ok(true)
     }
 
     fn remove_hw_watchpoint(
         &mut self,
         addr: <Self::Arch as Arch>::Usize,
-        _len: <Self::Arch as Arch>::Usize,
+        len: <Self::Arch as Arch>::Usize,
         kind: WatchKind,
     ) -> TargetResult<bool, Self> {
-        match self.watchpoints.iter().position(|(a, k)| *a == addr && *k == kind) {
+        match self.watchpoints.iter().position(|(a, l, k)| *a == addr && *l == len && *k == kind) {
             None => return Ok(false),
-            Some(pos) => self.breakpoints.remove(pos),
+            Some(pos) => self.watchpoints.remove(pos),
         };
 
         Ok(true)
     }
 }
 
+impl<DL> MonitorCmd for GdbStubHandler<DL>
+where
+    DL: CellDataProvider + HeaderProvider + ExtensionProvider + Send + Sync + Clone + 'static,
+{
+    fn handle_monitor_cmd(&mut self, cmd: &[u8], mut out: ConsoleOutput<'_>) -> Result<(), Self::Error> {
+        let cmd = String::from_utf8_lossy(cmd);
+        let args: Vec<&str> = cmd.split_whitespace().collect();
+        match args.as_slice() {
+            ["breakpoints"] => {
+                for (addr, breakpoint) in &self.breakpoints {
+                    outputln!(
+                        out,
+                        "{:#x}: hits {}, ignore {}, conditions {}",
+                        addr,
+                        breakpoint.hits,
+                        breakpoint.ignore,
+                        breakpoint.conditions.len()
+                    );
+                }
+            }
+            ["ignore", addr, count] => {
+                let addr = u64::from_str_radix(addr.trim_start_matches("0x"), 16).ok();
+                let count = count.parse::<u64>().ok();
+                match (addr.and_then(|e| self.breakpoints.get_mut(&e)), count) {
+                    (Some(breakpoint), Some(count)) => {
+                        breakpoint.ignore = count;
+                        outputln!(out, "Will ignore next {} hits", count);
+                    }
+                    _ => outputln!(out, "No breakpoint at {}", args[1]),
+                }
+            }
+            _ => {
+                outputln!(out, "monitor breakpoints            list breakpoints with their hit counts");
+                outputln!(out, "monitor ignore <addr> <count>  skip the next count hits of the breakpoint at addr");
+            }
+        }
+        Ok(())
+    }
+}
+
 impl<DL> CatchSyscalls for GdbStubHandler<DL>
 where
     DL: CellDataProvider + HeaderProvider + ExtensionProvider + Send + Sync + Clone + 'static,
@@ -477,12 +948,12 @@ where
 {
     type Target = GdbStubHandler<DL>;
     type Connection = Box<dyn ConnectionExt<Error = std::io::Error>>;
-    type StopReason = SingleThreadStopReason<u64>;
+    type StopReason = MultiThreadStopReason<u64>;
 
     fn on_interrupt(
-        _target: &mut Self::Target,
-    ) -> Result<Option<SingleThreadStopReason<u64>>, <GdbStubHandler<DL> as Target>::Error> {
-        Ok(Some(SingleThreadStopReason::Signal(Signal::SIGINT)))
+        target: &mut Self::Target,
+    ) -> Result<Option<MultiThreadStopReason<u64>>, <GdbStubHandler<DL> as Target>::Error> {
+        Ok(Some(MultiThreadStopReason::SignalWithThread { tid: tid_of(target.machine.id), signal: Signal::SIGINT }))
     }
 
     #[allow(clippy::type_complexity)]
@@ -490,34 +961,43 @@ where
         target: &mut Self::Target,
         conn: &mut Self::Connection,
     ) -> Result<
-        Event<SingleThreadStopReason<u64>>,
+        Event<MultiThreadStopReason<u64>>,
         WaitForStopReasonError<<Self::Target as Target>::Error, <Self::Connection as Connection>::Error>,
     > {
         let poll_incoming_data = || conn.peek().map(|b| b.is_some()).unwrap_or(true);
 
-        Ok(match target.execute(poll_incoming_data) {
+        let event = target.execute(poll_incoming_data);
+        let tid = tid_of(target.machine.id);
+        Ok(match event {
             VmEvent::IncomingData => {
                 let byte = conn.read().map_err(WaitForStopReasonError::Connection)?;
                 Event::IncomingData(byte)
             }
-            VmEvent::DoneStep => Event::TargetStopped(SingleThreadStopReason::DoneStep),
-            VmEvent::Exited(code) => Event::TargetStopped(SingleThreadStopReason::Exited(code)),
-            VmEvent::Break => Event::TargetStopped(SingleThreadStopReason::SwBreak(())),
-            VmEvent::WatchRead(addr) => Event::TargetStopped(SingleThreadStopReason::Watch {
-                tid: (),
-                kind: WatchKind::Read,
-                addr: addr.to_u64(),
+            // Report the stepped thread, since the current one may have been switched.
+            VmEvent::DoneStep(id) => Event::TargetStopped(MultiThreadStopReason::SignalWithThread {
+                tid: tid_of(id),
+                signal: Signal::SIGTRAP,
             }),
-            VmEvent::WatchWrite(addr) => Event::TargetStopped(SingleThreadStopReason::Watch {
-                tid: (),
-                kind: WatchKind::Write,
-                addr: addr.to_u64(),
+            VmEvent::Exited(code) => Event::TargetStopped(MultiThreadStopReason::Exited(code)),
+            VmEvent::Spawn(id) => Event::TargetStopped(MultiThreadStopReason::SignalWithThread {
+                tid: tid_of(id),
+                signal: Signal::SIGTRAP,
             }),
-            VmEvent::CatchSyscall(number) => Event::TargetStopped(SingleThreadStopReason::CatchSyscall {
-                tid: None,
+            VmEvent::Break => Event::TargetStopped(MultiThreadStopReason::SwBreak(tid)),
+            VmEvent::WatchRead(addr) => {
+                Event::TargetStopped(MultiThreadStopReason::Watch { tid, kind: WatchKind::Read, addr: addr.to_u64() })
+            }
+            VmEvent::WatchWrite(addr) => {
+                Event::TargetStopped(MultiThreadStopReason::Watch { tid, kind: WatchKind::Write, addr: addr.to_u64() })
+            }
+            VmEvent::CatchSyscall(number) => Event::TargetStopped(MultiThreadStopReason::CatchSyscall {
+                tid: Some(tid),
                 number: number.to_u64(),
                 position: CatchSyscallPosition::Entry,
             }),
+            VmEvent::ReplayBegin => {
+                Event::TargetStopped(MultiThreadStopReason::ReplayLog { tid: Some(tid), pos: ReplayLogPosition::Begin })
+            }
             VmEvent::Error(e) => return Err(WaitForStopReasonError::Target(e)),
         })
     }
@@ -577,10 +1057,12 @@ where
     }
 
     fn store_byte(&mut self, addr: u64, size: u64, value: u8) -> Result<(), Error> {
+        self.record_store(addr, size)?;
         self.machine.memory_mut().store_byte(addr, size, value)
     }
 
     fn store_bytes(&mut self, addr: u64, value: &[u8]) -> Result<(), Error> {
+        self.record_store(addr, value.len() as u64)?;
         self.machine.memory_mut().store_bytes(addr, value)
     }
 
@@ -594,50 +1076,46 @@ where
 
     fn load8(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
         let result = self.machine.memory_mut().load8(addr)?;
-        self.memory_reads.push(addr.clone());
+        self.memory_reads.push((*addr, 1));
         Ok(result)
     }
 
     fn load16(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
         let result = self.machine.memory_mut().load16(addr)?;
-        self.memory_reads.push(addr.clone());
+        self.memory_reads.push((*addr, 2));
         Ok(result)
     }
 
     fn load32(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
         let result = self.machine.memory_mut().load32(addr)?;
-        self.memory_reads.push(addr.clone());
+        self.memory_reads.push((*addr, 4));
         Ok(result)
     }
 
     fn load64(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
         let result = self.machine.memory_mut().load64(addr)?;
-        self.memory_reads.push(addr.clone());
+        self.memory_reads.push((*addr, 8));
         Ok(result)
     }
 
     fn store8(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
-        self.machine.memory_mut().store8(addr, value)?;
-        self.memory_writes.push(addr.clone());
-        Ok(())
+        self.record_store(*addr, 1)?;
+        self.machine.memory_mut().store8(addr, value)
     }
 
     fn store16(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
-        self.machine.memory_mut().store16(addr, value)?;
-        self.memory_writes.push(addr.clone());
-        Ok(())
+        self.record_store(*addr, 2)?;
+        self.machine.memory_mut().store16(addr, value)
     }
 
     fn store32(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
-        self.machine.memory_mut().store32(addr, value)?;
-        self.memory_writes.push(addr.clone());
-        Ok(())
+        self.record_store(*addr, 4)?;
+        self.machine.memory_mut().store32(addr, value)
     }
 
     fn store64(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
-        self.machine.memory_mut().store64(addr, value)?;
-        self.memory_writes.push(addr.clone());
-        Ok(())
+        self.record_store(*addr, 8)?;
+        self.machine.memory_mut().store64(addr, value)
     }
 }
 
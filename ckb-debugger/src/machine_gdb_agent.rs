@@ -0,0 +1,280 @@
+use ckb_vm::{CoreMachine, Error, Memory};
+use gdbstub::conn::{Connection, ConnectionExt};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+// Breakpoint conditions sent by gdb, indexed by address. Each condition is the bytecode of an agent expression.
+pub type BreakpointConditions = Arc<Mutex<BTreeMap<u64, Vec<Vec<u8>>>>>;
+
+fn operand(code: &[u8], pc: usize, size: usize) -> Result<u64, Error> {
+    let data = code.get(pc..pc + size).ok_or_else(|| Error::External(String::from("Truncated agent expression")))?;
+    Ok(data.iter().fold(0, |acc, e| (acc << 8) | *e as u64))
+}
+
+fn pop(stack: &mut Vec<u64>) -> Result<u64, Error> {
+    stack.pop().ok_or_else(|| Error::External(String::from("Agent expression stack underflow")))
+}
+
+fn sign_extend(value: u64, bits: u64) -> u64 {
+    if bits == 0 || bits >= 64 {
+        return value;
+    }
+    let shift = 64 - bits;
+    (((value << shift) as i64) >> shift) as u64
+}
+
+fn zero_extend(value: u64, bits: u64) -> u64 {
+    if bits >= 64 {
+        return value;
+    }
+    value & ((1 << bits) - 1)
+}
+
+// Evaluate an agent expression, see https://sourceware.org/gdb/current/onlinedocs/gdb.html/Bytecode-Descriptions.html.
+// Registers 0 to 31 are the general registers and 32 is the pc. Floating point, trace state variables and printf are
+// not supported, and tracing bytecodes are ignored.
+pub fn eval_agent_expr<M: CoreMachine<REG = u64>>(code: &[u8], machine: &mut M) -> Result<u64, Error> {
+    let mut stack: Vec<u64> = vec![];
+    let mut pc = 0;
+    // Gotos may loop forever.
+    for _ in 0..0x10000 {
+        let op = *code.get(pc).ok_or_else(|| Error::External(String::from("Agent expression without end")))?;
+        pc += 1;
+        match op {
+            0x02..=0x0b | 0x0f..=0x11 | 0x13..=0x15 => {
+                let b = pop(&mut stack)?;
+                let a = pop(&mut stack)?;
+                let r = match op {
+                    0x02 => a.wrapping_add(b),
+                    0x03 => a.wrapping_sub(b),
+                    0x04 => a.wrapping_mul(b),
+                    0x05..=0x08 if b == 0 => return Err(Error::External(String::from("Division by zero"))),
+                    0x05 => (a as i64).wrapping_div(b as i64) as u64,
+                    0x06 => a / b,
+                    0x07 => (a as i64).wrapping_rem(b as i64) as u64,
+                    0x08 => a % b,
+                    0x09 => a.checked_shl(b as u32).unwrap_or(0),
+                    0x0a => (a as i64).checked_shr(b as u32).unwrap_or(if (a as i64) < 0 { -1 } else { 0 }) as u64,
+                    0x0b => a.checked_shr(b as u32).unwrap_or(0),
+                    0x0f => a & b,
+                    0x10 => a | b,
+                    0x11 => a ^ b,
+                    0x13 => (a == b) as u64,
+                    0x14 => ((a as i64) < (b as i64)) as u64,
+                    _ => (a < b) as u64,
+                };
+                stack.push(r);
+            }
+            // trace, tracenz
+            0x0c | 0x2f => {
+                pop(&mut stack)?;
+                pop(&mut stack)?;
+            }
+            // trace_quick
+            0x0d => pc += 1,
+            // tracev, trace16
+            0x2e | 0x30 => pc += 2,
+            0x0e => {
+                let a = pop(&mut stack)?;
+                stack.push((a == 0) as u64);
+            }
+            0x12 => {
+                let a = pop(&mut stack)?;
+                stack.push(!a);
+            }
+            0x16 | 0x2a => {
+                let bits = operand(code, pc, 1)?;
+                pc += 1;
+                let a = pop(&mut stack)?;
+                stack.push(if op == 0x16 { sign_extend(a, bits) } else { zero_extend(a, bits) });
+            }
+            0x17..=0x1a => {
+                let addr = pop(&mut stack)?;
+                let memory = machine.memory_mut();
+                let value = match op {
+                    0x17 => memory.load8(&addr)?,
+                    0x18 => memory.load16(&addr)?,
+                    0x19 => memory.load32(&addr)?,
+                    _ => memory.load64(&addr)?,
+                };
+                stack.push(value);
+            }
+            0x20 => {
+                let target = operand(code, pc, 2)?;
+                pc += 2;
+                if pop(&mut stack)? != 0 {
+                    pc = target as usize;
+                }
+            }
+            0x21 => pc = operand(code, pc, 2)? as usize,
+            0x22..=0x25 => {
+                let size = 1 << (op - 0x22);
+                stack.push(operand(code, pc, size)?);
+                pc += size;
+            }
+            0x26 => {
+                let reg = operand(code, pc, 2)? as usize;
+                pc += 2;
+                let value = match reg {
+                    0..=31 => machine.registers()[reg],
+                    32 => *machine.pc(),
+                    _ => return Err(Error::External(format!("Invalid register {} in agent expression", reg))),
+                };
+                stack.push(value);
+            }
+            0x27 => return pop(&mut stack),
+            0x28 => {
+                let a = pop(&mut stack)?;
+                stack.push(a);
+                stack.push(a);
+            }
+            0x29 => {
+                pop(&mut stack)?;
+            }
+            0x2b => {
+                let b = pop(&mut stack)?;
+                let a = pop(&mut stack)?;
+                stack.push(b);
+                stack.push(a);
+            }
+            0x32 => {
+                let n = operand(code, pc, 1)? as usize;
+                pc += 1;
+                let value = *stack
+                    .iter()
+                    .rev()
+                    .nth(n)
+                    .ok_or_else(|| Error::External(String::from("Agent expression stack underflow")))?;
+                stack.push(value);
+            }
+            0x33 => {
+                let c = pop(&mut stack)?;
+                let b = pop(&mut stack)?;
+                let a = pop(&mut stack)?;
+                stack.extend([c, a, b]);
+            }
+            _ => return Err(Error::External(format!("Unsupported agent expression bytecode {:#04x}", op))),
+        }
+    }
+    Err(Error::External(String::from("Agent expression runs too long")))
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |acc, e| acc.wrapping_add(*e))
+}
+
+// Parse the condition list of a breakpoint packet, in the form of X<len>,<bytecode>;X<len>,<bytecode>.
+pub fn parse_conditions(data: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let mut conditions = vec![];
+    for e in data.split(|c| *c == b';') {
+        let e = std::str::from_utf8(e.strip_prefix(b"X")?).ok()?;
+        let (len, code) = e.split_once(',')?;
+        let code = hex::decode(code).ok()?;
+        if usize::from_str_radix(len, 16).ok()? != code.len() {
+            return None;
+        }
+        conditions.push(code);
+    }
+    Some(conditions)
+}
+
+// Gdbstub does not handle agent expressions. This connection tells gdb that conditional breakpoints are supported, and
+// takes the conditions out of the breakpoint packets before they reach gdbstub, so the handler can evaluate them.
+pub struct GdbConnection {
+    inner: Box<dyn ConnectionExt<Error = std::io::Error>>,
+    conditions: BreakpointConditions,
+    incoming: VecDeque<u8>,
+    outgoing: Vec<u8>,
+}
+
+impl GdbConnection {
+    pub fn new(inner: Box<dyn ConnectionExt<Error = std::io::Error>>, conditions: BreakpointConditions) -> Self {
+        Self { inner, conditions, incoming: VecDeque::new(), outgoing: vec![] }
+    }
+
+    fn packet(&mut self) -> Result<Vec<u8>, std::io::Error> {
+        let mut body = vec![];
+        loop {
+            match self.inner.read()? {
+                b'#' => break,
+                e => body.push(e),
+            }
+        }
+        // The checksum is recomputed once the packet is rewritten.
+        self.inner.read()?;
+        self.inner.read()?;
+        Ok(body)
+    }
+
+    fn rewrite_incoming(&mut self, body: Vec<u8>) -> Vec<u8> {
+        if !(body.starts_with(b"Z0,") || body.starts_with(b"Z1,")) {
+            return body;
+        }
+        let (base, conditions) = match body.iter().position(|e| *e == b';') {
+            Some(i) => (body[..i].to_vec(), parse_conditions(&body[i + 1..]).unwrap_or_default()),
+            None => (body, vec![]),
+        };
+        let addr =
+            base.split(|e| *e == b',').nth(1).and_then(|e| u64::from_str_radix(std::str::from_utf8(e).ok()?, 16).ok());
+        if let Some(addr) = addr {
+            self.conditions.lock().unwrap().insert(addr, conditions);
+        }
+        base
+    }
+}
+
+impl Connection for GdbConnection {
+    type Error = std::io::Error;
+
+    fn write(&mut self, byte: u8) -> Result<(), Self::Error> {
+        if self.outgoing.is_empty() && byte != b'$' {
+            return self.inner.write(byte);
+        }
+        self.outgoing.push(byte);
+        let len = self.outgoing.len();
+        if len < 3 || self.outgoing[len - 3] != b'#' {
+            return Ok(());
+        }
+        let mut body = self.outgoing[1..len - 3].to_vec();
+        self.outgoing.clear();
+        if body.starts_with(b"PacketSize=") {
+            body.extend_from_slice(b";ConditionalBreakpoints+");
+        }
+        self.inner.write(b'$')?;
+        self.inner.write_all(&body)?;
+        self.inner.write_all(format!("#{:02x}", checksum(&body)).as_bytes())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.inner.flush()
+    }
+
+    fn on_session_start(&mut self) -> Result<(), Self::Error> {
+        self.inner.on_session_start()
+    }
+}
+
+impl ConnectionExt for GdbConnection {
+    fn read(&mut self) -> Result<u8, Self::Error> {
+        if let Some(byte) = self.incoming.pop_front() {
+            return Ok(byte);
+        }
+        let byte = self.inner.read()?;
+        if byte != b'$' {
+            return Ok(byte);
+        }
+        let body = self.packet()?;
+        let body = self.rewrite_incoming(body);
+        self.incoming.extend(body.iter());
+        self.incoming.push_back(b'#');
+        self.incoming.extend(format!("{:02x}", checksum(&body)).bytes());
+        Ok(b'$')
+    }
+
+    fn peek(&mut self) -> Result<Option<u8>, Self::Error> {
+        match self.incoming.front() {
+            Some(byte) => Ok(Some(*byte)),
+            None => self.inner.peek(),
+        }
+    }
+}
@@ -0,0 +1,202 @@
+use crate::machine_assign::MachineAssign;
+use crate::snapshot::{resume_machine, vm_snapshot};
+use ckb_script::{DataPieceId, Scheduler, VmState};
+use ckb_traits::{CellDataProvider, ExtensionProvider, HeaderProvider};
+use ckb_vm::snapshot2::Snapshot2;
+use ckb_vm::Error;
+use std::any::Any;
+use std::collections::{BTreeMap, VecDeque};
+
+// The changes made by a single instruction, enough to undo it. Registers and memory hold the values before the
+// instruction is executed.
+#[derive(Clone, Debug, Default)]
+pub struct HistoryDelta {
+    pub id: u64,
+    pub pc: u64,
+    pub cycles: u64,
+    pub load_reservation_address: u64,
+    pub registers: Vec<(usize, u64)>,
+    pub memory: Vec<(u64, Vec<u8>)>,
+    // Address and size of the memory read.
+    pub reads: Vec<(u64, u64)>,
+}
+
+impl HistoryDelta {
+    fn size(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.registers.len() * std::mem::size_of::<(usize, u64)>()
+            + self.memory.iter().map(|e| std::mem::size_of::<(u64, Vec<u8>)>() + e.1.len()).sum::<usize>()
+            + self.reads.len() * std::mem::size_of::<(u64, u64)>()
+    }
+}
+
+// Put back a value of ckb-script kept as any, in place of a value of the same type. Its type, such as the one of fds,
+// is not exported.
+fn restore<T: Clone + 'static>(target: &mut T, value: &dyn Any) -> Result<(), Error> {
+    *target = value.downcast_ref::<T>().ok_or_else(|| Error::Unexpected(String::from("Invalid checkpoint")))?.clone();
+    Ok(())
+}
+
+// The whole debug session, kept in memory. Unlike a snapshot, vms blocked on pipes and open fds are saved as well,
+// since the states of the scheduler are cloned as they are.
+#[derive(Debug)]
+pub struct Checkpoint {
+    // Id of the debugged vm.
+    pub id: u64,
+    pub expand_cycles: u64,
+    pub vm_cycles: BTreeMap<u64, u64>,
+    pub total_cycles: u64,
+    pub current_iteration_cycles: u64,
+    pub next_vm_id: u64,
+    pub next_fd_slot: u64,
+    pub vms: Vec<(u64, VmState, Snapshot2<DataPieceId>)>,
+    fds: Box<dyn Any>,
+    inherited_fd: Box<dyn Any>,
+    pub terminated_vms: Vec<(u64, i8)>,
+    pub instantiated_ids: Vec<u64>,
+}
+
+impl Checkpoint {
+    pub fn new<DL>(machine: &mut MachineAssign<DL>) -> Result<Self, Error>
+    where
+        DL: CellDataProvider + HeaderProvider + ExtensionProvider + Send + Sync + Clone + 'static,
+    {
+        let scheduler = &mut machine.scheduler;
+        let mut vms = vec![];
+        for (id, state) in scheduler.states.clone() {
+            vms.push((id, state, vm_snapshot(scheduler, id)?));
+        }
+        Ok(Self {
+            id: machine.id,
+            expand_cycles: machine.expand_cycles,
+            vm_cycles: machine.vm_cycles.clone(),
+            total_cycles: scheduler.consumed_cycles(),
+            current_iteration_cycles: scheduler.current_iteration_cycles,
+            next_vm_id: scheduler.next_vm_id,
+            next_fd_slot: scheduler.next_fd_slot,
+            vms,
+            fds: Box::new(scheduler.fds.clone().into_iter().collect::<Vec<_>>()),
+            inherited_fd: Box::new(scheduler.inherited_fd.clone().into_iter().collect::<Vec<_>>()),
+            terminated_vms: scheduler.terminated_vms.iter().map(|(id, code)| (*id, *code)).collect(),
+            instantiated_ids: scheduler.instantiated.keys().cloned().collect(),
+        })
+    }
+
+    // Rebuild the debug session on a fresh scheduler of the same transaction and script group.
+    pub fn resume<DL>(&self, scheduler: Scheduler<DL>) -> Result<MachineAssign<DL>, Error>
+    where
+        DL: CellDataProvider + HeaderProvider + ExtensionProvider + Send + Sync + Clone + 'static,
+    {
+        let tx_data = scheduler.tx_data.clone();
+        let script_version = scheduler.script_version;
+        let syscalls_generator = scheduler.syscalls_generator.clone();
+        let mut full = scheduler.suspend()?;
+        full.total_cycles = self.total_cycles;
+        full.next_vm_id = self.next_vm_id;
+        full.next_fd_slot = self.next_fd_slot;
+        full.vms = self.vms.clone();
+        restore(&mut full.fds, self.fds.as_ref())?;
+        restore(&mut full.inherited_fd, self.inherited_fd.as_ref())?;
+        full.terminated_vms = self.terminated_vms.clone();
+        full.instantiated_ids = self.instantiated_ids.clone();
+        let scheduler = Scheduler::resume(tx_data, script_version, syscalls_generator, full);
+        resume_machine(scheduler, self.id, self.expand_cycles, self.vm_cycles.clone(), self.current_iteration_cycles)
+    }
+
+    fn size(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self
+                .vms
+                .iter()
+                .map(|(_, _, snapshot)| {
+                    std::mem::size_of::<(u64, VmState, Snapshot2<DataPieceId>)>()
+                        + snapshot.pages_from_source.len() * std::mem::size_of::<(u64, u8, DataPieceId, u64, u64)>()
+                        + snapshot
+                            .dirty_pages
+                            .iter()
+                            .map(|e| std::mem::size_of::<(u64, u8, Vec<u8>)>() + e.2.len())
+                            .sum::<usize>()
+                })
+                .sum::<usize>()
+    }
+}
+
+#[derive(Debug)]
+pub enum HistoryRecord {
+    // A plain instruction, which only changes the registers and memory of the vm executing it.
+    Delta(HistoryDelta),
+    // Syscalls may change everything in the scheduler, so the whole session is saved before executing them.
+    Checkpoint(Box<Checkpoint>),
+}
+
+impl HistoryRecord {
+    // Id of the vm executing the recorded instruction.
+    pub fn id(&self) -> u64 {
+        match self {
+            HistoryRecord::Delta(delta) => delta.id,
+            HistoryRecord::Checkpoint(checkpoint) => checkpoint.id,
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            HistoryRecord::Delta(delta) => delta.size(),
+            HistoryRecord::Checkpoint(checkpoint) => checkpoint.size(),
+        }
+    }
+}
+
+// Recorded execution of a debug session, newest last. Records are dropped from the oldest once their total size
+// exceeds the window, so the history covers the latest instructions only.
+#[derive(Debug, Default)]
+pub struct History {
+    pub window: usize,
+    size: usize,
+    records: VecDeque<(HistoryRecord, usize)>,
+}
+
+impl History {
+    pub fn new(window: usize) -> Self {
+        Self { window, size: 0, records: VecDeque::new() }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.window != 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub fn push(&mut self, record: HistoryRecord) {
+        let size = record.size();
+        self.size += size;
+        self.records.push_back((record, size));
+        while self.size > self.window {
+            match self.records.pop_front() {
+                Some((_, size)) => self.size -= size,
+                None => break,
+            }
+        }
+    }
+
+    // Id of the vm executing the last recorded instruction.
+    pub fn last_id(&self) -> Option<u64> {
+        self.records.back().map(|e| e.0.id())
+    }
+
+    pub fn pop(&mut self) -> Option<HistoryRecord> {
+        let (record, size) = self.records.pop_back()?;
+        self.size -= size;
+        Some(record)
+    }
+
+    pub fn clear(&mut self) {
+        self.size = 0;
+        self.records.clear();
+    }
+}
@@ -0,0 +1,317 @@
+use crate::machine_analyzer::{MachineProfile, SourceLines};
+use crate::machine_gdb::{GdbStubHandler, VmEvent};
+use ckb_traits::{CellDataProvider, ExtensionProvider, HeaderProvider};
+use ckb_vm::decoder::{build_decoder, Decoder};
+use ckb_vm::instructions::tagged::TaggedInstruction;
+use ckb_vm::instructions::{extract_opcode, instruction_length, instruction_opcode_name};
+use ckb_vm::registers::REGISTER_ABI_NAMES;
+use ckb_vm::{Bytes, CoreMachine, Memory, SupportMachine};
+use gdbstub::target::ext::breakpoints::SwBreakpoint;
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+
+const HELP: &str = "\
+break <symbol|file:line|*addr>  Set a breakpoint, alias b
+delete [addr]                   Delete a breakpoint, or all of them, alias d
+info breakpoints                List breakpoints
+continue                        Continue running until a breakpoint is hit, alias c
+step                            Step to the next source line, entering calls, alias s
+next                            Step to the next source line, over calls, alias n
+finish                          Run until the current function returns
+stepi                           Step an instruction, alias si
+registers                       Print registers, alias info registers
+x <addr|reg> [len]              Examine memory, 64 bytes by default
+backtrace                       Print the call stack, alias bt
+disassemble [addr|reg] [count]  Disassemble instructions, from the pc by default, alias disas
+cycles                          Print consumed cycles
+quit                            Exit the debugger, alias q";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Resume {
+    Continue,
+    Instruction,
+    Step,
+    Next,
+    Finish,
+}
+
+// Interactive debugger in the terminal, for those without a riscv gdb. The debugged vm is stepped by the gdb handler,
+// so breakpoints behave the same as in gdb mode.
+pub struct Repl<DL>
+where
+    DL: CellDataProvider + HeaderProvider + ExtensionProvider + Send + Sync + Clone + 'static,
+{
+    pub handler: GdbStubHandler<DL>,
+    decoder: Decoder,
+    lines: SourceLines,
+    symbols: HashMap<String, u64>,
+    exited: bool,
+}
+
+impl<DL> Repl<DL>
+where
+    DL: CellDataProvider + HeaderProvider + ExtensionProvider + Send + Sync + Clone + 'static,
+{
+    pub fn new(mut handler: GdbStubHandler<DL>, program: &Bytes) -> Result<Self, Box<dyn std::error::Error>> {
+        let elf = goblin::elf::Elf::parse(program)?;
+        let mut symbols = HashMap::new();
+        for sym in &elf.syms {
+            if let Some(Ok(name)) = elf.strtab.get(sym.st_name) {
+                if sym.st_value != 0 && !name.is_empty() {
+                    symbols.insert(name.to_string(), sym.st_value);
+                }
+            }
+        }
+        let decoder = build_decoder::<u64>(handler.machine.isa(), handler.machine.version());
+        handler.profile = Some(MachineProfile::new(program)?);
+        Ok(Self { handler, decoder, lines: SourceLines::new(program)?, symbols, exited: false })
+    }
+
+    // Read commands until quit or the end of input. An empty line repeats the last command.
+    pub fn run(&mut self, input: impl BufRead, output: &mut impl Write) -> Result<(), Box<dyn std::error::Error>> {
+        self.handler.machine.set_running(true);
+        let mut lines = input.lines();
+        let mut last = String::new();
+        loop {
+            output.write_all(b"(ckb-debugger) ")?;
+            output.flush()?;
+            let line = match lines.next() {
+                Some(line) => line?,
+                None => break,
+            };
+            let line = if line.trim().is_empty() { last.clone() } else { line.trim().to_string() };
+            if line.is_empty() {
+                continue;
+            }
+            last = line.clone();
+            match self.execute(&line, output) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => writeln!(output, "Error: {}", e)?,
+            }
+        }
+        Ok(())
+    }
+
+    // Execute a command. Returns false if the debugger should exit.
+    pub fn execute(&mut self, line: &str, output: &mut impl Write) -> Result<bool, Box<dyn std::error::Error>> {
+        let args: Vec<&str> = line.split_whitespace().collect();
+        match args.as_slice() {
+            ["break" | "b", location] => match self.location(location) {
+                Some(addr) => {
+                    self.handler.add_sw_breakpoint(addr, 4).map_err(|_| "Unable to set breakpoint")?;
+                    writeln!(output, "Breakpoint at {:#x}{}", addr, self.describe(addr))?;
+                }
+                None => writeln!(output, "Unable to find {}", location)?,
+            },
+            ["delete" | "d"] => {
+                for addr in self.handler.breakpoint_addrs() {
+                    self.handler.remove_sw_breakpoint(addr, 4).map_err(|_| "Unable to delete breakpoint")?;
+                }
+            }
+            ["delete" | "d", location] => match self.location(location) {
+                Some(addr) if self.handler.remove_sw_breakpoint(addr, 4).unwrap_or(false) => {}
+                _ => writeln!(output, "No breakpoint at {}", location)?,
+            },
+            ["info", "breakpoints" | "b"] => {
+                for addr in self.handler.breakpoint_addrs() {
+                    writeln!(output, "{:#x}{}", addr, self.describe(addr))?;
+                }
+            }
+            ["continue" | "c"] => self.resume(Resume::Continue, output)?,
+            ["step" | "s"] => self.resume(Resume::Step, output)?,
+            ["next" | "n"] => self.resume(Resume::Next, output)?,
+            ["finish"] => self.resume(Resume::Finish, output)?,
+            ["stepi" | "si"] => self.resume(Resume::Instruction, output)?,
+            ["registers" | "regs"] | ["info", "registers" | "r"] => {
+                writeln!(output, "{:<4} {:#x}", "pc", self.handler.machine.pc())?;
+                for (i, e) in self.handler.machine.registers().iter().enumerate() {
+                    writeln!(output, "{:<4} {:#x}", REGISTER_ABI_NAMES[i], e)?;
+                }
+            }
+            ["x", addr] | ["x", addr, _] => {
+                let len = match args.get(2) {
+                    Some(len) => parse_number(len).ok_or("Invalid length")?,
+                    None => 64,
+                };
+                let addr = self.value(addr).ok_or("Invalid address")?;
+                self.examine(addr, len, output)?;
+            }
+            ["backtrace" | "bt"] => self.backtrace(output)?,
+            ["disassemble" | "disas"] | ["disassemble" | "disas", _] | ["disassemble" | "disas", _, _] => {
+                let addr = match args.get(1) {
+                    Some(addr) => self.value(addr).ok_or("Invalid address")?,
+                    None => *self.handler.machine.pc(),
+                };
+                let count = match args.get(2) {
+                    Some(count) => parse_number(count).ok_or("Invalid count")?,
+                    None => 8,
+                };
+                self.disassemble(addr, count, output)?;
+            }
+            ["cycles"] => {
+                let machine = &self.handler.machine;
+                writeln!(output, "Cycles of pid {}: {}", machine.id, machine.cycles())?;
+                writeln!(output, "All cycles: {}", machine.total_cycles())?;
+                writeln!(output, "Max cycles: {}", machine.max_cycles())?;
+            }
+            ["help" | "h"] => writeln!(output, "{}", HELP)?,
+            ["quit" | "q"] => return Ok(false),
+            _ => writeln!(output, "Unknown command: {}, see help", line)?,
+        }
+        Ok(true)
+    }
+
+    // Address of a symbol, a source line or an expression starting with *.
+    fn location(&self, location: &str) -> Option<u64> {
+        if let Some(expr) = location.strip_prefix('*') {
+            return self.value(expr);
+        }
+        if let Some(addr) = self.symbols.get(location) {
+            return Some(*addr);
+        }
+        let (path, line) = location.rsplit_once(':')?;
+        let file = self.lines.file(path)?;
+        self.lines.resolve(&file, line.parse().ok()?).map(|e| e.0)
+    }
+
+    // Value of a number or a register such as $sp.
+    fn value(&self, expr: &str) -> Option<u64> {
+        if let Some(name) = expr.strip_prefix('$') {
+            if name == "pc" {
+                return Some(*self.handler.machine.pc());
+            }
+            let i = REGISTER_ABI_NAMES.iter().position(|e| *e == name)?;
+            return Some(self.handler.machine.registers()[i]);
+        }
+        parse_number(expr)
+    }
+
+    fn describe(&self, addr: u64) -> String {
+        match self.lines.location(addr) {
+            Some((file, line)) => format!(": {}:{}", file, line),
+            None => String::new(),
+        }
+    }
+
+    // Run until the resume condition is met, a breakpoint is hit or the program exits. Source lines are compared to
+    // the line where it starts, and the depth of the call stack tells whether a call is entered or returned.
+    fn resume(&mut self, resume: Resume, output: &mut impl Write) -> std::io::Result<()> {
+        if self.exited {
+            return writeln!(output, "The program is not being run");
+        }
+        let start = self.lines.location(*self.handler.machine.pc());
+        let depth = self.depth();
+        loop {
+            match self.handler.step() {
+                Some(VmEvent::Exited(code)) => {
+                    self.exited = true;
+                    writeln!(output, "Program exited with code {}", code as i8)?;
+                    return writeln!(output, "All cycles: {}", self.handler.machine.scheduler.consumed_cycles());
+                }
+                Some(VmEvent::Error(e)) => {
+                    self.exited = true;
+                    writeln!(output, "Error: {}", e)?;
+                    return self.backtrace(output);
+                }
+                Some(VmEvent::Break) => {
+                    writeln!(output, "Breakpoint hit")?;
+                    return self.where_am_i(output);
+                }
+                _ => {}
+            }
+            let location = self.lines.location(*self.handler.machine.pc());
+            let current = self.depth();
+            let done = match resume {
+                Resume::Continue => false,
+                Resume::Instruction => true,
+                Resume::Step => location.is_some() && location != start,
+                Resume::Next => location.is_some() && (current < depth || (current == depth && location != start)),
+                Resume::Finish => current < depth,
+            };
+            if done {
+                return self.where_am_i(output);
+            }
+        }
+    }
+
+    fn depth(&self) -> usize {
+        self.handler.profile.as_ref().map(|e| e.frames().len()).unwrap_or_default()
+    }
+
+    // Print the current pc, function and source line.
+    fn where_am_i(&mut self, output: &mut impl Write) -> std::io::Result<()> {
+        let pc = *self.handler.machine.pc();
+        let func = self.handler.profile.as_mut().map(|e| e.get_tag(pc).func()).unwrap_or_default();
+        match self.lines.location(pc) {
+            Some((file, line)) => {
+                writeln!(output, "{:#x} in {} at {}:{}", pc, func, file, line)?;
+                if let Some(text) =
+                    std::fs::read_to_string(&file).ok().and_then(|e| e.lines().nth(line as usize - 1).map(String::from))
+                {
+                    writeln!(output, "{}\t{}", line, text)?;
+                }
+                Ok(())
+            }
+            None => writeln!(output, "{:#x} in {}", pc, func),
+        }
+    }
+
+    fn backtrace(&mut self, output: &mut impl Write) -> std::io::Result<()> {
+        let pc = *self.handler.machine.pc();
+        let profile = match self.handler.profile.as_mut() {
+            Some(profile) => profile,
+            None => return Ok(()),
+        };
+        // Return addresses point after the call, the call itself is used to find the source line.
+        let addrs: Vec<u64> =
+            std::iter::once(pc).chain(profile.frames().into_iter().map(|e| e.wrapping_sub(1))).collect();
+        for (i, addr) in addrs.into_iter().enumerate() {
+            writeln!(output, "#{:<2} {}", i, profile.get_tag(addr).detail())?;
+        }
+        Ok(())
+    }
+
+    fn examine(&mut self, addr: u64, len: u64, output: &mut impl Write) -> std::io::Result<()> {
+        let memory = self.handler.machine.memory_mut();
+        for row in (0..len).step_by(16) {
+            let mut line = format!("{:#010x}:", addr.wrapping_add(row));
+            for i in row..std::cmp::min(row + 16, len) {
+                match memory.load8(&addr.wrapping_add(i)) {
+                    Ok(e) => line.push_str(&format!(" {:02x}", e)),
+                    Err(_) => line.push_str(" ??"),
+                }
+            }
+            writeln!(output, "{}", line)?;
+        }
+        Ok(())
+    }
+
+    fn disassemble(&mut self, addr: u64, count: u64, output: &mut impl Write) -> std::io::Result<()> {
+        let pc = *self.handler.machine.pc();
+        let mut addr = addr;
+        // The program may be replaced by exec since the last time.
+        self.decoder.reset_instructions_cache();
+        for _ in 0..count {
+            let inst = match self.decoder.decode(self.handler.machine.memory_mut(), addr) {
+                Ok(inst) => inst,
+                Err(e) => return writeln!(output, "   {:#x}: {}", addr, e),
+            };
+            let text = match TaggedInstruction::try_from(inst) {
+                Ok(e) => e.to_string(),
+                Err(_) => instruction_opcode_name(extract_opcode(inst)).to_lowercase(),
+            };
+            let mark = if addr == pc { "=>" } else { "  " };
+            writeln!(output, "{} {:#x}: {}", mark, addr, text)?;
+            addr += instruction_length(inst) as u64;
+        }
+        Ok(())
+    }
+}
+
+fn parse_number(data: &str) -> Option<u64> {
+    match data.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => data.parse().ok(),
+    }
+}
@@ -0,0 +1,440 @@
+use crate::disasm::instruction_text;
+use crate::machine_assign::MachineAssign;
+use crate::syscall_info::PARTIAL_LOADING_SYSCALLS;
+use ckb_traits::{CellDataProvider, ExtensionProvider, HeaderProvider};
+use ckb_vm::decoder::Decoder;
+use ckb_vm::instructions::{extract_opcode, insts, Instruction, Rtype, Stype};
+use ckb_vm::registers::{A0, A1, A7, REGISTER_ABI_NAMES};
+use ckb_vm::{CoreMachine, Error, Memory, SupportMachine};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+
+const MAGIC: &[u8] = b"CKBTRACE";
+const VERSION: u8 = 1;
+// Bit of the record flags, the pid follows if the record is made by another vm than the last one.
+const FLAG_PID: u8 = 1;
+
+// An instruction executed by a vm, with the registers and memory it writes.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TraceRecord {
+    pub pid: u64,
+    pub pc: u64,
+    pub instruction: Instruction,
+    // Cycles consumed by all vms before the instruction.
+    pub cycles: u64,
+    // Cycles of the instruction, including the cycles of syscalls.
+    pub cost: u64,
+    // Registers written and their new values.
+    pub registers: Vec<(usize, u64)>,
+    // Memory written and the new data.
+    pub memory: Vec<(u64, Vec<u8>)>,
+}
+
+impl TraceRecord {
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "pid": self.pid,
+            "pc": self.pc,
+            "instruction": instruction_text(self.instruction),
+            "cycles": self.cycles,
+            "cost": self.cost,
+            "registers": self.registers.iter().map(|(idx, value)| {
+                serde_json::json!({ "name": REGISTER_ABI_NAMES[*idx], "value": value })
+            }).collect::<Vec<_>>(),
+            "memory": self.memory.iter().map(|(addr, data)| {
+                serde_json::json!({ "addr": addr, "data": hex::encode(data) })
+            }).collect::<Vec<_>>(),
+        })
+    }
+}
+
+impl std::fmt::Display for TraceRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:>10} pid {} {:#x}: {} ({} cycles)",
+            self.cycles,
+            self.pid,
+            self.pc,
+            instruction_text(self.instruction),
+            self.cost
+        )?;
+        for (idx, value) in &self.registers {
+            write!(f, " | {} = {:#x}", REGISTER_ABI_NAMES[*idx], value)?;
+        }
+        for (addr, data) in &self.memory {
+            write!(f, " | [{:#x}] = {}", addr, hex::encode(data))?;
+        }
+        Ok(())
+    }
+}
+
+fn zigzag(n: u64) -> u64 {
+    ((n as i64) << 1 ^ ((n as i64) >> 63)) as u64
+}
+
+fn unzigzag(n: u64) -> u64 {
+    (n >> 1) ^ (n & 1).wrapping_neg()
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        buf.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    buf.push(n as u8);
+}
+
+fn read_u8(reader: &mut impl Read) -> Result<u8, std::io::Error> {
+    let mut buf = [0u8; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_varint(reader: &mut impl Read) -> Result<u64, std::io::Error> {
+    let mut n = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = read_u8(reader)?;
+        n |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(n);
+        }
+    }
+    Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid varint"))
+}
+
+// Writes records in a compact binary format. The file starts with the magic and version, and every record is the
+// flags, the pid if it changes, then varints of the pc and cycles relative to the last record, the instruction, the
+// cost, the registers written and the memory written, whose addresses are relative to the last address written.
+pub struct TraceWriter<W: Write> {
+    writer: W,
+    buf: Vec<u8>,
+    pid: Option<u64>,
+    pc: u64,
+    cycles: u64,
+    addr: u64,
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(mut writer: W) -> Result<Self, std::io::Error> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION])?;
+        Ok(Self { writer, buf: vec![], pid: None, pc: 0, cycles: 0, addr: 0 })
+    }
+
+    pub fn write(&mut self, record: &TraceRecord) -> Result<(), std::io::Error> {
+        self.buf.clear();
+        let flags = if self.pid == Some(record.pid) { 0 } else { FLAG_PID };
+        self.buf.push(flags);
+        if flags & FLAG_PID != 0 {
+            write_varint(&mut self.buf, record.pid);
+            self.pid = Some(record.pid);
+        }
+        write_varint(&mut self.buf, zigzag(record.pc.wrapping_sub(self.pc)));
+        write_varint(&mut self.buf, record.instruction);
+        write_varint(&mut self.buf, zigzag(record.cycles.wrapping_sub(self.cycles)));
+        write_varint(&mut self.buf, record.cost);
+        self.buf.push(record.registers.len() as u8);
+        for (idx, value) in &record.registers {
+            self.buf.push(*idx as u8);
+            write_varint(&mut self.buf, *value);
+        }
+        write_varint(&mut self.buf, record.memory.len() as u64);
+        for (addr, data) in &record.memory {
+            write_varint(&mut self.buf, zigzag(addr.wrapping_sub(self.addr)));
+            write_varint(&mut self.buf, data.len() as u64);
+            self.buf.extend_from_slice(data);
+            self.addr = *addr;
+        }
+        // Cycles of the next instruction are usually the cycles after this one, so they are encoded as a zero.
+        self.pc = record.pc;
+        self.cycles = record.cycles + record.cost;
+        self.writer.write_all(&self.buf)
+    }
+
+    pub fn flush(&mut self) -> Result<(), std::io::Error> {
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+// Reads the records written by TraceWriter.
+pub struct TraceReader<R: Read> {
+    reader: R,
+    pid: u64,
+    pc: u64,
+    cycles: u64,
+    addr: u64,
+}
+
+impl<R: Read> TraceReader<R> {
+    pub fn new(mut reader: R) -> Result<Self, std::io::Error> {
+        let mut header = [0u8; 9];
+        reader.read_exact(&mut header)?;
+        if &header[..8] != MAGIC || header[8] != VERSION {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid trace file"));
+        }
+        Ok(Self { reader, pid: 0, pc: 0, cycles: 0, addr: 0 })
+    }
+
+    fn read_record(&mut self, flags: u8) -> Result<TraceRecord, std::io::Error> {
+        if flags & FLAG_PID != 0 {
+            self.pid = read_varint(&mut self.reader)?;
+        }
+        self.pc = self.pc.wrapping_add(unzigzag(read_varint(&mut self.reader)?));
+        let instruction = read_varint(&mut self.reader)?;
+        let cycles = self.cycles.wrapping_add(unzigzag(read_varint(&mut self.reader)?));
+        let cost = read_varint(&mut self.reader)?;
+        let mut registers = vec![];
+        for _ in 0..read_u8(&mut self.reader)? {
+            let idx = read_u8(&mut self.reader)? as usize;
+            if idx >= REGISTER_ABI_NAMES.len() {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid register"));
+            }
+            registers.push((idx, read_varint(&mut self.reader)?));
+        }
+        let mut memory = vec![];
+        for _ in 0..read_varint(&mut self.reader)? {
+            self.addr = self.addr.wrapping_add(unzigzag(read_varint(&mut self.reader)?));
+            let mut data = vec![];
+            let len = read_varint(&mut self.reader)?;
+            (&mut self.reader).take(len).read_to_end(&mut data)?;
+            if data.len() as u64 != len {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+            memory.push((self.addr, data));
+        }
+        self.cycles = cycles + cost;
+        Ok(TraceRecord { pid: self.pid, pc: self.pc, instruction, cycles, cost, registers, memory })
+    }
+}
+
+impl<R: Read> Iterator for TraceReader<R> {
+    type Item = Result<TraceRecord, std::io::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut flags = [0u8; 1];
+        match self.reader.read(&mut flags) {
+            Ok(0) => None,
+            Ok(_) => Some(self.read_record(flags[0])),
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+// Parse a range in the form of start-end, the end is exclusive. Numbers are decimal or hex with 0x, and either side
+// may be omitted.
+pub fn parse_trace_range(s: &str) -> Result<(u64, u64), Box<dyn std::error::Error>> {
+    let parse = |s: &str, default: u64| -> Result<u64, Box<dyn std::error::Error>> {
+        match s.strip_prefix("0x") {
+            _ if s.is_empty() => Ok(default),
+            Some(hex) => Ok(u64::from_str_radix(hex, 16)?),
+            None => Ok(s.parse::<u64>()?),
+        }
+    };
+    let (start, end) = s.split_once('-').ok_or_else(|| format!("Invalid range {}", s))?;
+    Ok((parse(start, 0)?, parse(end, u64::MAX)?))
+}
+
+// Instructions recorded by MachineTrace. An instruction is recorded if it matches all the filters given.
+#[derive(Clone, Debug, Default)]
+pub struct TraceFilter {
+    pub pc: Option<(u64, u64)>,
+    // Instructions in the functions of these symbols, not including the functions they call.
+    pub functions: Vec<String>,
+    pub cycles: Option<(u64, u64)>,
+}
+
+impl TraceFilter {
+    // Filters of the pc and cycles, which the records of a trace can be filtered by as well.
+    pub fn matches(&self, pc: u64, cycles: u64) -> bool {
+        self.pc.map(|(start, end)| pc >= start && pc < end).unwrap_or(true)
+            && self.cycles.map(|(start, end)| cycles >= start && cycles < end).unwrap_or(true)
+    }
+
+    fn function_ranges(&self, program: &[u8]) -> Result<Vec<(u64, u64)>, Box<dyn std::error::Error>> {
+        let elf = goblin::elf::Elf::parse(program)?;
+        Ok(elf
+            .syms
+            .iter()
+            .filter(|e| e.is_function())
+            .filter(|e| matches!(elf.strtab.get(e.st_name), Some(Ok(r)) if self.functions.iter().any(|f| f == r)))
+            .map(|e| (e.st_value, e.st_value + e.st_size))
+            .collect())
+    }
+}
+
+// Terms of a search of the records of a trace, in the form of op=<name>, reg=<name>[=<value>] or mem=<addr>. A record
+// matches if it matches all the terms.
+#[derive(Clone, Debug, Default)]
+pub struct TraceSearch {
+    terms: Vec<(String, String)>,
+}
+
+impl TraceSearch {
+    pub fn new(terms: &[&str]) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut r = Self::default();
+        for term in terms {
+            let (key, value) = term.split_once('=').ok_or_else(|| format!("Invalid search term {}", term))?;
+            if !["op", "reg", "mem"].contains(&key) {
+                return Err(format!("Invalid search term {}", term).into());
+            }
+            r.terms.push((key.to_string(), value.to_string()));
+        }
+        Ok(r)
+    }
+
+    fn number(s: &str) -> Option<u64> {
+        match s.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16).ok(),
+            None => s.parse::<u64>().ok(),
+        }
+    }
+
+    pub fn matches(&self, record: &TraceRecord) -> bool {
+        self.terms.iter().all(|(key, value)| match key.as_str() {
+            "op" => instruction_text(record.instruction).split_whitespace().next() == Some(value.as_str()),
+            "reg" => {
+                let (name, expect) = match value.split_once('=') {
+                    Some((name, expect)) => (name, Self::number(expect)),
+                    None => (value.as_str(), None),
+                };
+                record
+                    .registers
+                    .iter()
+                    .any(|(idx, value)| REGISTER_ABI_NAMES[*idx] == name && expect.map(|e| e == *value).unwrap_or(true))
+            }
+            _ => match Self::number(value) {
+                Some(addr) => record.memory.iter().any(|(e, data)| addr >= *e && addr < e + data.len() as u64),
+                None => false,
+            },
+        })
+    }
+}
+
+// The instruction being executed, with the registers before it and the memory it may write.
+struct TracePending {
+    record: TraceRecord,
+    registers: Vec<u64>,
+    stores: Vec<(u64, u64)>,
+    syscall: Option<(u64, u64, u64)>,
+}
+
+// Record the instructions executed by MachineAnalyzer to a trace.
+pub struct MachineTrace {
+    pub filter: TraceFilter,
+    // Number of instructions recorded.
+    pub count: u64,
+    writer: TraceWriter<Box<dyn Write>>,
+    // Address ranges of the functions of the filter, in the program of each vm.
+    ranges: HashMap<u64, Vec<(u64, u64)>>,
+    pending: Option<TracePending>,
+}
+
+impl MachineTrace {
+    pub fn new(writer: Box<dyn Write>, filter: TraceFilter) -> Result<Self, std::io::Error> {
+        Ok(Self { filter, count: 0, writer: TraceWriter::new(writer)?, ranges: HashMap::new(), pending: None })
+    }
+
+    // The program of a vm is replaced by exec.
+    pub fn reset(&mut self, id: u64) {
+        self.ranges.remove(&id);
+    }
+
+    // Memory written by a store instruction.
+    fn stores(inst: Instruction, registers: &[u64]) -> Vec<(u64, u64)> {
+        let opcode = extract_opcode(inst);
+        let size = match opcode {
+            insts::OP_SB => 1,
+            insts::OP_SH => 2,
+            insts::OP_SW => 4,
+            insts::OP_SD => 8,
+            insts::OP_SC_W | insts::OP_AMOSWAP_W..=insts::OP_AMOMAXU_W => {
+                return vec![(registers[Rtype(inst).rs1()], 4)];
+            }
+            insts::OP_SC_D | insts::OP_AMOSWAP_D..=insts::OP_AMOMAXU_D => {
+                return vec![(registers[Rtype(inst).rs1()], 8)];
+            }
+            _ => return vec![],
+        };
+        let i = Stype(inst);
+        vec![(registers[i.rs1()].wrapping_add(i.immediate_s() as i64 as u64), size)]
+    }
+
+    pub fn before<DL>(&mut self, decoder: &mut Decoder, machine: &mut MachineAssign<DL>) -> Result<(), Error>
+    where
+        DL: CellDataProvider + HeaderProvider + ExtensionProvider + Send + Sync + Clone + 'static,
+    {
+        let pc = *machine.pc();
+        let cycles = machine.total_cycles();
+        if !self.filter.matches(pc, cycles) {
+            return Ok(());
+        }
+        if !self.filter.functions.is_empty() {
+            if let Entry::Vacant(e) = self.ranges.entry(machine.id) {
+                e.insert(self.filter.function_ranges(machine.code()).map_err(|e| Error::Unexpected(e.to_string()))?);
+            }
+            if !self.ranges[&machine.id].iter().any(|(start, end)| pc >= *start && pc < *end) {
+                return Ok(());
+            }
+        }
+        let instruction = decoder.decode(machine.memory_mut(), pc)?;
+        let registers = machine.registers().to_vec();
+        // Memory written by the other syscalls, such as read and wait, is done by the scheduler after the vm yields,
+        // and is not recorded.
+        let mut syscall = None;
+        if extract_opcode(instruction) == insts::OP_ECALL && PARTIAL_LOADING_SYSCALLS.contains(&registers[A7]) {
+            if let Ok(size) = machine.memory_mut().load64(&registers[A1]) {
+                syscall = Some((registers[A0], registers[A1], size));
+            }
+        }
+        self.pending = Some(TracePending {
+            record: TraceRecord { pid: machine.id, pc, instruction, cycles, ..Default::default() },
+            stores: Self::stores(instruction, &registers),
+            registers,
+            syscall,
+        });
+        Ok(())
+    }
+
+    pub fn after<DL>(&mut self, machine: &mut MachineAssign<DL>) -> Result<(), Error>
+    where
+        DL: CellDataProvider + HeaderProvider + ExtensionProvider + Send + Sync + Clone + 'static,
+    {
+        let TracePending { mut record, registers, mut stores, syscall } = match self.pending.take() {
+            Some(pending) => pending,
+            None => return Ok(()),
+        };
+        record.cost = machine.total_cycles().saturating_sub(record.cycles);
+        // The vm may be switched by a syscall, or suspended by the scheduler, whose state is then not available.
+        if let Some((_, vm)) = machine.scheduler.instantiated.get_mut(&record.pid) {
+            let dm = &mut vm.machine;
+            for (idx, value) in dm.registers().iter().enumerate() {
+                if registers[idx] != *value {
+                    record.registers.push((idx, *value));
+                }
+            }
+            if let (Some((addr, size_addr, size)), 0) = (syscall, dm.registers()[A0]) {
+                let full_size = dm.memory_mut().load64(&size_addr)?;
+                stores.push((size_addr, 8));
+                stores.push((addr, std::cmp::min(size, full_size)));
+            }
+            for (addr, size) in stores.into_iter().filter(|e| e.1 != 0) {
+                record.memory.push((addr, dm.memory_mut().load_bytes(addr, size)?.to_vec()));
+            }
+        }
+        self.writer.write(&record)?;
+        self.count += 1;
+        Ok(())
+    }
+
+    // Flush the trace, returns the number of instructions recorded.
+    pub fn finish(mut self) -> Result<u64, std::io::Error> {
+        self.writer.flush()?;
+        Ok(self.count)
+    }
+}
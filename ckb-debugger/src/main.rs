@@ -1,17 +1,25 @@
-use ckb_chain_spec::consensus::{ConsensusBuilder, TYPE_ID_CODE_HASH};
+use ckb_chain_spec::consensus::TYPE_ID_CODE_HASH;
 #[cfg(target_family = "unix")]
 use ckb_debugger::Stdio;
 use ckb_debugger::{
-    get_script_hash_by_index, pre_check, ElfDumper, FileOperation, FileStream, HumanReadableCycles, MachineAnalyzer,
-    MachineAssign, MachineOverlap, MachineProfile, MachineStepLog, Random, TimeNow,
+    check_budget, check_script_groups_cycles, check_transaction, compare, disasm, get_script_hash_by_index, lint,
+    load_profile, parse_trace_range, pre_check, run_script_groups, Coverage, CycleBudget, ElfDumper, FaultConfig,
+    FaultInjector, FileOperation, FileStream, HumanReadableCycles, MachineAnalyzer, MachineAssign, MachineOverlap,
+    MachineProfile, MachineStepLog, MachineTrace, MachineWatch, ProfileDiff, Random, SyscallTrace, TimeNow,
+    TraceFilter, TraceReader, TraceSearch,
 };
-use ckb_debugger::{Embed, GdbStubHandler, GdbStubHandlerEventLoop};
-use ckb_mock_tx_types::{MockCellDep, MockInfo, MockInput, MockTransaction, ReprMockTransaction, Resource};
-use ckb_script::{ScriptGroupType, ScriptVersion, TransactionScriptsVerifier, TxVerifyEnv, ROOT_VM_ID};
+use ckb_debugger::{
+    spawn_dap_reader, ChainEnv, DapServer, Embed, GdbStubHandler, GdbStubHandlerEventLoop, History, JsonCoverageReport,
+    JsonGroupReport, JsonProcessReport, JsonReport, JsonTraceReport, LintSeverity, Repl, RpcResourceLoader, Snapshot,
+};
+use ckb_mock_tx_types::{
+    FixtureResourceLoader, MockCellDep, MockInfo, MockInput, MockTransaction, ReprMockTransaction, Resource,
+};
+use ckb_script::{ScriptError, ScriptGroupType, ScriptVersion, TransactionScriptsVerifier, ROOT_VM_ID};
 use ckb_types::core::cell::{resolve_transaction, CellMetaBuilder};
-use ckb_types::core::{hardfork, Capacity, DepType, HeaderView, ScriptHashType, TransactionBuilder};
+use ckb_types::core::{Capacity, DepType, ScriptHashType, TransactionBuilder};
 use ckb_types::packed::{Byte32, CellDep, CellInput, CellOutput, OutPoint, Script, ScriptOpt};
-use ckb_types::prelude::{Builder, Entity, Pack};
+use ckb_types::prelude::{Builder, Entity, Pack, Unpack};
 use ckb_vm::cost_model::estimate_cycles;
 use ckb_vm::decoder::build_decoder;
 use ckb_vm::error::Error;
@@ -24,11 +32,12 @@ use gdbstub::{
     stub::{DisconnectReason, GdbStub},
 };
 use probe::probe;
+use protobuf::Message;
 use std::collections::HashSet;
-use std::io::{BufRead, Read};
+use std::io::{BufRead, BufReader, Read, Write};
 use std::net::TcpListener;
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     drop(env_logger::init());
@@ -36,11 +45,20 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let default_gdb_listen = "127.0.0.1:9999";
     let default_max_cycles = format!("{}", 3_500_000_000u64);
     let default_mode = "full";
+    let default_output_format = "text";
     let default_pid = ROOT_VM_ID.to_string();
     let default_script_version = "2";
 
     let matches = App::new("ckb-debugger")
         .version(crate_version!())
+        .arg(
+            Arg::with_name("annotate")
+                .long("annotate")
+                .help("Print the source lines of a function with their cycles and execution counts in full mode")
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true),
+        )
         .arg(Arg::with_name("args").multiple(true))
         .arg(
             Arg::with_name("bin")
@@ -48,6 +66,24 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .help("File used to replace the binary denoted in the script")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("bin-b")
+                .long("bin-b")
+                .help("Binary compared with the one of --bin, or the one denoted in the script, in compare mode")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("budget")
+                .long("budget")
+                .help("Cycle budget file of all mode. Exit with 253 if a limit is exceeded")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("block-number")
+                .long("block-number")
+                .help("Number of the block that the transaction is committed in")
+                .takes_value(true),
+        )
         .arg(Arg::with_name("cell-index").long("cell-index").short("i").help("Index of cell to run").takes_value(true))
         .arg(
             Arg::with_name("cell-type")
@@ -57,6 +93,59 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .help("Type of cell to run")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("chain")
+                .long("chain")
+                .possible_values(&["dev", "mainnet", "testnet"])
+                .help("Hardfork features of the chain that the transaction is verified on")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("chain-config")
+                .long("chain-config")
+                .help("File containing JSON formatted chain environment, including hardfork epochs and block header")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("ckb2021-epoch")
+                .long("ckb2021-epoch")
+                .help("Custom epoch that vm version 1 and syscalls 2 are enabled")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("ckb2023-epoch")
+                .long("ckb2023-epoch")
+                .help("Custom epoch that vm version 2 and syscalls 3 are enabled")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("coverage")
+                .long("coverage")
+                .help("Line and branch coverage of the sources, written by full mode")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("coverage-format")
+                .long("coverage-format")
+                .help("Format of --coverage output, lcov tracefile or Cobertura XML")
+                .possible_values(&["lcov", "cobertura"])
+                .default_value("lcov")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("coverage-merge")
+                .long("coverage-merge")
+                .help("Lcov tracefile of other runs merged into --coverage output")
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("dap-listen")
+                .long("dap-listen")
+                .help("Address to listen for debug adapter protocol clients in dap mode, default to stdio")
+                .takes_value(true),
+        )
         .arg(Arg::with_name("dump-file").long("dump-file").help("Dump file name").takes_value(true))
         .arg(
             Arg::with_name("enable-overlapping-detection")
@@ -70,6 +159,37 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .long("enable-steplog")
                 .help("Set to true to enable step mode, where we print PC address for each instruction"),
         )
+        .arg(
+            Arg::with_name("epoch")
+                .long("epoch")
+                .help("Epoch of the block that the transaction is committed in, in the form of number[,index,length]")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("faults")
+                .long("faults")
+                .help("JSON file of faults injected into syscalls, in the modes running the script step by step")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("fixture-cells")
+                .long("fixture-cells")
+                .help("Directory of cell fixtures named <tx_hash>-<index>.json, used by build-mock-tx mode")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("fixture-headers")
+                .long("fixture-headers")
+                .help("Directory of header fixtures named <block_hash>.json, default to the directory of cell fixtures")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("gdb-history")
+                .long("gdb-history")
+                .default_value("64")
+                .help("Memory in MiB to record the latest execution for reverse debugging in gdb mode, 0 to disable")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("gdb-listen")
                 .long("gdb-listen")
@@ -88,18 +208,88 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             Arg::with_name("mode")
                 .long("mode")
                 .help("Execution mode of debugger")
-                .possible_values(&["decode-instruction", "fast", "full", "gdb", "probe"])
+                .possible_values(&[
+                    "all",
+                    "build-mock-tx",
+                    "compare",
+                    "dap",
+                    "decode-instruction",
+                    "disasm",
+                    "fast",
+                    "full",
+                    "gdb",
+                    "lint",
+                    "probe",
+                    "profile-diff",
+                    "repl",
+                    "trace-dump",
+                    "verify",
+                ])
                 .default_value(&default_mode)
                 .required(true)
                 .takes_value(true),
         )
-        .arg(Arg::with_name("pid").long("pid").default_value(&default_pid).help("Process ID").takes_value(true))
+        .arg(
+            Arg::with_name("output")
+                .long("output")
+                .short("o")
+                .help("Output file of build-mock-tx, profile-diff, trace-dump mode and snapshots, default to stdout")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("output-format")
+                .long("output-format")
+                .help("Output format of the run result")
+                .possible_values(&["json", "text"])
+                .default_value(&default_output_format)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("pid")
+                .long("pid")
+                .default_value(&default_pid)
+                .help("Process ID to debug. If not given, full mode profiles all and gdb mode exposes all as threads")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("pprof")
                 .long("pprof")
                 .help("Performance profiling, specify output file for further use")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("pprof-format")
+                .long("pprof-format")
+                .help("Format of --pprof output, folded stacks or the protobuf of pprof")
+                .possible_values(&["folded", "proto"])
+                .default_value("folded")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("pprof-lines")
+                .long("pprof-lines")
+                .required(false)
+                .takes_value(false)
+                .help("Split the frames of --pprof output by source lines, in the form of file:line:func"),
+        )
+        .arg(
+            Arg::with_name("profile-base")
+                .long("profile-base")
+                .help("Profile of the base run in profile-diff mode, folded stacks or pprof protobuf")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("profile-new")
+                .long("profile-new")
+                .help("Profile of the new run in profile-diff mode, folded stacks or pprof protobuf")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("profile-threshold")
+                .long("profile-threshold")
+                .help("Exit with 1 in profile-diff mode if the cycles of a function grow by more than the percent")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("prompt")
                 .long("prompt")
@@ -113,6 +303,24 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .help("Read content from local file or stdin. Then feed the content to syscall in scripts")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("resume-from")
+                .long("resume-from")
+                .help("Resume the debug session from a snapshot of --snapshot-at-cycles, in full, gdb or probe mode")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("rpc")
+                .long("rpc")
+                .help("Url of a ckb node, e.g. http://127.0.0.1:8114, to fetch cells and headers missing in the tx")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("rpc-cache")
+                .long("rpc-cache")
+                .help("Directory to cache the cells and headers fetched by --rpc. Works offline if --rpc is not given")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("script-group-type")
                 .long("script-group-type")
@@ -126,7 +334,67 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             Arg::with_name("script-version")
                 .long("script-version")
                 .default_value(&default_script_version)
-                .help("Script version")
+                .help("Script version, ignored when --chain or --chain-config is given")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("snapshot-at-cycles")
+                .long("snapshot-at-cycles")
+                .help("Run until the given cycles are consumed, then save a snapshot of all vms to --output and exit")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("timestamp")
+                .long("timestamp")
+                .help("Timestamp in milliseconds of the block that the transaction is committed in")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("trace")
+                .long("trace")
+                .help("Binary trace of the instructions executed, written by full mode and read by trace-dump mode")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("trace-cycles")
+                .long("trace-cycles")
+                .help("Trace the instructions executed in the cycle window of <start>-<end>")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("trace-function")
+                .long("trace-function")
+                .help("Trace the instructions of the function in full mode")
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("trace-pc")
+                .long("trace-pc")
+                .help("Trace the instructions in the address range of <start>-<end>")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("trace-search")
+                .long("trace-search")
+                .help("Search the trace in trace-dump mode, by op=<name>, reg=<name>[=<value>] or mem=<addr>")
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("trace-syscalls")
+                .long("trace-syscalls")
+                .help("Log the syscalls made in full mode to a file, or - for stdout")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("trace-syscalls-format")
+                .long("trace-syscalls-format")
+                .help("Format of --trace-syscalls output, text or JSON lines")
+                .possible_values(&["text", "json"])
+                .default_value("text")
                 .takes_value(true),
         )
         .arg(
@@ -136,26 +404,182 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .help("Filename containing JSON formatted transaction dump")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("watch")
+                .long("watch")
+                .help("Watch memory in full mode by <addr|symbol>[:len]. Changes are printed with the stack trace")
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true),
+        )
         .get_matches();
 
+    let matches_annotate: Vec<&str> = matches.values_of("annotate").unwrap_or_default().collect();
     let matches_args = matches.values_of("args").unwrap_or_default();
     let matches_bin = matches.value_of("bin");
+    let matches_bin_b = matches.value_of("bin-b");
+    let matches_block_number = matches.value_of("block-number");
+    let matches_budget = matches.value_of("budget");
     let matches_cell_index = matches.value_of("cell-index");
     let matches_cell_type = matches.value_of("cell-type");
+    let matches_chain = matches.value_of("chain");
+    let matches_chain_config = matches.value_of("chain-config");
+    let matches_ckb2021_epoch = matches.value_of("ckb2021-epoch");
+    let matches_ckb2023_epoch = matches.value_of("ckb2023-epoch");
+    let matches_coverage = matches.value_of("coverage");
+    let matches_coverage_format = matches.value_of("coverage-format").unwrap();
+    let matches_coverage_merge: Vec<&str> = matches.values_of("coverage-merge").unwrap_or_default().collect();
+    let matches_dap_listen = matches.value_of("dap-listen");
     let matches_dump_file = matches.value_of("dump-file");
     let matches_enable_overlapping_detection = matches.is_present("enable-overlapping-detection");
     let matches_enable_steplog = matches.is_present("enable-steplog");
+    let matches_epoch = matches.value_of("epoch");
+    let matches_faults = matches.value_of("faults");
+    let matches_fixture_cells = matches.value_of("fixture-cells");
+    let matches_fixture_headers = matches.value_of("fixture-headers");
+    let matches_gdb_history: usize = matches.value_of("gdb-history").unwrap().parse()?;
     let matches_gdb_listen = matches.value_of("gdb-listen").unwrap();
     let matches_max_cycles = matches.value_of("max-cycles").unwrap();
     let matches_mode = matches.value_of("mode").unwrap();
+    let matches_output = matches.value_of("output");
+    let matches_output_format = matches.value_of("output-format").unwrap();
     let matches_pid = u64::from_str_radix(matches.value_of("pid").unwrap(), 10).unwrap();
     let matches_pprof = matches.value_of("pprof");
+    let matches_pprof_format = matches.value_of("pprof-format").unwrap();
+    let matches_pprof_lines = matches.is_present("pprof-lines");
+    let matches_profile_base = matches.value_of("profile-base");
+    let matches_profile_new = matches.value_of("profile-new");
+    let matches_profile_threshold = matches.value_of("profile-threshold");
     let matches_prompt = matches.is_present("prompt");
     let matches_read_file_name = matches.value_of("read-file");
+    let matches_resume_from = matches.value_of("resume-from");
+    let matches_rpc = matches.value_of("rpc");
+    let matches_rpc_cache = matches.value_of("rpc-cache");
     let matches_script_group_type = matches.value_of("script-group-type");
     let matches_script_hash = matches.value_of("script-hash");
     let matches_script_version = matches.value_of("script-version").unwrap();
+    let matches_snapshot_at_cycles = matches.value_of("snapshot-at-cycles");
+    let matches_timestamp = matches.value_of("timestamp");
+    let matches_trace = matches.value_of("trace");
+    let matches_trace_cycles = matches.value_of("trace-cycles");
+    let matches_trace_function: Vec<&str> = matches.values_of("trace-function").unwrap_or_default().collect();
+    let matches_trace_pc = matches.value_of("trace-pc");
+    let matches_trace_search: Vec<&str> = matches.values_of("trace-search").unwrap_or_default().collect();
+    let matches_trace_syscalls = matches.value_of("trace-syscalls");
+    let matches_trace_syscalls_format = matches.value_of("trace-syscalls-format").unwrap();
     let matches_tx_file = matches.value_of("tx-file");
+    let matches_watch: Vec<&str> = matches.values_of("watch").unwrap_or_default().collect();
+
+    if matches_mode == "build-mock-tx" {
+        let buf = match matches_tx_file {
+            Some("-") | None => {
+                let mut buf = String::new();
+                std::io::stdin().read_to_string(&mut buf)?;
+                buf
+            }
+            Some(doc) => std::fs::read_to_string(doc)?,
+        };
+        // Accepts a bare transaction, the result of get_transaction, or an incomplete mock transaction.
+        let mut value: serde_json::Value = serde_json::from_str(&buf)?;
+        if value.get("transaction").is_some() {
+            value = value["transaction"].take();
+        }
+        let mut mock_tx: MockTransaction = if value.get("mock_info").is_some() {
+            serde_json::from_value::<ReprMockTransaction>(value)?.into()
+        } else {
+            if let Some(e) = value.as_object_mut() {
+                e.remove("hash");
+            }
+            let tx: ckb_jsonrpc_types::Transaction = serde_json::from_value(value)?;
+            MockTransaction { mock_info: MockInfo::default(), tx: tx.into() }
+        };
+        if matches_fixture_cells.is_none() && matches_rpc.is_none() && matches_rpc_cache.is_none() {
+            return Err("Requires --fixture-cells, --rpc or --rpc-cache to resolve cells and headers".into());
+        }
+        // Cells and headers are looked up in the fixtures first, then from the ckb node.
+        let mut unresolved = vec![];
+        if let Some(cells) = matches_fixture_cells {
+            let headers = matches_fixture_headers.unwrap_or(cells);
+            unresolved = mock_tx.try_fill_mock_info(&mut FixtureResourceLoader::new(cells, headers))?;
+        }
+        if matches_rpc.is_some() || matches_rpc_cache.is_some() {
+            let mut loader =
+                RpcResourceLoader::new(matches_rpc.map(String::from), matches_rpc_cache.map(PathBuf::from));
+            unresolved = mock_tx.try_fill_mock_info(&mut loader)?;
+        }
+        if !unresolved.is_empty() {
+            for e in &unresolved {
+                eprintln!("Unresolved {}", e);
+            }
+            return Err(format!("{} out points or headers can not be resolved", unresolved.len()).into());
+        }
+        let repr_mock_tx: ReprMockTransaction = mock_tx.into();
+        pre_check(&repr_mock_tx)?;
+        let data = serde_json::to_string_pretty(&repr_mock_tx)?;
+        match matches_output {
+            Some(path) => std::fs::write(path, data)?,
+            None => println!("{}", data),
+        }
+        return Ok(());
+    }
+
+    if matches_mode == "profile-diff" {
+        let base = load_profile(matches_profile_base.ok_or("Requires --profile-base")?)?;
+        let new = load_profile(matches_profile_new.ok_or("Requires --profile-new")?)?;
+        let threshold = matches_profile_threshold.map(|e| e.parse::<f64>()).transpose()?;
+        let diff = ProfileDiff::new(base, new);
+        let functions = diff.functions(threshold);
+        if matches_output_format == "json" {
+            let report = serde_json::json!({
+                "base_cycles": diff.base_cycles(),
+                "new_cycles": diff.new_cycles(),
+                "functions": functions,
+            });
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        } else {
+            diff.display_report(&functions, &mut std::io::stdout());
+        }
+        if let Some(path) = matches_output {
+            diff.display_folded(&mut std::fs::File::create(path)?);
+        }
+        if functions.iter().any(|e| e.regressed) {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let trace_filter = TraceFilter {
+        pc: matches_trace_pc.map(parse_trace_range).transpose()?,
+        functions: matches_trace_function.iter().map(|e| e.to_string()).collect(),
+        cycles: matches_trace_cycles.map(parse_trace_range).transpose()?,
+    };
+
+    if matches_mode == "trace-dump" {
+        let path = matches_trace.ok_or("Requires --trace")?;
+        let reader = TraceReader::new(std::io::BufReader::new(std::fs::File::open(path)?))?;
+        let search = TraceSearch::new(&matches_trace_search)?;
+        let pid = if matches.occurrences_of("pid") > 0 { Some(matches_pid) } else { None };
+        let mut output: Box<dyn std::io::Write> = match matches_output {
+            Some(path) => Box::new(std::io::BufWriter::new(std::fs::File::create(path)?)),
+            None => Box::new(std::io::BufWriter::new(std::io::stdout())),
+        };
+        for record in reader {
+            let record = record?;
+            if pid.map(|e| e != record.pid).unwrap_or(false)
+                || !trace_filter.matches(record.pc, record.cycles)
+                || !search.matches(&record)
+            {
+                continue;
+            }
+            if matches_output_format == "json" {
+                writeln!(output, "{}", record.to_json())?;
+            } else {
+                writeln!(output, "{}", record)?;
+            }
+        }
+        output.flush()?;
+        return Ok(());
+    }
 
     if matches_mode == "decode-instruction" {
         let args: Vec<String> = matches_args.clone().into_iter().map(|s| s.into()).collect();
@@ -211,22 +635,48 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let verifier_max_cycles: u64 = matches_max_cycles.parse()?;
     let verifier_mock_tx: MockTransaction = match matches_tx_file {
-        Some("-") => {
-            let mut buf = String::new();
-            std::io::stdin().read_to_string(&mut buf)?;
-            let repr_mock_tx: ReprMockTransaction = serde_json::from_str(&buf)?;
-            if let Err(msg) = pre_check(&repr_mock_tx) {
-                println!("Potential format error found: {}", msg);
-            }
-            repr_mock_tx.into()
-        }
         Some(doc) => {
-            let buf = std::fs::read_to_string(doc)?;
-            let mut mock_tx_embed = Embed::new(PathBuf::from(doc.to_string()), buf.clone());
-            let buf = mock_tx_embed.replace_all();
-            let repr_mock_tx: ReprMockTransaction = serde_json::from_str(&buf)?;
+            let buf = if doc == "-" {
+                let mut buf = String::new();
+                std::io::stdin().read_to_string(&mut buf)?;
+                buf
+            } else {
+                let buf = std::fs::read_to_string(doc)?;
+                let mut mock_tx_embed = Embed::new(PathBuf::from(doc.to_string()), buf.clone());
+                mock_tx_embed.replace_all()
+            };
+            let mut repr_mock_tx: ReprMockTransaction = serde_json::from_str(&buf)?;
+            if matches_rpc.is_some() || matches_rpc_cache.is_some() {
+                let mut loader =
+                    RpcResourceLoader::new(matches_rpc.map(String::from), matches_rpc_cache.map(PathBuf::from));
+                let mut mock_tx: MockTransaction = repr_mock_tx.into();
+                mock_tx.fill_mock_info(&mut loader)?;
+                repr_mock_tx = mock_tx.into();
+            }
+            if matches_mode == "lint" {
+                let issues = lint(&repr_mock_tx);
+                let errors = issues.iter().filter(|e| e.severity == LintSeverity::Error).count();
+                if matches_output_format == "json" {
+                    println!("{}", serde_json::to_string_pretty(&issues)?);
+                } else {
+                    for e in &issues {
+                        println!("{}", e);
+                    }
+                    println!("{} errors, {} warnings", errors, issues.len() - errors);
+                }
+                if errors != 0 {
+                    std::process::exit(1);
+                }
+                return Ok(());
+            }
             if let Err(msg) = pre_check(&repr_mock_tx) {
-                println!("Potential format error found: {}", msg);
+                for line in msg.lines() {
+                    if matches_output_format == "json" {
+                        eprintln!("Potential format error found: {}", line);
+                    } else {
+                        println!("Potential format error found: {}", line);
+                    }
+                }
             }
             repr_mock_tx.into()
         }
@@ -280,6 +730,192 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             MockTransaction { mock_info: mock_info, tx: tx.data() }
         }
     };
+    let verifier_chain_env = {
+        let mut env = if let Some(path) = matches_chain_config {
+            ChainEnv::from_file(path)?
+        } else if let Some(chain) = matches_chain {
+            ChainEnv::new(chain.parse()?)
+        } else {
+            ChainEnv::with_script_version(match matches_script_version {
+                "0" => ScriptVersion::V0,
+                "1" => ScriptVersion::V1,
+                "2" => ScriptVersion::V2,
+                _ => panic!("Wrong script version"),
+            })
+        };
+        if let Some(data) = matches_chain {
+            env.chain = data.parse()?;
+        }
+        if let Some(data) = matches_ckb2021_epoch {
+            env.ckb2021 = Some(data.parse()?);
+        }
+        if let Some(data) = matches_ckb2023_epoch {
+            env.ckb2023 = Some(data.parse()?);
+        }
+        if let Some(data) = matches_epoch {
+            let parts = data.split(',').map(|e| e.trim().parse::<u64>()).collect::<Result<Vec<_>, _>>()?;
+            match parts[..] {
+                [number] => (env.epoch, env.epoch_index, env.epoch_length) = (number, 0, 1),
+                [number, index, length] => (env.epoch, env.epoch_index, env.epoch_length) = (number, index, length),
+                _ => return Err(format!("Invalid epoch {}", data).into()),
+            }
+        }
+        if let Some(data) = matches_block_number {
+            env.block_number = data.parse()?;
+        }
+        if let Some(data) = matches_timestamp {
+            env.timestamp = data.parse()?;
+        }
+        env.check()?;
+        env
+    };
+    let verifier_script_version = verifier_chain_env.script_version()?;
+    let verifier_resource = Resource::from_mock_tx(&verifier_mock_tx)?;
+    let verifier_resolve_transaction = resolve_transaction(
+        verifier_mock_tx.core_transaction(),
+        &mut HashSet::new(),
+        &verifier_resource,
+        &verifier_resource,
+    )?;
+    let mut verifier = {
+        let consensus = Arc::new(verifier_chain_env.consensus()?);
+        let tx_env = Arc::new(verifier_chain_env.tx_verify_env());
+        TransactionScriptsVerifier::new(
+            Arc::new(verifier_resolve_transaction.clone()),
+            verifier_resource.clone(),
+            consensus.clone(),
+            tx_env.clone(),
+        )
+    };
+    let verifier_messages: Arc<Mutex<Vec<(Byte32, String)>>> = Arc::new(Mutex::new(vec![]));
+    let verifier_messages_clone = verifier_messages.clone();
+    // Stdout is the protocol channel of dap mode, where messages are sent as output events instead.
+    let verifier_messages_print = matches_output_format == "text" && matches_mode != "dap";
+    verifier.set_debug_printer(Box::new(move |hash: &Byte32, message: &str| {
+        let message = message.trim_end_matches('\n');
+        if message != "" {
+            if verifier_messages_print {
+                println!("Script log: {}", message);
+            }
+            verifier_messages_clone.lock().unwrap().push((hash.clone(), message.to_string()));
+        }
+    }));
+    let verifier_messages_of = |hash: &Byte32| -> Vec<String> {
+        let messages = verifier_messages.lock().unwrap();
+        messages.iter().filter(|(h, _)| h == hash).map(|(_, m)| m.clone()).collect()
+    };
+    if matches_mode == "all" {
+        let results = run_script_groups(&verifier, verifier_max_cycles);
+        let check = check_script_groups_cycles(&results, verifier_max_cycles);
+        let failure = check.is_err() || results.iter().any(|e| !e.is_ok());
+        // Messages of the scripts are taken before the budget runs the scripts again.
+        let messages: Vec<Vec<String>> = results.iter().map(|e| verifier_messages_of(&e.script_hash)).collect();
+        let budget = match matches_budget {
+            Some(path) => {
+                let budget: CycleBudget = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+                check_budget(&verifier, &results, &budget, verifier_max_cycles)?
+            }
+            None => vec![],
+        };
+        let exceeded = budget.iter().any(|e| e.exceeded());
+        if matches_output_format == "json" {
+            let mut report = JsonReport::default();
+            match check {
+                Ok(cycles) => report.cycle = Some(cycles),
+                Err(i) => report.error = Some(format!("ExceededMaximumCycles at group {:#x}", results[i].script_hash)),
+            }
+            for (e, messages) in results.iter().zip(messages) {
+                report.groups.push(JsonGroupReport {
+                    script_group_type: e.group_type,
+                    script_hash: e.script_hash.unpack(),
+                    input_indices: e.input_indices.clone(),
+                    output_indices: e.output_indices.clone(),
+                    exit_code: e.exit_code,
+                    cycle: e.cycles,
+                    error: e.error.as_ref().map(|e| e.to_string()),
+                    messages,
+                });
+            }
+            report.budget = budget;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            if failure {
+                std::process::exit(254);
+            }
+            if exceeded {
+                std::process::exit(253);
+            }
+            return Ok(());
+        }
+        println!("{:<6} {:<66} {:<12} {:<12} {:<6} {}", "Group", "Script Hash", "Inputs", "Outputs", "Exit", "Cycles");
+        for e in &results {
+            println!(
+                "{:<6} {:<66} {:<12} {:<12} {:<6} {}",
+                e.group_type.to_string().to_lowercase(),
+                format!("{:#x}", e.script_hash),
+                format!("{:?}", e.input_indices),
+                format!("{:?}", e.output_indices),
+                e.exit_code.map(|c| c.to_string()).unwrap_or(String::from("-")),
+                HumanReadableCycles(e.cycles)
+            );
+        }
+        for e in results.iter().filter(|e| e.error.is_some()) {
+            println!(
+                "Error: {} {:#x}: {}",
+                e.group_type.to_string().to_lowercase(),
+                e.script_hash,
+                e.error.as_ref().unwrap()
+            );
+        }
+        match check {
+            Ok(cycles) => println!("All cycles: {}", HumanReadableCycles(cycles)),
+            Err(i) => println!(
+                "All cycles: exceeded max cycles {} at {} {:#x}",
+                verifier_max_cycles,
+                results[i].group_type.to_string().to_lowercase(),
+                results[i].script_hash
+            ),
+        }
+        if !budget.is_empty() {
+            println!("Budget:");
+            for e in &budget {
+                println!("  {}", e);
+            }
+        }
+        if failure {
+            std::process::exit(254);
+        }
+        if exceeded {
+            std::process::exit(253);
+        }
+        return Ok(());
+    }
+    if matches_mode == "verify" {
+        let issues = check_transaction(&verifier_mock_tx, &verifier_chain_env);
+        let result = verifier.verify(verifier_max_cycles);
+        let failure = result.is_err() || issues.iter().any(|e| e.severity == LintSeverity::Error);
+        if matches_output_format == "json" {
+            let mut report = JsonReport::default();
+            match &result {
+                Ok(cycles) => report.cycle = Some(*cycles),
+                Err(e) => report.error = Some(e.to_string()),
+            }
+            report.issues = issues;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        } else {
+            for e in &issues {
+                println!("{}", e);
+            }
+            match &result {
+                Ok(cycles) => println!("All cycles: {}", HumanReadableCycles(*cycles)),
+                Err(e) => println!("Error: {}", e),
+            }
+            println!("Verify result: {}", if failure { "rejected" } else { "accepted" });
+        }
+        if failure {
+            std::process::exit(254);
+        }
+        return Ok(());
+    }
     let verifier_script_group_type = {
         let script_group_type = if matches_tx_file.is_none() { "lock" } else { matches_script_group_type.unwrap() };
         serde_plain::from_str(script_group_type)?
@@ -302,7 +938,11 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 }
                 if cell_index.is_none() {
                     cell_index = Some("0");
-                    println!("The cell_index is not specified. Assume --cell-index = 0")
+                    if matches_output_format == "json" {
+                        eprintln!("The cell_index is not specified. Assume --cell-index = 0")
+                    } else {
+                        println!("The cell_index is not specified. Assume --cell-index = 0")
+                    }
                 }
             }
             ScriptGroupType::Type => {
@@ -315,45 +955,6 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         let cell_index: usize = cell_index.unwrap().parse()?;
         get_script_hash_by_index(&verifier_mock_tx, &verifier_script_group_type, cell_type, cell_index)
     };
-    let verifier_script_version = match matches_script_version {
-        "0" => ScriptVersion::V0,
-        "1" => ScriptVersion::V1,
-        "2" => ScriptVersion::V2,
-        _ => panic!("Wrong script version"),
-    };
-    let verifier_resource = Resource::from_mock_tx(&verifier_mock_tx)?;
-    let verifier_resolve_transaction = resolve_transaction(
-        verifier_mock_tx.core_transaction(),
-        &mut HashSet::new(),
-        &verifier_resource,
-        &verifier_resource,
-    )?;
-    let mut verifier = {
-        let hardforks = hardfork::HardForks {
-            ckb2021: hardfork::CKB2021::new_mirana().as_builder().rfc_0032(20).build().unwrap(),
-            ckb2023: hardfork::CKB2023::new_mirana().as_builder().rfc_0049(30).build().unwrap(),
-        };
-        let consensus = Arc::new(ConsensusBuilder::default().hardfork_switch(hardforks).build());
-        let epoch = match verifier_script_version {
-            ScriptVersion::V0 => ckb_types::core::EpochNumberWithFraction::new(15, 0, 1),
-            ScriptVersion::V1 => ckb_types::core::EpochNumberWithFraction::new(25, 0, 1),
-            ScriptVersion::V2 => ckb_types::core::EpochNumberWithFraction::new(35, 0, 1),
-        };
-        let header_view = HeaderView::new_advanced_builder().epoch(epoch.pack()).build();
-        let tx_env = Arc::new(TxVerifyEnv::new_commit(&header_view));
-        TransactionScriptsVerifier::new(
-            Arc::new(verifier_resolve_transaction.clone()),
-            verifier_resource.clone(),
-            consensus.clone(),
-            tx_env.clone(),
-        )
-    };
-    verifier.set_debug_printer(Box::new(move |_hash: &Byte32, message: &str| {
-        let message = message.trim_end_matches('\n');
-        if message != "" {
-            println!("Script log: {}", message);
-        }
-    }));
     let verifier_script_group = verifier.find_script_group(verifier_script_group_type, &verifier_script_hash).unwrap();
     let verifier_program = match matches_bin {
         Some(path) => {
@@ -363,13 +964,56 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         None => verifier.extract_script(&verifier_script_group.script)?,
     };
 
+    if matches_mode == "disasm" {
+        let isa = verifier_script_version.vm_isa();
+        disasm(&verifier_program, isa, verifier_script_version.vm_version(), &mut std::io::stdout())?;
+        return Ok(());
+    }
+
+    if matches_mode == "compare" {
+        let program_b: Bytes = std::fs::read(matches_bin_b.ok_or("Requires --bin-b")?)?.into();
+        // Both runs debug all vms. Syscalls of the debugger are left out, since some of them, such as random and time,
+        // differ in every run.
+        let machine_init = |program: &Bytes| -> Result<_, Box<dyn std::error::Error>> {
+            let args: Vec<Bytes> = matches_args.clone().map(|s| Bytes::copy_from_slice(s.as_bytes())).collect();
+            let mut scheduler = verifier.create_scheduler(&verifier_script_group)?;
+            scheduler.tx_data.program = program.clone();
+            MachineAnalyzer::boot(scheduler, &args, verifier_max_cycles)
+        };
+        let report = compare(machine_init(&verifier_program)?, machine_init(&program_b)?);
+        if matches_output_format == "json" {
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        } else {
+            report.display(&mut std::io::stdout())?;
+        }
+        if report.divergence.is_some() {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let verifier_snapshot: Option<Snapshot> = match matches_resume_from {
+        Some(path) => Some(serde_json::from_str(&std::fs::read_to_string(path)?)?),
+        None => None,
+    };
+    let fault_config: Option<FaultConfig> = match matches_faults {
+        Some(path) => Some(serde_json::from_str(&std::fs::read_to_string(path)?)?),
+        None => None,
+    };
     let machine_assign_init = || -> Result<_, Box<dyn std::error::Error>> {
         let args: Vec<String> = matches_args.clone().into_iter().map(|s| s.into()).collect();
         let args: Vec<Bytes> = args.into_iter().map(|s| s.into()).collect();
-        let mut scheduler = verifier.create_scheduler(&verifier_script_group).unwrap();
+        let mut scheduler = verifier.create_scheduler(&verifier_script_group)?;
         scheduler.tx_data.program = verifier_program.clone();
-        let mut machine_assign = MachineAssign::new(matches_pid, &args, scheduler)?;
-        machine_assign.expand_cycles = verifier_max_cycles;
+        let mut machine_assign = match &verifier_snapshot {
+            Some(snapshot) => snapshot.resume(scheduler)?,
+            None => {
+                let mut machine_assign = MachineAssign::new(matches_pid, &args, scheduler)?;
+                machine_assign.expand_cycles = verifier_max_cycles;
+                machine_assign.wait()?;
+                machine_assign
+            }
+        };
         if let Some(data) = matches_dump_file {
             machine_assign.expand_syscalls.push(Box::new(ElfDumper::new(data.to_string(), 4097, 64)));
         }
@@ -381,40 +1025,224 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         #[cfg(target_family = "unix")]
         machine_assign.expand_syscalls.push(Box::new(Stdio::new(false)));
         machine_assign.expand_syscalls.push(Box::new(TimeNow::new()));
-        machine_assign.wait()?;
+        if let Some(config) = &fault_config {
+            machine_assign.faults = Some(FaultInjector::new(config.clone())?);
+        }
         Ok(machine_assign)
     };
+    // The code of the debugged vm, which is not kept by a resumed vm.
+    let machine_assign_code = |machine_assign: &MachineAssign<_>| match &verifier_snapshot {
+        Some(snapshot) => snapshot.code.clone().into_bytes(),
+        None => machine_assign.code().clone(),
+    };
+
+    let report_init = || JsonReport {
+        script_group_type: Some(verifier_script_group_type),
+        script_hash: Some(verifier_script_hash.unpack()),
+        messages: verifier_messages_of(&verifier_script_hash),
+        ..Default::default()
+    };
+
+    if let Some(data) = matches_snapshot_at_cycles {
+        let cycles: u64 = data.parse()?;
+        let mut machine = machine_assign_init()?;
+        machine.set_running(true);
+        // The vm is decoded by the version of its script, which may be older than the one of the chain.
+        let mut decoder = build_decoder::<u64>(machine.isa(), machine.version());
+        while machine.running() && machine.total_cycles() < cycles {
+            if machine.reset_signal() {
+                decoder.reset_instructions_cache();
+            }
+            machine.step(&mut decoder)?;
+        }
+        if !machine.running() {
+            return Err(
+                format!("Vm {} exits at cycles {} before the snapshot", machine.id, machine.total_cycles()).into()
+            );
+        }
+        let snapshot = Snapshot::new(&mut machine)?;
+        let data = serde_json::to_string_pretty(&snapshot)?;
+        match matches_output {
+            Some(path) => std::fs::write(path, data)?,
+            None => println!("{}", data),
+        }
+        eprintln!("Snapshot of vm {} saved at cycles {}", snapshot.id, HumanReadableCycles(snapshot.cycles()));
+        return Ok(());
+    }
 
     if matches_mode == "fast" {
-        let cycles = verifier.verify_single(verifier_script_group_type, &verifier_script_hash, verifier_max_cycles)?;
-        println!("All cycles: {}", HumanReadableCycles(cycles));
+        let result = verifier.verify_single(verifier_script_group_type, &verifier_script_hash, verifier_max_cycles);
+        if matches_output_format == "json" {
+            let mut report = report_init();
+            match &result {
+                Ok(cycles) => {
+                    report.cycle = Some(*cycles);
+                    report.exit_code = Some(0);
+                }
+                Err(ScriptError::ValidationFailure(_, exit_code)) => {
+                    report.exit_code = Some(*exit_code);
+                    report.error = Some(result.as_ref().unwrap_err().to_string());
+                }
+                Err(e) => report.error = Some(e.to_string()),
+            }
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        }
+        let cycles = result?;
+        if matches_output_format == "text" {
+            println!("All cycles: {}", HumanReadableCycles(cycles));
+        }
         return Ok(());
     }
 
     if matches_mode == "full" {
-        let machine_assign = machine_assign_init()?;
-        let machine_profile = MachineProfile::new(&machine_assign.code().clone())?;
-        let machine_overlap = MachineOverlap::new(&machine_assign.code().clone())?;
+        let mut machine_assign = machine_assign_init()?;
+        // All spawned vms are profiled, unless a single one is selected by --pid or a snapshot.
+        machine_assign.all_vms = matches.occurrences_of("pid") == 0 && verifier_snapshot.is_none();
+        let machine_profile = MachineProfile::new(&machine_assign_code(&machine_assign))?;
+        let machine_overlap = MachineOverlap::new(&machine_assign_code(&machine_assign))?;
         let machine_steplog = MachineStepLog::new();
+        if let Some(path) = matches_trace_syscalls {
+            let output: Box<dyn Write> = match path {
+                "-" if matches_output_format == "json" => {
+                    return Err("--trace-syscalls - can not be used with --output-format json".into());
+                }
+                "-" => Box::new(std::io::stdout()),
+                _ => Box::new(std::io::BufWriter::new(std::fs::File::create(path)?)),
+            };
+            machine_assign.syscall_trace = Some(SyscallTrace::new(output, matches_trace_syscalls_format == "json"));
+        }
         let mut machine = MachineAnalyzer::new(machine_assign, machine_profile, machine_overlap, machine_steplog);
+        if matches_coverage.is_some() {
+            machine.coverage = Some(Coverage::default());
+        }
         if matches_enable_overlapping_detection {
             machine.enable_overlap = 1;
         }
         if matches_enable_steplog {
             machine.enable_steplog = 1;
         }
+        if let Some(path) = matches_trace {
+            let output = Box::new(std::io::BufWriter::new(std::fs::File::create(path)?));
+            machine.trace = Some(MachineTrace::new(output, trace_filter.clone())?);
+            machine.enable_trace = 1;
+        }
+        if !matches_watch.is_empty() {
+            let code = machine_assign_code(&machine.machine);
+            machine.watch = Some(MachineWatch::new(machine.machine.id, &code, &matches_watch)?);
+            machine.enable_watch = 1;
+        }
+        let write_pprof = |machine: &mut MachineAnalyzer<_>, fp: &str| -> Result<(), Box<dyn std::error::Error>> {
+            let mut output = std::fs::File::create(fp)?;
+            if matches_pprof_format == "proto" {
+                output.write_all(&machine.pprof()?.write_to_bytes()?)?;
+            } else if matches_pprof_lines {
+                machine.display_flamegraph_lines(&mut output);
+            } else {
+                machine.display_flamegraph(&mut output);
+            }
+            Ok(())
+        };
         let result = machine.run();
-        if matches_pid != ROOT_VM_ID {
+        let trace_count = machine.trace.take().map(|e| e.finish()).transpose()?;
+        let syscall_count = machine.machine.syscall_trace.take().map(|e| e.finish()).transpose()?;
+        let faults_injected = machine.machine.faults.take().map(|e| e.injected).unwrap_or_default();
+        // Coverage is written even if the script fails, since tests of failures cover code as well.
+        let coverage = match matches_coverage {
+            Some(path) => {
+                let mut coverage = machine.coverage()?;
+                for e in &matches_coverage_merge {
+                    coverage.merge(Coverage::load_lcov(&std::fs::read_to_string(e)?)?);
+                }
+                let mut output = std::io::BufWriter::new(std::fs::File::create(path)?);
+                if matches_coverage_format == "cobertura" {
+                    coverage.display_cobertura(&mut output)?;
+                } else {
+                    coverage.display_lcov(&mut output)?;
+                }
+                Some(coverage)
+            }
+            None => None,
+        };
+        if !machine.machine.all_vms && machine.machine.id != ROOT_VM_ID {
             machine.machine.done()?;
         }
         let cycles = machine.machine.scheduler.consumed_cycles();
+        if matches_output_format == "json" {
+            let mut report = report_init();
+            report.cycle = Some(cycles);
+            report.vm_cycles = machine.machine.vm_cycles.clone();
+            for (id, process) in &machine.processes {
+                report.processes.push(JsonProcessReport {
+                    pid: *id,
+                    parent: process.parent,
+                    exit_code: machine.exit_code_of(*id),
+                    cycle: machine.machine.vm_cycles.get(id).copied().unwrap_or_default(),
+                });
+            }
+            report.faults = faults_injected;
+            report.trace = trace_count.map(|count| JsonTraceReport { path: matches_trace.unwrap().into(), count });
+            report.syscall_trace =
+                syscall_count.map(|count| JsonTraceReport { path: matches_trace_syscalls.unwrap().into(), count });
+            report.coverage = coverage.map(|e| JsonCoverageReport {
+                path: matches_coverage.unwrap().into(),
+                lines_hit: e.lines_hit(),
+                lines_found: e.lines_found(),
+                branches_hit: e.branches_hit(),
+                branches_found: e.branches_found(),
+            });
+            match &result {
+                Ok(data) => report.exit_code = Some(*data),
+                Err(err) => {
+                    let mut stack_trace = vec![];
+                    machine.display_stacktrace("", &mut stack_trace);
+                    report.error = Some(err.to_string());
+                    report.stack_trace = String::from_utf8_lossy(&stack_trace).lines().map(String::from).collect();
+                }
+            }
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            if let (Ok(_), Some(fp)) = (&result, matches_pprof) {
+                write_pprof(&mut machine, fp)?;
+            }
+            return match result {
+                Ok(0) => Ok(()),
+                Ok(_) => std::process::exit(254),
+                Err(err) => Err(Box::new(err)),
+            };
+        }
+        for e in &faults_injected {
+            println!("Fault injected: {}", e);
+        }
         match result {
             Ok(data) => {
                 println!("Run result: {:?}", data);
                 println!("All cycles: {}", HumanReadableCycles(cycles));
+                if let Some(count) = trace_count {
+                    println!("Trace: {} instructions recorded to {}", count, matches_trace.unwrap());
+                }
+                if let (Some(count), Some(path)) = (syscall_count, matches_trace_syscalls.filter(|e| *e != "-")) {
+                    println!("Syscall trace: {} syscalls recorded to {}", count, path);
+                }
+                if let Some(coverage) = &coverage {
+                    println!(
+                        "Coverage: lines {} of {}, branches {} of {}, written to {}",
+                        coverage.lines_hit(),
+                        coverage.lines_found(),
+                        coverage.branches_hit(),
+                        coverage.branches_found(),
+                        matches_coverage.unwrap()
+                    );
+                }
+                if machine.processes.len() > 1 {
+                    println!("Spawn tree:");
+                    machine.display_spawn_tree(&mut std::io::stdout());
+                }
+                for func in &matches_annotate {
+                    if !machine.display_annotate(func, &mut std::io::stdout()) {
+                        println!("Function {} is not executed", func);
+                    }
+                }
                 if let Some(fp) = matches_pprof {
-                    let mut output = std::fs::File::create(&fp)?;
-                    machine.profile.display_flamegraph(&mut output);
+                    write_pprof(&mut machine, fp)?;
                 }
                 if data != 0 {
                     std::process::exit(254);
@@ -422,7 +1250,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 return Ok(());
             }
             Err(err) => {
-                machine.profile.display_stacktrace("", &mut std::io::stdout());
+                machine.display_stacktrace("", &mut std::io::stdout());
                 println!("");
                 println!("{}", machine);
                 return Err(Box::new(err));
@@ -437,9 +1265,14 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             if let Ok(stream) = res {
                 println!("Accepted connection from: {}, booting VM", stream.peer_addr()?);
                 let mut machine_assign = machine_assign_init()?;
+                // Every vm is exposed as a gdb thread, unless a single one is selected by --pid.
+                machine_assign.all_vms = matches.occurrences_of("pid") == 0;
                 machine_assign.set_running(true);
                 let mut h = GdbStubHandler::new(machine_assign);
+                h.history = History::new(matches_gdb_history << 20);
                 let connection: Box<(dyn ConnectionExt<Error = std::io::Error> + 'static)> = Box::new(stream);
+                let connection: Box<(dyn ConnectionExt<Error = std::io::Error> + 'static)> =
+                    Box::new(h.connection(connection));
                 let gdb = GdbStub::new(connection);
 
                 let result = match gdb.run_blocking::<GdbStubHandlerEventLoop<_>>(&mut h) {
@@ -470,6 +1303,41 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         return Ok(());
     }
 
+    if matches_mode == "dap" {
+        let serve =
+            |reader: Box<dyn BufRead + Send>, writer: Box<dyn Write>| -> Result<(), Box<dyn std::error::Error>> {
+                let machine_assign = machine_assign_init()?;
+                let code = machine_assign_code(&machine_assign);
+                let mut server = DapServer::new(machine_assign, &code, verifier_messages.clone(), writer)?;
+                server.serve(spawn_dap_reader(reader))
+            };
+        match matches_dap_listen {
+            Some(addr) => {
+                let listener = TcpListener::bind(addr)?;
+                println!("Listening for debug adapter protocol connection on {}", addr);
+                for res in listener.incoming() {
+                    if let Ok(stream) = res {
+                        println!("Accepted connection from: {}, booting VM", stream.peer_addr()?);
+                        if let Err(e) = serve(Box::new(BufReader::new(stream.try_clone()?)), Box::new(stream)) {
+                            println!("Error: {}", e);
+                        }
+                    }
+                }
+            }
+            None => serve(Box::new(BufReader::new(std::io::stdin())), Box::new(std::io::stdout()))?,
+        }
+        return Ok(());
+    }
+
+    if matches_mode == "repl" {
+        let mut machine_assign = machine_assign_init()?;
+        machine_assign.set_running(true);
+        let code = machine_assign_code(&machine_assign);
+        let mut repl = Repl::new(GdbStubHandler::new(machine_assign), &code)?;
+        repl.run(std::io::stdin().lock(), &mut std::io::stdout())?;
+        return Ok(());
+    }
+
     if matches_mode == "probe" {
         if matches_prompt {
             println!("Enter to start executing:");
@@ -501,6 +1369,17 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 });
         }
         let result = step_result.map(|_| machine.exit_code());
+        if matches_output_format == "json" {
+            let mut report = report_init();
+            report.cycle = Some(machine.scheduler.consumed_cycles());
+            report.vm_cycles = machine.vm_cycles.clone();
+            match result {
+                Ok(data) => report.exit_code = Some(data),
+                Err(err) => report.error = Some(err.to_string()),
+            }
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            return Ok(());
+        }
         println!("Run result: {:?}", result);
         println!("All cycles: {}", HumanReadableCycles(machine.scheduler.consumed_cycles()));
     }
@@ -0,0 +1,161 @@
+use ckb_vm::Bytes;
+use ckb_vm_pprof_protos::profile;
+use std::collections::HashMap;
+
+const CYCLES: &str = "cycles";
+const COUNT: &str = "count";
+const CPU: &str = "fakecpu";
+const NANOSECONDS: &str = "nanoseconds";
+// 1 cycle takes roughly 2 nanoseconds to run on the CKB mainnet, the same as ckb-vm-pprof-converter.
+const FREQUENCY: i64 = 500_000_000;
+
+// Builds a profile in the format of https://github.com/google/pprof/blob/main/proto/profile.proto. Strings, functions
+// and locations are deduplicated, so a profile can be built from many processes.
+pub struct PprofBuilder {
+    strings: Vec<String>,
+    string_ids: HashMap<String, i64>,
+    functions: Vec<profile::Function>,
+    function_ids: HashMap<(String, String), u64>,
+    locations: Vec<profile::Location>,
+    location_ids: HashMap<(u64, u64), u64>,
+    mappings: Vec<profile::Mapping>,
+    samples: Vec<profile::Sample>,
+}
+
+impl Default for PprofBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PprofBuilder {
+    pub fn new() -> Self {
+        // string table's first element must be an empty string
+        let mut builder = Self {
+            strings: vec![],
+            string_ids: HashMap::new(),
+            functions: vec![],
+            function_ids: HashMap::new(),
+            locations: vec![],
+            location_ids: HashMap::new(),
+            mappings: vec![],
+            samples: vec![],
+        };
+        builder.string("");
+        builder
+    }
+
+    pub fn string(&mut self, s: &str) -> i64 {
+        if let Some(id) = self.string_ids.get(s) {
+            return *id;
+        }
+        let id = self.strings.len() as i64;
+        self.strings.push(s.to_string());
+        self.string_ids.insert(s.to_string(), id);
+        id
+    }
+
+    // The system name is the name before demangling.
+    pub fn function(&mut self, name: &str, system_name: &str, file: &str, start_line: u32) -> u64 {
+        if let Some(id) = self.function_ids.get(&(name.to_string(), file.to_string())) {
+            return *id;
+        }
+        let id = self.functions.len() as u64 + 1;
+        let function = profile::Function {
+            id,
+            name: self.string(name),
+            system_name: self.string(system_name),
+            filename: self.string(file),
+            start_line: start_line as i64,
+            ..Default::default()
+        };
+        self.functions.push(function);
+        self.function_ids.insert((name.to_string(), file.to_string()), id);
+        id
+    }
+
+    // Mapping of the executable segments of a program. The program is identified by its data hash, which is the code
+    // hash of scripts with the data hash type.
+    pub fn mapping(&mut self, program: &Bytes) -> Result<u64, Box<dyn std::error::Error>> {
+        let elf = goblin::elf::Elf::parse(program)?;
+        let segments: Vec<_> = elf
+            .program_headers
+            .iter()
+            .filter(|e| e.p_type == goblin::elf::program_header::PT_LOAD && e.is_executable())
+            .collect();
+        let hash = format!("0x{}", hex::encode(ckb_hash::blake2b_256(program)));
+        let id = self.mappings.len() as u64 + 1;
+        let mapping = profile::Mapping {
+            id,
+            memory_start: segments.iter().map(|e| e.p_vaddr).min().unwrap_or_default(),
+            memory_limit: segments.iter().map(|e| e.p_vaddr + e.p_memsz).max().unwrap_or_default(),
+            file_offset: segments.iter().map(|e| e.p_offset).min().unwrap_or_default(),
+            filename: self.string(&hash),
+            build_id: self.string(&hash),
+            has_functions: true,
+            has_filenames: true,
+            has_line_numbers: true,
+            has_inline_frames: true,
+            ..Default::default()
+        };
+        self.mappings.push(mapping);
+        Ok(id)
+    }
+
+    pub fn location_id(&self, mapping_id: u64, address: u64) -> Option<u64> {
+        self.location_ids.get(&(mapping_id, address)).copied()
+    }
+
+    // Lines are function ids and line numbers, the innermost inlined function first.
+    pub fn location(&mut self, mapping_id: u64, address: u64, lines: &[(u64, u32)]) -> u64 {
+        if let Some(id) = self.location_id(mapping_id, address) {
+            return id;
+        }
+        let id = self.locations.len() as u64 + 1;
+        let lines: Vec<profile::Line> = lines
+            .iter()
+            .map(|(function_id, line)| profile::Line {
+                function_id: *function_id,
+                line: *line as i64,
+                ..Default::default()
+            })
+            .collect();
+        let location = profile::Location { id, mapping_id, address, line: lines.into(), ..Default::default() };
+        self.locations.push(location);
+        self.location_ids.insert((mapping_id, address), id);
+        id
+    }
+
+    // Locations are the leaf first. Labels are numeric, such as the vm id.
+    pub fn sample(&mut self, location_ids: Vec<u64>, cycles: u64, labels: &[(&str, i64)]) {
+        let labels: Vec<profile::Label> = labels
+            .iter()
+            .map(|(key, num)| profile::Label { key: self.string(key), num: *num, ..Default::default() })
+            .collect();
+        let sample = profile::Sample {
+            location_id: location_ids,
+            value: vec![cycles as i64, cycles as i64 * 1_000_000_000 / FREQUENCY],
+            label: labels.into(),
+            ..Default::default()
+        };
+        self.samples.push(sample);
+    }
+
+    pub fn build(mut self) -> profile::Profile {
+        let samples_value =
+            profile::ValueType { field_type: self.string(CYCLES), unit: self.string(COUNT), ..Default::default() };
+        let time_value =
+            profile::ValueType { field_type: self.string(CPU), unit: self.string(NANOSECONDS), ..Default::default() };
+        profile::Profile {
+            sample_type: vec![samples_value, time_value.clone()].into(),
+            sample: self.samples.into(),
+            mapping: self.mappings.into(),
+            location: self.locations.into(),
+            function: self.functions.into(),
+            string_table: self.strings.into(),
+            period_type: Some(time_value).into(),
+            period: 1_000_000_000 / FREQUENCY,
+            ..Default::default()
+        }
+    }
+}
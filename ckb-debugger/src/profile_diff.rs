@@ -0,0 +1,201 @@
+use ckb_vm_pprof_protos::profile;
+use protobuf::Message;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Read;
+
+// Cycles of folded stacks, whose frames are separated by "; " from the root to the leaf.
+pub type FoldedStacks = BTreeMap<String, u64>;
+
+// Folded stacks written by --pprof. The same stack may appear more than once, their cycles are summed.
+pub fn load_folded(data: &str) -> Result<FoldedStacks, Box<dyn std::error::Error>> {
+    let mut stacks = FoldedStacks::new();
+    for line in data.lines().filter(|e| !e.trim().is_empty()) {
+        let (stack, cycles) = line.rsplit_once(' ').ok_or_else(|| format!("Invalid folded stack {}", line))?;
+        *stacks.entry(stack.to_string()).or_default() += cycles.parse::<u64>()?;
+    }
+    Ok(stacks)
+}
+
+// Folded stacks of a pprof profile, which may be gzipped. Frames are in the form of file:func as those written by
+// --pprof, and root frames are prefixed by [pid N] if samples are labeled with more than one pid.
+pub fn load_pprof(data: &[u8]) -> Result<FoldedStacks, Box<dyn std::error::Error>> {
+    let mut unzipped = vec![];
+    let data = if data.starts_with(&[0x1f, 0x8b]) {
+        flate2::read::GzDecoder::new(data).read_to_end(&mut unzipped)?;
+        &unzipped[..]
+    } else {
+        data
+    };
+    let profile = profile::Profile::parse_from_bytes(data)?;
+    let string = |i: i64| profile.string_table.get(i as usize).map(|e| e.as_str()).unwrap_or("??");
+    let index = profile.sample_type.iter().position(|e| string(e.field_type) == "cycles").unwrap_or(0);
+    let functions: BTreeMap<u64, &profile::Function> = profile.function.iter().map(|e| (e.id, e)).collect();
+    let locations: BTreeMap<u64, &profile::Location> = profile.location.iter().map(|e| (e.id, e)).collect();
+    let pid = |sample: &profile::Sample| sample.label.iter().find(|e| string(e.key) == "pid").map(|e| e.num);
+    let pids: BTreeSet<Option<i64>> = profile.sample.iter().map(pid).collect();
+    let mut stacks = FoldedStacks::new();
+    for sample in profile.sample.iter() {
+        let mut frames = vec![];
+        for id in sample.location_id.iter().rev() {
+            let location = locations.get(id).ok_or_else(|| format!("Unknown location {}", id))?;
+            for line in location.line.iter().rev() {
+                let function =
+                    functions.get(&line.function_id).ok_or_else(|| format!("Unknown function {}", line.function_id))?;
+                frames.push(format!("{}:{}", string(function.filename), string(function.name)));
+            }
+        }
+        let mut stack = frames.join("; ");
+        if let (true, Some(pid)) = (pids.len() > 1, pid(sample)) {
+            stack = format!("[pid {}] {}", pid, stack);
+        }
+        *stacks.entry(stack).or_default() += sample.value.get(index).copied().unwrap_or_default() as u64;
+    }
+    Ok(stacks)
+}
+
+// Load a profile of folded stacks or pprof protobuf.
+pub fn load_profile(path: &str) -> Result<FoldedStacks, Box<dyn std::error::Error>> {
+    let data = std::fs::read(path)?;
+    if let Ok(text) = std::str::from_utf8(&data) {
+        if let Ok(stacks) = load_folded(text) {
+            return Ok(stacks);
+        }
+    }
+    load_pprof(&data).map_err(|e| format!("Unable to load profile {}: {}", path, e).into())
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct FunctionDelta {
+    pub function: String,
+    // Cycles spent in the function itself.
+    pub base_self: u64,
+    pub new_self: u64,
+    // Cycles spent in the function and the functions it calls.
+    pub base_total: u64,
+    pub new_total: u64,
+    // Total cycles grow more than the threshold.
+    pub regressed: bool,
+}
+
+impl FunctionDelta {
+    pub fn delta(&self) -> i64 {
+        self.new_total as i64 - self.base_total as i64
+    }
+
+    // Change of total cycles in percent, infinite for new functions.
+    pub fn change(&self) -> f64 {
+        self.delta() as f64 * 100.0 / self.base_total as f64
+    }
+}
+
+impl std::fmt::Display for FunctionDelta {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let change = if self.base_total == 0 { String::from("new") } else { format!("{:+.2}%", self.change()) };
+        write!(
+            f,
+            "{:>10} {:>10} {:>+10} {:>9}  {}",
+            self.base_total,
+            self.new_total,
+            self.delta(),
+            change,
+            self.function
+        )
+    }
+}
+
+// The function of a frame, without the pid prefix of root frames.
+fn frame_function(frame: &str) -> &str {
+    match frame.strip_prefix("[pid ") {
+        Some(rest) => rest.split_once("] ").map(|e| e.1).unwrap_or(frame),
+        None => frame,
+    }
+}
+
+pub struct ProfileDiff {
+    pub base: FoldedStacks,
+    pub new: FoldedStacks,
+}
+
+impl ProfileDiff {
+    pub fn new(base: FoldedStacks, new: FoldedStacks) -> Self {
+        Self { base, new }
+    }
+
+    fn functions_of(stacks: &FoldedStacks) -> BTreeMap<String, (u64, u64)> {
+        let mut functions: BTreeMap<String, (u64, u64)> = BTreeMap::new();
+        for (stack, cycles) in stacks {
+            let frames: Vec<&str> = stack.split("; ").map(frame_function).collect();
+            // Recursive functions are counted once per stack.
+            for frame in frames.iter().collect::<BTreeSet<_>>() {
+                functions.entry(frame.to_string()).or_default().1 += cycles;
+            }
+            if let Some(leaf) = frames.last() {
+                functions.entry(leaf.to_string()).or_default().0 += cycles;
+            }
+        }
+        functions
+    }
+
+    pub fn base_cycles(&self) -> u64 {
+        self.base.values().sum()
+    }
+
+    pub fn new_cycles(&self) -> u64 {
+        self.new.values().sum()
+    }
+
+    // Functions whose cycles are changed, the largest change first. A function regresses if its total cycles grow by
+    // more than the threshold in percent.
+    pub fn functions(&self, threshold: Option<f64>) -> Vec<FunctionDelta> {
+        let base = Self::functions_of(&self.base);
+        let new = Self::functions_of(&self.new);
+        let names: BTreeSet<&String> = base.keys().chain(new.keys()).collect();
+        let mut functions: Vec<FunctionDelta> = names
+            .into_iter()
+            .map(|name| {
+                let (base_self, base_total) = base.get(name).copied().unwrap_or_default();
+                let (new_self, new_total) = new.get(name).copied().unwrap_or_default();
+                let mut e = FunctionDelta {
+                    function: name.clone(),
+                    base_self,
+                    new_self,
+                    base_total,
+                    new_total,
+                    regressed: false,
+                };
+                e.regressed = threshold.map(|t| e.delta() > 0 && e.change() > t).unwrap_or(false);
+                e
+            })
+            .filter(|e| e.base_self != e.new_self || e.base_total != e.new_total)
+            .collect();
+        functions.sort_by(|a, b| b.delta().abs().cmp(&a.delta().abs()).then_with(|| a.function.cmp(&b.function)));
+        functions
+    }
+
+    pub fn display_report(&self, functions: &[FunctionDelta], writer: &mut impl std::io::Write) {
+        let (base, new) = (self.base_cycles(), self.new_cycles());
+        let change = (new as f64 - base as f64) * 100.0 / std::cmp::max(base, 1) as f64;
+        writeln!(writer, "All cycles: {} -> {} ({:+}, {:+.2}%)", base, new, new as i64 - base as i64, change).unwrap();
+        writeln!(writer, "{:>10} {:>10} {:>10} {:>9}  Function", "Base", "New", "Delta", "Change").unwrap();
+        for e in functions {
+            writeln!(writer, "{}", e).unwrap();
+        }
+        for e in functions.iter().filter(|e| e.regressed) {
+            writeln!(writer, "Regression: {}", e).unwrap();
+        }
+        writer.flush().unwrap();
+    }
+
+    // Stacks with the cycles of both profiles, in the format of inferno-diff-folded, which inferno-flamegraph turns
+    // into a differential flamegraph.
+    pub fn display_folded(&self, writer: &mut impl std::io::Write) {
+        let stacks: BTreeSet<&String> = self.base.keys().chain(self.new.keys()).collect();
+        for stack in stacks {
+            let base = self.base.get(stack).copied().unwrap_or_default();
+            let new = self.new.get(stack).copied().unwrap_or_default();
+            writeln!(writer, "{} {} {}", stack, base, new).unwrap();
+        }
+        writer.flush().unwrap();
+    }
+}
@@ -0,0 +1,169 @@
+use ckb_jsonrpc_types as json_types;
+use ckb_mock_tx_types::{FixtureResourceLoader, MockResourceLoader};
+use ckb_types::core::HeaderView;
+use ckb_types::packed::{Byte32, CellOutput, OutPoint};
+use ckb_types::prelude::{Pack, Unpack};
+use ckb_types::H256;
+use ckb_vm::Bytes;
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::time::Duration;
+
+// A resource loader which fetches cells and headers from a ckb node through json-rpc. If a cache directory is set,
+// every fetched cell and header is saved in its cells and headers sub directories in the FixtureResourceLoader format,
+// and the loader looks in the cache before asking the node. A loader with a cache directory but without an url works
+// offline, which is useful to replay a transaction later.
+//
+// Only plain http endpoints are supported, e.g. http://127.0.0.1:8114.
+pub struct RpcResourceLoader {
+    pub url: Option<String>,
+    pub cache: Option<FixtureResourceLoader>,
+    pub timeout: Duration,
+    id: u64,
+}
+
+impl RpcResourceLoader {
+    pub fn new(url: Option<String>, cache: Option<PathBuf>) -> Self {
+        let cache = cache.map(|dir| FixtureResourceLoader::new(dir.join("cells"), dir.join("headers")));
+        Self { url, cache, timeout: Duration::from_secs(30), id: 0 }
+    }
+
+    // Send a json-rpc request and return the result field of the response.
+    pub fn call(&mut self, method: &str, params: Value) -> Result<Value, String> {
+        let url = self.url.clone().ok_or_else(|| format!("Rpc {} is not available in offline mode", method))?;
+        self.id += 1;
+        let body = json!({"id": self.id, "jsonrpc": "2.0", "method": method, "params": params}).to_string();
+        let response = http_post(&url, &body, self.timeout).map_err(|e| format!("Rpc {} failed: {}", method, e))?;
+        let mut response: Value =
+            serde_json::from_str(&response).map_err(|e| format!("Rpc {} returns invalid json: {}", method, e))?;
+        if let Some(error) = response.get("error").filter(|e| !e.is_null()) {
+            return Err(format!("Rpc {} returns error: {}", method, error));
+        }
+        Ok(response["result"].take())
+    }
+
+    // Look up the cell in the transaction that creates it, so that dead cells and the block hash are available.
+    // Falls back to get_live_cell for nodes that do not index the transaction.
+    fn fetch_cell(&mut self, out_point: &OutPoint) -> Result<Option<(CellOutput, Bytes, Option<Byte32>)>, String> {
+        let tx_hash: H256 = out_point.tx_hash().unpack();
+        let index: u32 = out_point.index().unpack();
+        let result = self.call("get_transaction", json!([tx_hash]))?;
+        if !result.is_null() && !result["transaction"].is_null() {
+            let tx: json_types::TransactionView = serde_json::from_value(result["transaction"].clone())
+                .map_err(|e| format!("Parse transaction {:x} failed: {}", tx_hash, e))?;
+            let header: Option<H256> = serde_json::from_value(result["tx_status"]["block_hash"].clone())
+                .map_err(|e| format!("Parse transaction {:x} failed: {}", tx_hash, e))?;
+            let output = tx.inner.outputs.get(index as usize).cloned();
+            let data = tx.inner.outputs_data.get(index as usize).cloned();
+            return Ok(output
+                .zip(data)
+                .map(|(output, data)| (output.into(), data.into_bytes(), header.map(|h| h.pack()))));
+        }
+        let result = self.call("get_live_cell", json!([json_types::OutPoint::from(out_point.clone()), true]))?;
+        let cell: json_types::CellWithStatus =
+            serde_json::from_value(result).map_err(|e| format!("Parse cell {} failed: {}", out_point, e))?;
+        Ok(cell
+            .cell
+            .map(|cell| (cell.output.into(), cell.data.map(|e| e.content).unwrap_or_default().into_bytes(), None)))
+    }
+}
+
+impl MockResourceLoader for RpcResourceLoader {
+    fn get_header(&mut self, hash: H256) -> Result<Option<HeaderView>, String> {
+        if let Some(header) = self.cache.as_mut().map(|e| e.get_header(hash.clone())).transpose()?.flatten() {
+            return Ok(Some(header));
+        }
+        let result = self.call("get_header", json!([hash]))?;
+        let header: Option<json_types::HeaderView> =
+            serde_json::from_value(result).map_err(|e| format!("Parse header {:#x} failed: {}", hash, e))?;
+        let header = header.map(HeaderView::from);
+        if let (Some(cache), Some(header)) = (&self.cache, &header) {
+            cache.save_header(header)?;
+        }
+        Ok(header)
+    }
+
+    fn get_live_cell(&mut self, out_point: OutPoint) -> Result<Option<(CellOutput, Bytes, Option<Byte32>)>, String> {
+        if let Some(cell) = self.cache.as_mut().map(|e| e.get_live_cell(out_point.clone())).transpose()?.flatten() {
+            return Ok(Some(cell));
+        }
+        let cell = self.fetch_cell(&out_point)?;
+        if let (Some(cache), Some((output, data, header))) = (&self.cache, &cell) {
+            cache.save_cell(&out_point, output, data, header.as_ref())?;
+        }
+        Ok(cell)
+    }
+}
+
+// A minimal http/1.1 client, just enough to talk to a ckb node.
+fn http_post(url: &str, body: &str, timeout: Duration) -> Result<String, String> {
+    let rest = url.strip_prefix("http://").ok_or_else(|| format!("Unsupported url {}", url))?;
+    let (host, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let addr = if host.contains(':') { host.to_string() } else { format!("{}:80", host) };
+    let mut stream = TcpStream::connect(&addr).map_err(|e| e.to_string())?;
+    stream.set_read_timeout(Some(timeout)).map_err(|e| e.to_string())?;
+    stream.set_write_timeout(Some(timeout)).map_err(|e| e.to_string())?;
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path,
+        host,
+        body.len(),
+        body
+    );
+    stream.write_all(request.as_bytes()).map_err(|e| e.to_string())?;
+
+    let mut reader = BufReader::new(stream);
+    let mut status = String::new();
+    reader.read_line(&mut status).map_err(|e| e.to_string())?;
+    let code = status.split_whitespace().nth(1).unwrap_or_default();
+    if code != "200" {
+        return Err(format!("Unexpected http status {}", status.trim()));
+    }
+    let mut content_length = None;
+    let mut chunked = false;
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).map_err(|e| e.to_string())?;
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((k, v)) = line.split_once(':') {
+            let k = k.trim().to_ascii_lowercase();
+            let v = v.trim();
+            if k == "content-length" {
+                content_length = Some(v.parse::<usize>().map_err(|e| e.to_string())?);
+            }
+            if k == "transfer-encoding" && v.eq_ignore_ascii_case("chunked") {
+                chunked = true;
+            }
+        }
+    }
+    let mut data = vec![];
+    if chunked {
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).map_err(|e| e.to_string())?;
+            let size = usize::from_str_radix(line.trim().split(';').next().unwrap_or_default(), 16)
+                .map_err(|e| e.to_string())?;
+            let mut chunk = vec![0; size + 2];
+            reader.read_exact(&mut chunk).map_err(|e| e.to_string())?;
+            if size == 0 {
+                break;
+            }
+            data.extend_from_slice(&chunk[..size]);
+        }
+    } else if let Some(n) = content_length {
+        data.resize(n, 0);
+        reader.read_exact(&mut data).map_err(|e| e.to_string())?;
+    } else {
+        reader.read_to_end(&mut data).map_err(|e| e.to_string())?;
+    }
+    String::from_utf8(data).map_err(|e| e.to_string())
+}
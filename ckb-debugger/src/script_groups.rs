@@ -0,0 +1,81 @@
+use ckb_chain_spec::consensus::TYPE_ID_CODE_HASH;
+use ckb_script::{ScriptError, ScriptGroupType, TransactionScriptsVerifier};
+use ckb_traits::{CellDataProvider, ExtensionProvider, HeaderProvider};
+use ckb_types::core::{Cycle, ScriptHashType};
+use ckb_types::packed::Byte32;
+use ckb_types::prelude::Pack;
+
+// Execution result of a single script group.
+#[derive(Clone, Debug)]
+pub struct ScriptGroupResult {
+    pub group_type: ScriptGroupType,
+    pub script_hash: Byte32,
+    pub input_indices: Vec<usize>,
+    pub output_indices: Vec<usize>,
+    // None if the script did not run to its end, e.g. a vm error or the type id system script.
+    pub exit_code: Option<i8>,
+    pub cycles: Cycle,
+    pub error: Option<ScriptError>,
+}
+
+impl ScriptGroupResult {
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none() && self.exit_code.unwrap_or(0) == 0
+    }
+}
+
+// Run every script group found in the resolved transaction, in the same order as a ckb node does: lock groups first,
+// then type groups. Each group gets the full max_cycles budget, so that the real consumption of every group is
+// reported even if the transaction as a whole is over the limit.
+pub fn run_script_groups<DL>(verifier: &TransactionScriptsVerifier<DL>, max_cycles: Cycle) -> Vec<ScriptGroupResult>
+where
+    DL: CellDataProvider + HeaderProvider + ExtensionProvider + Send + Sync + Clone + 'static,
+{
+    let mut results = vec![];
+    for (group_type, script_hash, group) in verifier.groups_with_type() {
+        let mut result = ScriptGroupResult {
+            group_type,
+            script_hash: script_hash.clone(),
+            input_indices: group.input_indices.clone(),
+            output_indices: group.output_indices.clone(),
+            exit_code: None,
+            cycles: 0,
+            error: None,
+        };
+        let is_type_id = group.script.code_hash() == TYPE_ID_CODE_HASH.pack()
+            && group.script.hash_type() == ScriptHashType::Type.into();
+        if is_type_id {
+            // The type id script is built into ckb, there is no program to be executed.
+            match verifier.verify_single(group_type, script_hash, max_cycles) {
+                Ok(cycles) => result.cycles = cycles,
+                Err(e) => result.error = Some(e),
+            }
+        } else {
+            match verifier.detailed_run(group, max_cycles) {
+                Ok((exit_code, cycles)) => {
+                    result.exit_code = Some(exit_code);
+                    result.cycles = cycles;
+                }
+                Err(e) => result.error = Some(e),
+            }
+        }
+        results.push(result);
+    }
+    results
+}
+
+// Check the accumulated cycles of all script groups against max_cycles. Like a ckb node, the limit applies to the
+// running sum, and the index of the first group that pushes the sum over the limit is returned.
+pub fn check_script_groups_cycles(results: &[ScriptGroupResult], max_cycles: Cycle) -> Result<Cycle, usize> {
+    let mut cycles: Cycle = 0;
+    for (i, result) in results.iter().enumerate() {
+        if let Some(ScriptError::ExceededMaximumCycles(_)) = result.error {
+            return Err(i);
+        }
+        cycles = cycles.checked_add(result.cycles).ok_or(i)?;
+        if cycles > max_cycles {
+            return Err(i);
+        }
+    }
+    Ok(cycles)
+}
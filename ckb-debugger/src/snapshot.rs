@@ -0,0 +1,301 @@
+use crate::machine_assign::MachineAssign;
+use ckb_hash::blake2b_256;
+use ckb_jsonrpc_types::JsonBytes;
+use ckb_script::{DataPieceId, Scheduler, VmState};
+use ckb_traits::{CellDataProvider, ExtensionProvider, HeaderProvider};
+use ckb_types::prelude::Unpack;
+use ckb_types::H256;
+use ckb_vm::snapshot2::Snapshot2;
+use ckb_vm::{Error, SupportMachine};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReprDataPieceId {
+    Program,
+    Input(u32),
+    Output(u32),
+    CellDep(u32),
+    GroupInput(u32),
+    GroupOutput(u32),
+    Witness(u32),
+    WitnessGroupInput(u32),
+    WitnessGroupOutput(u32),
+}
+
+impl From<&DataPieceId> for ReprDataPieceId {
+    fn from(id: &DataPieceId) -> Self {
+        match *id {
+            DataPieceId::Program => Self::Program,
+            DataPieceId::Input(i) => Self::Input(i),
+            DataPieceId::Output(i) => Self::Output(i),
+            DataPieceId::CellDep(i) => Self::CellDep(i),
+            DataPieceId::GroupInput(i) => Self::GroupInput(i),
+            DataPieceId::GroupOutput(i) => Self::GroupOutput(i),
+            DataPieceId::Witness(i) => Self::Witness(i),
+            DataPieceId::WitnessGroupInput(i) => Self::WitnessGroupInput(i),
+            DataPieceId::WitnessGroupOutput(i) => Self::WitnessGroupOutput(i),
+        }
+    }
+}
+
+impl From<&ReprDataPieceId> for DataPieceId {
+    fn from(id: &ReprDataPieceId) -> Self {
+        match *id {
+            ReprDataPieceId::Program => Self::Program,
+            ReprDataPieceId::Input(i) => Self::Input(i),
+            ReprDataPieceId::Output(i) => Self::Output(i),
+            ReprDataPieceId::CellDep(i) => Self::CellDep(i),
+            ReprDataPieceId::GroupInput(i) => Self::GroupInput(i),
+            ReprDataPieceId::GroupOutput(i) => Self::GroupOutput(i),
+            ReprDataPieceId::Witness(i) => Self::Witness(i),
+            ReprDataPieceId::WitnessGroupInput(i) => Self::WitnessGroupInput(i),
+            ReprDataPieceId::WitnessGroupOutput(i) => Self::WitnessGroupOutput(i),
+        }
+    }
+}
+
+// States of a vm in a snapshot. Vms blocked on pipes are not included, since the fds of ckb-script can not be built
+// outside of it.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReprVmState {
+    Runnable,
+    Terminated,
+    Wait { target_vm_id: u64, exit_code_addr: u64 },
+}
+
+impl ReprVmState {
+    pub fn new(id: u64, state: &VmState) -> Result<Self, Error> {
+        Ok(match state {
+            VmState::Runnable => Self::Runnable,
+            VmState::Terminated => Self::Terminated,
+            VmState::Wait { target_vm_id, exit_code_addr } => {
+                Self::Wait { target_vm_id: *target_vm_id, exit_code_addr: *exit_code_addr }
+            }
+            VmState::WaitForWrite(_) | VmState::WaitForRead(_) => {
+                return Err(Error::External(format!("Vm {} is blocked on a pipe, which can not be saved", id)))
+            }
+        })
+    }
+
+    pub fn to_vm_state(&self) -> VmState {
+        match *self {
+            Self::Runnable => VmState::Runnable,
+            Self::Terminated => VmState::Terminated,
+            Self::Wait { target_vm_id, exit_code_addr } => VmState::Wait { target_vm_id, exit_code_addr },
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct VmSnapshot {
+    pub id: u64,
+    pub state: ReprVmState,
+    // (address, flag, data piece, offset, length)
+    pub pages_from_source: Vec<(u64, u8, ReprDataPieceId, u64, u64)>,
+    // (address, flag, content)
+    pub dirty_pages: Vec<(u64, u8, JsonBytes)>,
+    pub version: u32,
+    pub registers: Vec<u64>,
+    pub pc: u64,
+    pub cycles: u64,
+    pub max_cycles: u64,
+    pub load_reservation_address: u64,
+}
+
+impl VmSnapshot {
+    fn new(id: u64, state: &VmState, snapshot: &Snapshot2<DataPieceId>) -> Result<Self, Error> {
+        Ok(Self {
+            id,
+            state: ReprVmState::new(id, state)?,
+            pages_from_source: snapshot
+                .pages_from_source
+                .iter()
+                .map(|(address, flag, id, offset, length)| (*address, *flag, id.into(), *offset, *length))
+                .collect(),
+            dirty_pages: snapshot
+                .dirty_pages
+                .iter()
+                .map(|(address, flag, data)| (*address, *flag, JsonBytes::from_vec(data.clone())))
+                .collect(),
+            version: snapshot.version,
+            registers: snapshot.registers.to_vec(),
+            pc: snapshot.pc,
+            cycles: snapshot.cycles,
+            max_cycles: snapshot.max_cycles,
+            load_reservation_address: snapshot.load_reservation_address,
+        })
+    }
+
+    fn to_snapshot2(&self) -> Result<Snapshot2<DataPieceId>, Error> {
+        Ok(Snapshot2 {
+            pages_from_source: self
+                .pages_from_source
+                .iter()
+                .map(|(address, flag, id, offset, length)| (*address, *flag, id.into(), *offset, *length))
+                .collect(),
+            dirty_pages: self
+                .dirty_pages
+                .iter()
+                .map(|(address, flag, data)| (*address, *flag, data.as_bytes().to_vec()))
+                .collect(),
+            version: self.version,
+            registers: self
+                .registers
+                .clone()
+                .try_into()
+                .map_err(|_| Error::Unexpected(format!("Invalid registers of vm {}", self.id)))?,
+            pc: self.pc,
+            cycles: self.cycles,
+            max_cycles: self.max_cycles,
+            load_reservation_address: self.load_reservation_address,
+        })
+    }
+}
+
+// The state of a debug session: all vms of the scheduler, cycles, and which vm is being debugged. A snapshot can be
+// taken between any two instructions of the debugged vm, and resumed later with the same transaction and script group.
+// States kept outside of the scheduler, such as files opened by the debugger syscalls, are not included.
+//
+// Pipes are not supported yet: sessions with open fds, such as pipes or fds inherited by spawned vms, can not be
+// saved, since the fds of ckb-script can not be built outside of it. See the note on snapshots in the README.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub tx_hash: H256,
+    pub script_hash: H256,
+    pub program_hash: H256,
+    // Id and code of the debugged vm.
+    pub id: u64,
+    pub code: JsonBytes,
+    pub expand_cycles: u64,
+    pub vm_cycles: BTreeMap<u64, u64>,
+    pub total_cycles: u64,
+    pub current_iteration_cycles: u64,
+    pub next_vm_id: u64,
+    pub next_fd_slot: u64,
+    pub vms: Vec<VmSnapshot>,
+    // Vms spawned, none of which inherits fds.
+    pub spawned_vms: Vec<u64>,
+    pub terminated_vms: Vec<(u64, i8)>,
+    pub instantiated_ids: Vec<u64>,
+}
+
+fn tx_data_hashes<DL>(scheduler: &Scheduler<DL>) -> (H256, H256, H256)
+where
+    DL: CellDataProvider + HeaderProvider + ExtensionProvider + Send + Sync + Clone + 'static,
+{
+    let tx_data = &scheduler.tx_data;
+    (
+        tx_data.rtx.transaction.hash().unpack(),
+        tx_data.script_group.script.calc_script_hash().unpack(),
+        blake2b_256(&tx_data.program).into(),
+    )
+}
+
+// Snapshot of a vm, taken from the running machine if it is instantiated, or the suspended one otherwise.
+pub(crate) fn vm_snapshot<DL>(scheduler: &mut Scheduler<DL>, id: u64) -> Result<Snapshot2<DataPieceId>, Error>
+where
+    DL: CellDataProvider + HeaderProvider + ExtensionProvider + Send + Sync + Clone + 'static,
+{
+    if let Some((context, vm)) = scheduler.instantiated.get_mut(&id) {
+        let sc = context.snapshot2_context().lock().map_err(|e| Error::Unexpected(e.to_string()))?;
+        sc.make_snapshot(&mut vm.machine)
+    } else if let Some(snapshot) = scheduler.suspended.get(&id) {
+        Ok(snapshot.clone())
+    } else {
+        Err(Error::Unexpected(format!("Unable to find vm {}", id)))
+    }
+}
+
+// Debug the vm of a resumed scheduler, with the cycles of the saved session.
+pub(crate) fn resume_machine<DL>(
+    scheduler: Scheduler<DL>,
+    id: u64,
+    expand_cycles: u64,
+    vm_cycles: BTreeMap<u64, u64>,
+    current_iteration_cycles: u64,
+) -> Result<MachineAssign<DL>, Error>
+where
+    DL: CellDataProvider + HeaderProvider + ExtensionProvider + Send + Sync + Clone + 'static,
+{
+    let mut machine = MachineAssign::new(id, &[], scheduler)?;
+    machine.expand_cycles = expand_cycles;
+    machine.vm_cycles = vm_cycles;
+    machine.wait()?;
+    // Resuming vms costs cycles, which are not charged in the original session.
+    machine.scheduler.current_iteration_cycles = current_iteration_cycles;
+    Ok(machine)
+}
+
+impl Snapshot {
+    pub fn new<DL>(machine: &mut MachineAssign<DL>) -> Result<Self, Error>
+    where
+        DL: CellDataProvider + HeaderProvider + ExtensionProvider + Send + Sync + Clone + 'static,
+    {
+        let code = JsonBytes::from_bytes(machine.code().clone());
+        let scheduler = &mut machine.scheduler;
+        let (tx_hash, script_hash, program_hash) = tx_data_hashes(scheduler);
+        if !scheduler.fds.is_empty() || scheduler.inherited_fd.values().any(|e| !e.is_empty()) {
+            return Err(Error::External(String::from("Vms with open fds can not be saved")));
+        }
+        let mut vms = vec![];
+        for (id, state) in scheduler.states.clone() {
+            let snapshot = vm_snapshot(scheduler, id)?;
+            vms.push(VmSnapshot::new(id, &state, &snapshot)?);
+        }
+        Ok(Self {
+            tx_hash,
+            script_hash,
+            program_hash,
+            id: machine.id,
+            code,
+            expand_cycles: machine.expand_cycles,
+            vm_cycles: machine.vm_cycles.clone(),
+            total_cycles: scheduler.consumed_cycles(),
+            current_iteration_cycles: scheduler.current_iteration_cycles,
+            next_vm_id: scheduler.next_vm_id,
+            next_fd_slot: scheduler.next_fd_slot,
+            vms,
+            spawned_vms: scheduler.inherited_fd.keys().cloned().collect(),
+            terminated_vms: scheduler.terminated_vms.iter().map(|(id, code)| (*id, *code)).collect(),
+            instantiated_ids: scheduler.instantiated.keys().cloned().collect(),
+        })
+    }
+
+    // Rebuild the debug session on a fresh scheduler created for the same transaction and script group. The returned
+    // machine is ready to execute the next instruction of the debugged vm.
+    pub fn resume<DL>(&self, scheduler: Scheduler<DL>) -> Result<MachineAssign<DL>, Error>
+    where
+        DL: CellDataProvider + HeaderProvider + ExtensionProvider + Send + Sync + Clone + 'static,
+    {
+        if tx_data_hashes(&scheduler) != (self.tx_hash.clone(), self.script_hash.clone(), self.program_hash.clone()) {
+            return Err(Error::External(String::from(
+                "The snapshot is taken from a different transaction, script group or program",
+            )));
+        }
+        let tx_data = scheduler.tx_data.clone();
+        let script_version = scheduler.script_version;
+        let syscalls_generator = scheduler.syscalls_generator.clone();
+        // The suspended state type is not exported, so it is obtained from the empty scheduler and filled in.
+        let mut full = scheduler.suspend()?;
+        full.total_cycles = self.total_cycles;
+        full.next_vm_id = self.next_vm_id;
+        full.next_fd_slot = self.next_fd_slot;
+        for e in &self.vms {
+            full.vms.push((e.id, e.state.to_vm_state(), e.to_snapshot2()?));
+        }
+        full.inherited_fd = self.spawned_vms.iter().map(|id| (*id, vec![])).collect();
+        full.terminated_vms = self.terminated_vms.clone();
+        full.instantiated_ids = self.instantiated_ids.clone();
+        let scheduler = Scheduler::resume(tx_data, script_version, syscalls_generator, full);
+        resume_machine(scheduler, self.id, self.expand_cycles, self.vm_cycles.clone(), self.current_iteration_cycles)
+    }
+
+    // Cycles consumed by all vms when the snapshot is taken.
+    pub fn cycles(&self) -> u64 {
+        let current = self.vms.iter().find(|e| e.id == self.id).map_or(0, |e| e.cycles);
+        self.total_cycles + self.current_iteration_cycles + current
+    }
+}
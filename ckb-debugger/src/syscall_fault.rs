@@ -0,0 +1,223 @@
+use crate::syscall_info::{
+    display_arg, error_code, error_name, syscall_info, syscall_name, PARTIAL_LOADING_SYSCALLS, SYSCALLS,
+};
+use ckb_vm::registers::{A0, A1, A2, A7};
+use ckb_vm::{CoreMachine, Memory};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// A value of a fault config, given as a number, or a name such as load_witness, group_input or INDEX_OUT_OF_BOUND.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum FaultValue {
+    Number(u64),
+    Name(String),
+}
+
+impl FaultValue {
+    fn number(&self) -> Option<u64> {
+        match self {
+            FaultValue::Number(n) => Some(*n),
+            FaultValue::Name(s) => match s.strip_prefix("0x") {
+                Some(hex) => u64::from_str_radix(hex, 16).ok(),
+                None => s.parse().ok(),
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for FaultValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FaultValue::Number(n) => write!(f, "{}", n),
+            FaultValue::Name(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+// Syscall faults, read from the file of --faults.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FaultConfig {
+    #[serde(default)]
+    pub faults: Vec<Fault>,
+}
+
+// A fault injected into the syscalls matched. A syscall is matched by its number or name, the pid of the vm and its
+// arguments which are values, such as index, source and field, compared by their numbers or decoded names.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fault {
+    pub syscall: FaultValue,
+    #[serde(default)]
+    pub pid: Option<u64>,
+    #[serde(default)]
+    pub args: BTreeMap<String, FaultValue>,
+    // Inject the fault into the nth syscall matched only, counting from 1.
+    #[serde(default)]
+    pub occurrence: Option<u64>,
+    // The syscall is not made, and returns the error code instead.
+    #[serde(default)]
+    pub error: Option<FaultValue>,
+    // Data loaded or read is cut to the length, as if there is no more data.
+    #[serde(default)]
+    pub truncate: Option<u64>,
+    // Bytes of the data loaded or read at the offsets are inverted.
+    #[serde(default)]
+    pub corrupt: Vec<u64>,
+}
+
+// A syscall injected with a fault on its data, waiting for the vm to run again.
+struct PendingFault {
+    pid: u64,
+    addr: u64,
+    size_addr: u64,
+    // Size of the buffer.
+    size: u64,
+    truncate: Option<u64>,
+    corrupt: Vec<u64>,
+}
+
+// Inject the faults of a config into the syscalls of the debugged vms, see MachineAssign::ecall.
+pub struct FaultInjector {
+    faults: Vec<(Fault, u64, Option<u64>)>,
+    counts: Vec<u64>,
+    pending: Vec<PendingFault>,
+    // Faults injected, in the form of pid 0 load_witness(index=2, source=group_input): error INDEX_OUT_OF_BOUND.
+    pub injected: Vec<String>,
+}
+
+impl FaultInjector {
+    pub fn new(config: FaultConfig) -> Result<Self, String> {
+        let mut faults = vec![];
+        for fault in config.faults {
+            let number = fault
+                .syscall
+                .number()
+                .or_else(|| SYSCALLS.iter().find(|e| e.name == fault.syscall.to_string()).map(|e| e.number))
+                .ok_or_else(|| format!("Unknown syscall {}", fault.syscall))?;
+            let error = match &fault.error {
+                Some(error) => Some(
+                    error
+                        .number()
+                        .or_else(|| error_code(&error.to_string()))
+                        .ok_or_else(|| format!("Unknown error {}", error))?,
+                ),
+                None => None,
+            };
+            let actions =
+                error.is_some() as usize + fault.truncate.is_some() as usize + !fault.corrupt.is_empty() as usize;
+            if actions == 0 {
+                return Err(format!("Fault of {} requires error, truncate or corrupt", fault.syscall));
+            }
+            if error.is_some() && actions > 1 {
+                return Err(format!("Fault of {} can not both return an error and change data", fault.syscall));
+            }
+            if error.is_none() && number != 2606 && !PARTIAL_LOADING_SYSCALLS.contains(&number) {
+                return Err(format!("Fault of {} can not change data, which is not loaded or read", fault.syscall));
+            }
+            let names: Vec<&str> =
+                syscall_info(number).map(|e| e.args.iter().map(|e| e.1).collect()).unwrap_or_default();
+            if let Some(name) = fault.args.keys().find(|e| !names.contains(&e.as_str())) {
+                return Err(format!("Unknown argument {} of {}", name, fault.syscall));
+            }
+            faults.push((fault, number, error));
+        }
+        let counts = vec![0; faults.len()];
+        Ok(Self { faults, counts, pending: vec![], injected: vec![] })
+    }
+
+    fn matches(fault: &Fault, number: u64, pid: u64, registers: &[u64]) -> bool {
+        if fault.pid.map(|e| e != pid).unwrap_or(false) {
+            return false;
+        }
+        let info = match syscall_info(number) {
+            Some(info) => info,
+            None => return fault.args.is_empty(),
+        };
+        fault.args.iter().all(|(name, expect)| {
+            let idx = info.args.iter().find(|e| e.1 == name).map(|e| e.0).unwrap();
+            let value = registers[idx];
+            expect.number() == Some(value) || expect.to_string() == display_arg(number, name, value)
+        })
+    }
+
+    // A syscall about to be made by a vm. Returns the error code the syscall fails with, if it is not to be made.
+    pub fn call<M>(&mut self, pid: u64, machine: &mut M) -> Result<Option<u64>, ckb_vm::Error>
+    where
+        M: CoreMachine<REG = u64>,
+    {
+        let registers = machine.registers().to_vec();
+        let number = registers[A7];
+        let mut selected = None;
+        for (i, (fault, n, _)) in self.faults.iter().enumerate() {
+            if *n != number || !Self::matches(fault, number, pid, &registers) {
+                continue;
+            }
+            self.counts[i] += 1;
+            if selected.is_none() && fault.occurrence.map(|e| e == self.counts[i]).unwrap_or(true) {
+                selected = Some(i);
+            }
+        }
+        let (fault, _, error) = match selected {
+            Some(i) => &self.faults[i],
+            None => return Ok(None),
+        };
+        let args: Vec<String> = match syscall_info(number) {
+            Some(info) => info
+                .args
+                .iter()
+                .map(|(idx, name)| format!("{}={}", name, display_arg(number, name, registers[*idx])))
+                .collect(),
+            None => vec![],
+        };
+        let call = format!("pid {} {}({})", pid, syscall_name(number), args.join(", "));
+        if let Some(error) = error {
+            let name = error_name(*error).map(String::from).unwrap_or(error.to_string());
+            self.injected.push(format!("{}: error {}", call, name));
+            return Ok(Some(*error));
+        }
+        let (addr, size_addr) =
+            if number == 2606 { (registers[A1], registers[A2]) } else { (registers[A0], registers[A1]) };
+        let mut size = machine.memory_mut().load64(&size_addr)?;
+        if let Some(truncate) = fault.truncate {
+            size = size.min(truncate);
+            machine.memory_mut().store64(&size_addr, &size)?;
+            self.injected.push(format!("{}: truncate {}", call, truncate));
+        }
+        if !fault.corrupt.is_empty() {
+            let offsets: Vec<String> = fault.corrupt.iter().map(|e| e.to_string()).collect();
+            self.injected.push(format!("{}: corrupt {}", call, offsets.join(", ")));
+        }
+        self.pending.push(PendingFault {
+            pid,
+            addr,
+            size_addr,
+            size,
+            truncate: fault.truncate,
+            corrupt: fault.corrupt.clone(),
+        });
+        Ok(None)
+    }
+
+    // The syscalls of a vm are done, since it runs again. Data of the syscalls succeeded is changed.
+    pub fn ret<M>(&mut self, pid: u64, machine: &mut M) -> Result<(), ckb_vm::Error>
+    where
+        M: CoreMachine<REG = u64>,
+    {
+        let ret = machine.registers()[A0];
+        let (done, pending) = std::mem::take(&mut self.pending).into_iter().partition(|e| e.pid == pid);
+        self.pending = pending;
+        for e in done.into_iter().filter(|_| ret == 0) {
+            let mut length = machine.memory_mut().load64(&e.size_addr)?;
+            if let Some(truncate) = e.truncate {
+                length = length.min(truncate);
+                machine.memory_mut().store64(&e.size_addr, &length)?;
+            }
+            // Partial loading stores the full length of the data, which may exceed the buffer.
+            for offset in e.corrupt.iter().filter(|offset| **offset < length.min(e.size)) {
+                let byte = machine.memory_mut().load8(&(e.addr + offset))?;
+                machine.memory_mut().store8(&(e.addr + offset), &(!byte & 0xff))?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,143 @@
+use ckb_vm::registers::{A0, A1, A2, A3, A4, A5};
+
+// Syscalls storing data in the buffer of a0 and its length in the address of a1, in the way of partial loading.
+pub const PARTIAL_LOADING_SYSCALLS: &[u64] =
+    &[2051, 2052, 2061, 2062, 2071, 2072, 2073, 2074, 2081, 2082, 2083, 2092, 2104];
+
+// A syscall and its arguments which are values, such as indices and sources. Arguments which are addresses in the
+// memory of the vm are left out, since they differ between builds of the same script.
+pub struct SyscallInfo {
+    pub number: u64,
+    pub name: &'static str,
+    pub args: &'static [(usize, &'static str)],
+}
+
+const LOAD: &[(usize, &str)] = &[(A2, "offset")];
+const LOAD_BY_INDEX: &[(usize, &str)] = &[(A2, "offset"), (A3, "index"), (A4, "source")];
+const LOAD_BY_FIELD: &[(usize, &str)] = &[(A2, "offset"), (A3, "index"), (A4, "source"), (A5, "field")];
+const LOAD_CODE: &[(usize, &str)] = &[(A4, "index"), (A5, "source")];
+const FD: &[(usize, &str)] = &[(A0, "fd")];
+
+pub const SYSCALLS: &[SyscallInfo] = &[
+    SyscallInfo { number: 93, name: "exit", args: &[(A0, "code")] },
+    SyscallInfo { number: 2041, name: "vm_version", args: &[] },
+    SyscallInfo { number: 2042, name: "current_cycles", args: &[] },
+    SyscallInfo { number: 2043, name: "exec", args: &[(A0, "index"), (A1, "source"), (A2, "place"), (A3, "bounds")] },
+    SyscallInfo { number: 2051, name: "load_transaction", args: LOAD },
+    SyscallInfo { number: 2052, name: "load_script", args: LOAD },
+    SyscallInfo { number: 2061, name: "load_tx_hash", args: LOAD },
+    SyscallInfo { number: 2062, name: "load_script_hash", args: LOAD },
+    SyscallInfo { number: 2071, name: "load_cell", args: LOAD_BY_INDEX },
+    SyscallInfo { number: 2072, name: "load_header", args: LOAD_BY_INDEX },
+    SyscallInfo { number: 2073, name: "load_input", args: LOAD_BY_INDEX },
+    SyscallInfo { number: 2074, name: "load_witness", args: LOAD_BY_INDEX },
+    SyscallInfo { number: 2081, name: "load_cell_by_field", args: LOAD_BY_FIELD },
+    SyscallInfo { number: 2082, name: "load_header_by_field", args: LOAD_BY_FIELD },
+    SyscallInfo { number: 2083, name: "load_input_by_field", args: LOAD_BY_FIELD },
+    SyscallInfo { number: 2091, name: "load_cell_data_as_code", args: LOAD_CODE },
+    SyscallInfo { number: 2092, name: "load_cell_data", args: LOAD_BY_INDEX },
+    SyscallInfo { number: 2104, name: "load_block_extension", args: LOAD_BY_INDEX },
+    SyscallInfo { number: 2177, name: "debug", args: &[] },
+    SyscallInfo { number: 2601, name: "spawn", args: &[(A0, "index"), (A1, "source"), (A2, "place"), (A3, "bounds")] },
+    SyscallInfo { number: 2602, name: "wait", args: &[(A0, "pid")] },
+    SyscallInfo { number: 2603, name: "process_id", args: &[] },
+    SyscallInfo { number: 2604, name: "pipe", args: &[] },
+    SyscallInfo { number: 2605, name: "write", args: FD },
+    SyscallInfo { number: 2606, name: "read", args: FD },
+    SyscallInfo { number: 2607, name: "inherited_fd", args: &[] },
+    SyscallInfo { number: 2608, name: "close", args: FD },
+    SyscallInfo { number: 9000, name: "debugger_read", args: &[] },
+    SyscallInfo { number: 9001, name: "debugger_now", args: &[] },
+    SyscallInfo { number: 9002, name: "debugger_random", args: &[] },
+    SyscallInfo { number: 9003, name: "debugger_fopen", args: &[] },
+    SyscallInfo { number: 9004, name: "debugger_freopen", args: &[] },
+    SyscallInfo { number: 9005, name: "debugger_fread", args: &[] },
+    SyscallInfo { number: 9006, name: "debugger_feof", args: &[] },
+    SyscallInfo { number: 9007, name: "debugger_ferror", args: &[] },
+    SyscallInfo { number: 9008, name: "debugger_fgetc", args: &[] },
+    SyscallInfo { number: 9009, name: "debugger_fclose", args: &[] },
+    SyscallInfo { number: 9010, name: "debugger_ftell", args: &[] },
+    SyscallInfo { number: 9011, name: "debugger_fseek", args: &[] },
+];
+
+pub fn syscall_info(number: u64) -> Option<&'static SyscallInfo> {
+    SYSCALLS.iter().find(|e| e.number == number)
+}
+
+// Name of a syscall, or its number if it is unknown.
+pub fn syscall_name(number: u64) -> String {
+    match syscall_info(number) {
+        Some(info) => info.name.to_string(),
+        None => format!("syscall_{}", number),
+    }
+}
+
+// Name of a source, such as group_input.
+pub fn source_name(source: u64) -> Option<&'static str> {
+    match source {
+        1 => Some("input"),
+        2 => Some("output"),
+        3 => Some("cell_dep"),
+        4 => Some("header_dep"),
+        0x0100_0000_0000_0001 => Some("group_input"),
+        0x0100_0000_0000_0002 => Some("group_output"),
+        _ => None,
+    }
+}
+
+// Name of a field loaded by load_cell_by_field, load_header_by_field or load_input_by_field.
+pub fn field_name(number: u64, field: u64) -> Option<&'static str> {
+    let fields: &[&str] = match number {
+        2081 => &["capacity", "data_hash", "lock", "lock_hash", "type", "type_hash", "occupied_capacity"],
+        2082 => &["epoch_number", "epoch_start_block_number", "epoch_length"],
+        2083 => &["out_point", "since"],
+        _ => &[],
+    };
+    fields.get(field as usize).copied()
+}
+
+// Error codes returned by syscalls, indexed by the codes.
+const ERRORS: &[&str] = &[
+    "SUCCESS",
+    "INDEX_OUT_OF_BOUND",
+    "ITEM_MISSING",
+    "SLICE_OUT_OF_BOUND",
+    "WRONG_FORMAT",
+    "WAIT_FAILURE",
+    "INVALID_FD",
+    "OTHER_END_CLOSED",
+    "MAX_VMS_SPAWNED",
+    "MAX_FDS_CREATED",
+];
+
+// Name of an error code returned by a syscall.
+pub fn error_name(code: u64) -> Option<&'static str> {
+    ERRORS.get(code as usize).copied()
+}
+
+pub fn error_code(name: &str) -> Option<u64> {
+    ERRORS.iter().position(|e| *e == name).map(|e| e as u64)
+}
+
+// An argument of a syscall in text, with sources, fields and places decoded. Other values are in hex if they are large.
+pub fn display_arg(number: u64, name: &str, value: u64) -> String {
+    if name == "code" {
+        return (value as i8).to_string();
+    }
+    let decoded = match name {
+        "source" => source_name(value),
+        "field" => field_name(number, value),
+        "place" => ["cell_data", "witness"].get(value as usize).copied(),
+        _ => None,
+    };
+    match decoded {
+        Some(decoded) => decoded.to_string(),
+        None if value > 0xffff => format!("{:#x}", value),
+        None => value.to_string(),
+    }
+}
+
+// Syscalls returning error codes, such as INDEX_OUT_OF_BOUND, rather than values.
+pub fn returns_error(number: u64) -> bool {
+    matches!(number, 2043 | 2051..=2104 | 2601 | 2602 | 2604..=2608)
+}
@@ -0,0 +1,160 @@
+use crate::syscall_info::{
+    display_arg, error_name, returns_error, syscall_info, syscall_name, PARTIAL_LOADING_SYSCALLS,
+};
+use ckb_vm::registers::{A0, A1, A2, A7};
+use ckb_vm::{CoreMachine, Memory};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::io::Write;
+
+// A syscall made by a vm, with its arguments decoded and its results.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct SyscallRecord {
+    pub pid: u64,
+    pub number: u64,
+    pub name: String,
+    // Arguments which are values, such as source=group_input.
+    pub args: Vec<(String, String)>,
+    // Value of a0 when the vm runs again, or none if it does not, such as after exit.
+    pub ret: Option<u64>,
+    // Name of the error code returned, if the syscall fails.
+    pub error: Option<String>,
+    // Size of the buffer of a syscall storing data, or the length of data to write, and the length stored back on
+    // success. The length is the full length of the data for partial loading, which may exceed the size.
+    pub size: Option<u64>,
+    pub length: Option<u64>,
+    // Cycles consumed by all vms before the syscall.
+    pub cycles: u64,
+    pub backtrace: Vec<String>,
+}
+
+impl std::fmt::Display for SyscallRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let args: Vec<String> = self.args.iter().map(|(name, value)| format!("{}={}", name, value)).collect();
+        let ret = self.ret.map(|e| e.to_string()).unwrap_or(String::from("?"));
+        write!(f, "{:>10} pid {} {}({}) = {}", self.cycles, self.pid, self.name, args.join(", "), ret)?;
+        if let Some(error) = &self.error {
+            write!(f, " ({})", error)?;
+        }
+        if let Some(size) = self.size {
+            write!(f, ", size {}", size)?;
+        }
+        if let Some(length) = self.length {
+            write!(f, ", length {}", length)?;
+        }
+        Ok(())
+    }
+}
+
+// A syscall waiting for the vm to run again, with the address of the size of its buffer if it has one.
+struct PendingSyscall {
+    record: SyscallRecord,
+    size_addr: Option<u64>,
+    done: bool,
+}
+
+// Syscalls of the debugged vms, written in the order of calls as text or JSON lines. Syscalls yielding to the
+// scheduler, such as spawn, read and wait, are done when the vm runs again, by then other vms may make syscalls.
+pub struct SyscallTrace {
+    writer: Box<dyn Write>,
+    json: bool,
+    pending: VecDeque<PendingSyscall>,
+    // Backtrace of the next syscall, set by the profiler before the ecall is executed.
+    pub backtrace: Vec<String>,
+    count: u64,
+}
+
+impl SyscallTrace {
+    pub fn new(writer: Box<dyn Write>, json: bool) -> Self {
+        Self { writer, json, pending: VecDeque::new(), backtrace: vec![], count: 0 }
+    }
+
+    fn write(&mut self, record: &SyscallRecord) -> Result<(), std::io::Error> {
+        if self.json {
+            serde_json::to_writer(&mut self.writer, record)?;
+            writeln!(self.writer)?;
+        } else {
+            writeln!(self.writer, "{}", record)?;
+            for line in &record.backtrace {
+                writeln!(self.writer, "  {}", line)?;
+            }
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    fn write_done(&mut self) -> Result<(), std::io::Error> {
+        while self.pending.front().map(|e| e.done).unwrap_or(false) {
+            let e = self.pending.pop_front().unwrap();
+            self.write(&e.record)?;
+        }
+        Ok(())
+    }
+
+    // A syscall about to be made by a vm.
+    pub fn call<M>(&mut self, pid: u64, cycles: u64, machine: &mut M) -> Result<(), std::io::Error>
+    where
+        M: CoreMachine<REG = u64>,
+    {
+        let registers = machine.registers().to_vec();
+        let number = registers[A7];
+        let args = match syscall_info(number) {
+            Some(info) => info
+                .args
+                .iter()
+                .map(|(idx, name)| (name.to_string(), display_arg(number, name, registers[*idx])))
+                .collect(),
+            None => vec![],
+        };
+        let size_addr = match number {
+            2605 | 2606 => Some(registers[A2]),
+            _ if PARTIAL_LOADING_SYSCALLS.contains(&number) => Some(registers[A1]),
+            _ => None,
+        };
+        let record = SyscallRecord {
+            pid,
+            number,
+            name: syscall_name(number),
+            args,
+            size: size_addr.and_then(|addr| machine.memory_mut().load64(&addr).ok()),
+            cycles,
+            backtrace: std::mem::take(&mut self.backtrace),
+            ..Default::default()
+        };
+        // Exit never returns.
+        self.pending.push_back(PendingSyscall { record, size_addr, done: number == 93 });
+        self.write_done()
+    }
+
+    // The syscalls of a vm are done, since it runs again.
+    pub fn ret<M>(&mut self, pid: u64, machine: &mut M) -> Result<(), std::io::Error>
+    where
+        M: CoreMachine<REG = u64>,
+    {
+        let ret = machine.registers()[A0];
+        for e in self.pending.iter_mut().filter(|e| !e.done && e.record.pid == pid) {
+            e.done = true;
+            // Debug returns nothing, a0 is still the address of the message.
+            if e.record.number == 2177 {
+                continue;
+            }
+            e.record.ret = Some(ret);
+            if ret != 0 && returns_error(e.record.number) {
+                e.record.error = Some(error_name(ret).map(String::from).unwrap_or(format!("error {}", ret)));
+            }
+            if let (Some(addr), 0) = (e.size_addr, ret) {
+                e.record.length = machine.memory_mut().load64(&addr).ok();
+            }
+        }
+        self.write_done()
+    }
+
+    // Write the syscalls not done yet, since the run ends. Returns the number of syscalls written.
+    pub fn finish(mut self) -> Result<u64, std::io::Error> {
+        while let Some(e) = self.pending.pop_front() {
+            self.write(&e.record)?;
+        }
+        self.writer.flush()?;
+        Ok(self.count)
+    }
+}
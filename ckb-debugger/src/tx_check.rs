@@ -0,0 +1,285 @@
+use crate::chain_env::ChainEnv;
+use crate::lint::{LintIssue, LintSeverity};
+use ckb_dao_utils::extract_dao_data;
+use ckb_mock_tx_types::MockTransaction;
+use ckb_types::core::{Capacity, EpochNumberWithFraction, HeaderView, ScriptHashType};
+use ckb_types::packed::{Byte32, CellOutput, WitnessArgs};
+use ckb_types::prelude::{Entity, Pack, Unpack};
+use ckb_types::{h256, H256};
+use std::collections::{HashMap, HashSet};
+
+// Type hash of the Nervos DAO script, same on the mainnet and the testnet.
+pub const DAO_TYPE_HASH: H256 = h256!("0x82d76d1b75fe2fd9a27dfbaa65a039221a380d76c926f378d3f81cf3e7e13f2e");
+// Deposited cells are locked in cycles of 180 epochs.
+const DAO_LOCK_PERIOD_EPOCHS: u64 = 180;
+
+const SINCE_RELATIVE_FLAG: u64 = 0x8000_0000_0000_0000;
+const SINCE_METRIC_MASK: u64 = 0x6000_0000_0000_0000;
+const SINCE_METRIC_BLOCK_NUMBER: u64 = 0x0000_0000_0000_0000;
+const SINCE_METRIC_EPOCH: u64 = 0x2000_0000_0000_0000;
+const SINCE_METRIC_TIMESTAMP: u64 = 0x4000_0000_0000_0000;
+const SINCE_REMAIN_FLAGS_MASK: u64 = 0x1f00_0000_0000_0000;
+const SINCE_VALUE_MASK: u64 = 0x00ff_ffff_ffff_ffff;
+
+fn error(path: String, message: String) -> LintIssue {
+    LintIssue { severity: LintSeverity::Error, path, message }
+}
+
+fn is_dao(output: &CellOutput) -> bool {
+    output.type_().to_opt().map_or(false, |script| {
+        script.code_hash() == DAO_TYPE_HASH.pack() && script.hash_type() == ScriptHashType::Type.into()
+    })
+}
+
+struct Checker<'a> {
+    mock_tx: &'a MockTransaction,
+    env: &'a ChainEnv,
+    headers: HashMap<Byte32, HeaderView>,
+    issues: Vec<LintIssue>,
+}
+
+impl<'a> Checker<'a> {
+    // Header of the block that the input cell is committed in. It is only available if it is in header deps, otherwise
+    // an issue of the severity is reported.
+    fn input_header(&mut self, i: usize, severity: LintSeverity, reason: &str) -> Option<HeaderView> {
+        let path = format!("$.mock_info.inputs[{}].header", i);
+        let message = match &self.mock_tx.mock_info.inputs[i].header {
+            None => format!("is missing, {}", reason),
+            Some(hash) => match self.headers.get(hash) {
+                Some(header) => return Some(header.clone()),
+                None => format!("block {:#x} is not in $.tx.header_deps, {}", hash, reason),
+            },
+        };
+        self.issues.push(LintIssue { severity, path, message });
+        None
+    }
+
+    fn check_since(&mut self) {
+        let tx = self.mock_tx.core_transaction();
+        let tip_epoch = self.env.epoch();
+        for (i, input) in tx.inputs().into_iter().enumerate() {
+            let since: u64 = input.since().unpack();
+            if since == 0 {
+                continue;
+            }
+            let path = format!("$.tx.inputs[{}].since", i);
+            if since & SINCE_REMAIN_FLAGS_MASK != 0 || since & SINCE_METRIC_MASK == SINCE_METRIC_MASK {
+                self.issues.push(error(path, format!("{:#x} has invalid flags", since)));
+                continue;
+            }
+            let value = since & SINCE_VALUE_MASK;
+            let relative = since & SINCE_RELATIVE_FLAG != 0;
+            // A node checks a relative since by the transaction info of the input cell, which does not need header
+            // deps. The debugger only knows the header from them, so the since is not checked without it.
+            let base = if relative {
+                match self.input_header(i, LintSeverity::Warning, "the relative since is not checked without it") {
+                    Some(header) => Some(header),
+                    None => continue,
+                }
+            } else {
+                None
+            };
+            let satisfied = match since & SINCE_METRIC_MASK {
+                SINCE_METRIC_BLOCK_NUMBER => {
+                    let base_number = base.as_ref().map_or(0, |e| e.number());
+                    base_number.checked_add(value).map_or(false, |e| self.env.block_number >= e)
+                }
+                SINCE_METRIC_EPOCH => {
+                    let epoch = EpochNumberWithFraction::from_full_value_unchecked(value);
+                    let well_formed = if relative { epoch.is_well_formed_increment() } else { epoch.is_well_formed() };
+                    if !well_formed {
+                        self.issues.push(error(path, format!("{:#x} has an invalid epoch", since)));
+                        continue;
+                    }
+                    let target = match &base {
+                        Some(base) if !base.epoch().is_well_formed() => {
+                            let message = format!("block {:#x} has an invalid epoch", base.hash());
+                            self.issues.push(error(format!("$.mock_info.inputs[{}].header", i), message));
+                            continue;
+                        }
+                        Some(base) => base.epoch().to_rational() + epoch.to_rational(),
+                        None => epoch.to_rational(),
+                    };
+                    tip_epoch.to_rational() >= target
+                }
+                SINCE_METRIC_TIMESTAMP => {
+                    // The node compares with the median time of the past 37 blocks. Here the timestamp of the
+                    // environment is used as the median time.
+                    let base_timestamp = base.as_ref().map_or(0, |e| e.timestamp());
+                    value
+                        .checked_mul(1000)
+                        .and_then(|e| e.checked_add(base_timestamp))
+                        .map_or(false, |e| self.env.timestamp >= e)
+                }
+                _ => unreachable!(),
+            };
+            if !satisfied {
+                self.issues.push(error(path, format!("{:#x} is immature in the current environment", since)));
+            }
+        }
+    }
+
+    // Returns the maximum capacity that a withdrawing dao cell can be withdrawn, or None if the cell is not a valid
+    // withdrawing cell.
+    fn check_dao_withdraw(&mut self, i: usize) -> Option<Capacity> {
+        let tx = self.mock_tx.core_transaction();
+        let mock_input = &self.mock_tx.mock_info.inputs[i];
+        let withdrawing_header =
+            self.input_header(i, LintSeverity::Error, "which is required to withdraw from the dao")?;
+        let path = format!("$.tx.witnesses[{}]", i);
+        let witness = tx.witnesses().get(i).map(|e| e.raw_data()).unwrap_or_default();
+        let index = WitnessArgs::from_slice(&witness)
+            .ok()
+            .and_then(|e| e.input_type().to_opt())
+            .map(|e| e.raw_data())
+            .filter(|e| e.len() == 8)
+            .map(|e| u64::from_le_bytes(e[..].try_into().unwrap()));
+        let index = match index {
+            Some(index) => index,
+            None => {
+                let message = String::from("input_type should be the index of the deposit header in header deps");
+                self.issues.push(error(path, message));
+                return None;
+            }
+        };
+        let deposit_header = match tx.header_deps().get(index as usize).and_then(|e| self.headers.get(&e).cloned()) {
+            Some(header) => header,
+            None => {
+                self.issues.push(error(path, format!("header dep {} of the deposit block is missing", index)));
+                return None;
+            }
+        };
+        let deposit_number = u64::from_le_bytes(mock_input.data[0..8].try_into().unwrap());
+        if deposit_header.number() != deposit_number {
+            let message = format!(
+                "block number {} of the deposit header dep {} mismatches the cell data {}",
+                deposit_header.number(),
+                index,
+                deposit_number
+            );
+            self.issues.push(error(path, message));
+            return None;
+        }
+        let deposit_epoch = deposit_header.epoch();
+        let (deposit_ar, _, _, _) = extract_dao_data(deposit_header.dao());
+        let path = format!("$.tx.header_deps[{}]", index);
+        if !deposit_epoch.is_well_formed() {
+            self.issues.push(error(path, format!("epoch {} of the deposit header is invalid", deposit_epoch)));
+            return None;
+        }
+        if deposit_ar == 0 {
+            self.issues.push(error(path, String::from("accumulate rate of the deposit header is zero")));
+            return None;
+        }
+
+        // The withdrawing cell must be locked until the end of the current lock period.
+        let withdrawing_epoch = withdrawing_header.epoch();
+        let mut deposited_epochs = withdrawing_epoch.number().saturating_sub(deposit_epoch.number());
+        if withdrawing_epoch.index() * deposit_epoch.length() > deposit_epoch.index() * withdrawing_epoch.length() {
+            deposited_epochs += 1;
+        }
+        let lock_epochs = deposited_epochs.div_ceil(DAO_LOCK_PERIOD_EPOCHS) * DAO_LOCK_PERIOD_EPOCHS;
+        let minimal_since = EpochNumberWithFraction::new(
+            deposit_epoch.number() + lock_epochs,
+            deposit_epoch.index(),
+            deposit_epoch.length(),
+        );
+        let since: u64 = tx.inputs().get(i).unwrap().since().unpack();
+        let since_epoch = EpochNumberWithFraction::from_full_value_unchecked(since & SINCE_VALUE_MASK);
+        if since & (SINCE_RELATIVE_FLAG | SINCE_METRIC_MASK) != SINCE_METRIC_EPOCH
+            || !since_epoch.is_well_formed()
+            || since_epoch.to_rational() < minimal_since.to_rational()
+        {
+            let message = format!("should be an absolute epoch no less than {}", minimal_since);
+            self.issues.push(error(format!("$.tx.inputs[{}].since", i), message));
+        }
+
+        let output = &mock_input.output;
+        let capacity: Capacity = output.capacity().unpack();
+        let occupied = output.occupied_capacity(Capacity::bytes(mock_input.data.len()).ok()?).ok()?;
+        let counted = capacity.safe_sub(occupied).ok()?;
+        let (withdrawing_ar, _, _, _) = extract_dao_data(withdrawing_header.dao());
+        let withdraw = u128::from(counted.as_u64()) * u128::from(withdrawing_ar) / u128::from(deposit_ar);
+        Capacity::shannons(u64::try_from(withdraw).ok()?).safe_add(occupied).ok()
+    }
+
+    fn check_dao_deposit(&mut self, i: usize) {
+        let tx = self.mock_tx.core_transaction();
+        // A deposited cell is withdrawn to a withdrawing cell at the same index.
+        if !tx.outputs().get(i).map_or(false, |e| is_dao(&e)) {
+            return;
+        }
+        let deposit_header =
+            match self.input_header(i, LintSeverity::Error, "which is required to withdraw from the dao") {
+                Some(header) => header,
+                None => return,
+            };
+        let data = tx.outputs_data().get(i).map(|e| e.raw_data()).unwrap_or_default();
+        if data.len() != 8 || u64::from_le_bytes(data[..].try_into().unwrap()) != deposit_header.number() {
+            let message = format!("should be the deposit block number {} in little endian", deposit_header.number());
+            self.issues.push(error(format!("$.tx.outputs_data[{}]", i), message));
+        }
+    }
+
+    fn check_capacity(&mut self) {
+        let tx = self.mock_tx.core_transaction();
+        let mut inputs_capacity = Capacity::zero();
+        for (i, mock_input) in self.mock_tx.mock_info.inputs.iter().enumerate() {
+            let capacity = if is_dao(&mock_input.output) && mock_input.data.len() == 8 {
+                if mock_input.data[..] == [0; 8] {
+                    self.check_dao_deposit(i);
+                    mock_input.output.capacity().unpack()
+                } else {
+                    match self.check_dao_withdraw(i) {
+                        Some(capacity) => capacity,
+                        None => mock_input.output.capacity().unpack(),
+                    }
+                }
+            } else {
+                mock_input.output.capacity().unpack()
+            };
+            inputs_capacity = match inputs_capacity.safe_add(capacity) {
+                Ok(e) => e,
+                Err(_) => {
+                    self.issues.push(error(String::from("$.mock_info.inputs"), String::from("capacity overflow")));
+                    return;
+                }
+            };
+        }
+        let mut outputs_capacity = Capacity::zero();
+        for output in tx.outputs().into_iter() {
+            let capacity: Capacity = output.capacity().unpack();
+            outputs_capacity = match outputs_capacity.safe_add(capacity) {
+                Ok(e) => e,
+                Err(_) => {
+                    self.issues.push(error(String::from("$.tx.outputs"), String::from("capacity overflow")));
+                    return;
+                }
+            };
+        }
+        if outputs_capacity > inputs_capacity {
+            let message = format!(
+                "outputs capacity {} shannons exceeds inputs capacity {} shannons",
+                outputs_capacity, inputs_capacity
+            );
+            self.issues.push(error(String::from("$.tx.outputs"), message));
+        }
+    }
+}
+
+// Run the checks a ckb node does on a transaction besides scripts, which can be done offline in the given chain
+// environment: capacity balance, since of the inputs, and the rules of the Nervos DAO on header deps.
+pub fn check_transaction(mock_tx: &MockTransaction, env: &ChainEnv) -> Vec<LintIssue> {
+    let headers = mock_tx.mock_info.header_deps.iter().map(|e| (e.hash(), e.clone())).collect();
+    let mut checker = Checker { mock_tx, env, headers, issues: vec![] };
+    if mock_tx.mock_info.inputs.len() != mock_tx.tx.raw().inputs().len() {
+        checker.issues.push(error(String::from("$.mock_info.inputs"), String::from("mismatches $.tx.inputs")));
+        return checker.issues;
+    }
+    checker.check_capacity();
+    checker.check_since();
+    // The same missing header may be reported by several checks.
+    let mut seen = HashSet::new();
+    checker.issues.retain(|e| seen.insert(e.clone()));
+    checker.issues
+}
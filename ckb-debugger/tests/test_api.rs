@@ -1,4 +1,6 @@
-use ckb_mock_tx_types::{MockTransaction, ReprMockTransaction};
+use ckb_debugger::LintSeverity;
+use ckb_jsonrpc_types::DepType;
+use ckb_mock_tx_types::{build_mock_tx, FixtureResourceLoader, MockTransaction, ReprMockTransaction, Unresolved};
 
 #[test]
 pub fn test_run_json() {
@@ -10,3 +12,1169 @@ pub fn test_run_json() {
     let result = ckb_debugger::run_json(&mock_tx_repr_str, "lock", &script_hash_hex, "70000000");
     assert_eq!(result, "{\"cycle\":1641938,\"error\":null}");
 }
+
+#[test]
+pub fn test_run_with_env() {
+    let mock_tx_repr_str = std::fs::read_to_string("examples/spawn.json").unwrap();
+    let mock_tx_repr_str =
+        ckb_debugger::Embed::new(std::path::PathBuf::from("examples/spawn.json"), mock_tx_repr_str).replace_all();
+    let mock_tx_repr: ReprMockTransaction = serde_json::from_str(&mock_tx_repr_str).unwrap();
+    let mock_tx: MockTransaction = mock_tx_repr.into();
+    let script_group_type = ckb_script::ScriptGroupType::Lock;
+    let script_hash = ckb_debugger::get_script_hash_by_index(&mock_tx, &script_group_type, "input", 0);
+    let mut env = ckb_debugger::ChainEnv::default();
+    env.ckb2023 = Some(10);
+    env.epoch = 9;
+    assert!(ckb_debugger::run_with_env(&mock_tx, &script_group_type, &script_hash, 70000000, &env).is_err());
+    env.epoch = 10;
+    assert!(ckb_debugger::run_with_env(&mock_tx, &script_group_type, &script_hash, 70000000, &env).is_ok());
+
+    // Only vm versions are moved from the hardforks of mainnet.
+    use ckb_types::core::hardfork::{CKB2021, CKB2023};
+    let hardforks = ckb_debugger::ChainEnv::with_script_version(ckb_script::ScriptVersion::V2).hardforks().unwrap();
+    let ckb2021 = CKB2021::new_mirana().as_builder().rfc_0032(20).build().unwrap();
+    let ckb2023 = CKB2023::new_mirana().as_builder().rfc_0049(30).build().unwrap();
+    assert_eq!(format!("{:?}", hardforks.ckb2021), format!("{:?}", ckb2021));
+    assert_eq!(format!("{:?}", hardforks.ckb2023), format!("{:?}", ckb2023));
+}
+
+#[test]
+pub fn test_build_mock_tx() {
+    let mock_tx_repr_str = std::fs::read_to_string("examples/mock_tx.json").unwrap();
+    let mock_tx_repr: ReprMockTransaction = serde_json::from_str(&mock_tx_repr_str).unwrap();
+    let mock_tx: MockTransaction = mock_tx_repr.clone().into();
+    let fixtures = std::env::temp_dir().join(format!("ckb-debugger-test-build-mock-tx-{}", std::process::id()));
+    let mut loader = FixtureResourceLoader::new(fixtures.join("cells"), fixtures.join("headers"));
+    for e in &mock_tx.mock_info.inputs {
+        loader.save_cell(&e.input.previous_output(), &e.output, &e.data, e.header.as_ref()).unwrap();
+    }
+    for e in &mock_tx.mock_info.cell_deps {
+        loader.save_cell(&e.cell_dep.out_point(), &e.output, &e.data, e.header.as_ref()).unwrap();
+    }
+
+    let (built, unresolved) = build_mock_tx(mock_tx_repr.tx.clone(), &mut loader).unwrap();
+    assert!(unresolved.is_empty());
+    assert!(ckb_debugger::pre_check(&built.clone().into()).is_ok());
+    let script_group_type = ckb_script::ScriptGroupType::Lock;
+    let script_hash = ckb_debugger::get_script_hash_by_index(&built, &script_group_type, "input", 0);
+    assert_eq!(ckb_debugger::run(&built, &script_group_type, &script_hash, 70000000).unwrap(), 1641938);
+
+    let mut tx = mock_tx_repr.tx.clone();
+    tx.header_deps.push(ckb_types::H256::default());
+    tx.cell_deps[1].out_point.index = 9.into();
+    let (_, unresolved) = build_mock_tx(tx, &mut loader).unwrap();
+    assert_eq!(unresolved.len(), 2);
+    assert!(matches!(unresolved[0], Unresolved::CellDep(_)));
+    assert_eq!(unresolved[1], Unresolved::Header(ckb_types::H256::default()));
+    std::fs::remove_dir_all(&fixtures).unwrap();
+}
+
+#[test]
+pub fn test_lint() {
+    let mock_tx_repr_str = std::fs::read_to_string("examples/mock_tx.json").unwrap();
+    let mock_tx_repr: ReprMockTransaction = serde_json::from_str(&mock_tx_repr_str).unwrap();
+    let issues = ckb_debugger::lint(&mock_tx_repr);
+    assert!(issues.iter().all(|e| e.severity == LintSeverity::Warning));
+
+    let mut tx = mock_tx_repr.clone();
+    tx.tx.inputs.push(tx.tx.inputs[0].clone());
+    tx.mock_info.inputs.push(tx.mock_info.inputs[0].clone());
+    tx.tx.outputs[0].capacity = 1.into();
+    let group = tx.mock_info.cell_deps.iter_mut().find(|e| e.cell_dep.dep_type == DepType::DepGroup).unwrap();
+    group.data = ckb_jsonrpc_types::JsonBytes::from_vec(vec![1, 2, 3]);
+    let issues: Vec<(LintSeverity, String)> =
+        ckb_debugger::lint(&tx).into_iter().map(|e| (e.severity, e.path)).collect();
+    assert!(issues.contains(&(LintSeverity::Error, String::from("$.tx.inputs[2].previous_output"))));
+    assert!(issues.contains(&(LintSeverity::Error, String::from("$.tx.outputs[0].capacity"))));
+    assert!(issues.contains(&(LintSeverity::Error, String::from("$.mock_info.cell_deps[2].data"))));
+    assert!(issues.contains(&(LintSeverity::Error, String::from("$.mock_info.inputs[0].output.lock.code_hash"))));
+    assert!(ckb_debugger::pre_check(&tx).is_err());
+}
+
+#[test]
+pub fn test_check_transaction_since() {
+    let mock_tx_repr_str = std::fs::read_to_string("examples/mock_tx.json").unwrap();
+    let mock_tx_repr: ReprMockTransaction = serde_json::from_str(&mock_tx_repr_str).unwrap();
+    let mut env = ckb_debugger::ChainEnv::default();
+    assert!(ckb_debugger::check_transaction(&mock_tx_repr.clone().into(), &env).is_empty());
+
+    let mut tx = mock_tx_repr.clone();
+    tx.tx.inputs[0].since = 100.into();
+    env.block_number = 99;
+    let issues = ckb_debugger::check_transaction(&tx.clone().into(), &env);
+    assert_eq!(issues.len(), 1);
+    assert_eq!(issues[0].path, "$.tx.inputs[0].since");
+    env.block_number = 100;
+    assert!(ckb_debugger::check_transaction(&tx.clone().into(), &env).is_empty());
+
+    // A relative since can not be checked without the header of the input cell, which a node does not need.
+    tx.tx.inputs[0].since = (0xa000_0000_0000_0000u64 | 1).into();
+    let issues = ckb_debugger::check_transaction(&tx.clone().into(), &env);
+    assert_eq!(issues.len(), 1);
+    assert_eq!(issues[0].path, "$.mock_info.inputs[0].header");
+    assert_eq!(issues[0].severity, LintSeverity::Warning);
+
+    // A header with an invalid epoch is reported instead of being the base of the since.
+    use ckb_types::core::{EpochNumberWithFraction, HeaderView};
+    use ckb_types::prelude::*;
+    let mut mock_tx: MockTransaction = tx.clone().into();
+    let header =
+        HeaderView::new_advanced_builder().epoch(EpochNumberWithFraction::new_unchecked(0, 1, 0).pack()).build();
+    mock_tx.mock_info.inputs[0].header = Some(header.hash());
+    mock_tx.mock_info.header_deps.push(header);
+    let issues = ckb_debugger::check_transaction(&mock_tx, &env);
+    assert_eq!(issues.len(), 1);
+    assert_eq!(issues[0].path, "$.mock_info.inputs[0].header");
+    assert_eq!(issues[0].severity, LintSeverity::Error);
+}
+
+#[test]
+pub fn test_check_transaction_dao() {
+    use ckb_types::core::{Capacity, EpochNumberWithFraction, HeaderView, ScriptHashType, TransactionBuilder};
+    use ckb_types::packed::{CellInput, CellOutput, OutPoint, Script, WitnessArgs};
+    use ckb_types::prelude::*;
+
+    let dao_header = |number: u64, epoch: u64, ar: u64| {
+        let dao = ckb_dao_utils::pack_dao_data(ar, Capacity::zero(), Capacity::zero(), Capacity::zero());
+        HeaderView::new_advanced_builder()
+            .number(number.pack())
+            .epoch(EpochNumberWithFraction::new(epoch, 0, 1000).pack())
+            .dao(dao)
+            .build()
+    };
+    let deposit_header = dao_header(1000, 10, 10_000_000_000_000_000);
+    let withdrawing_header = dao_header(2000, 20, 11_000_000_000_000_000);
+    let dao_script = Script::new_builder()
+        .code_hash(ckb_debugger::DAO_TYPE_HASH.pack())
+        .hash_type(ScriptHashType::Type.into())
+        .build();
+    let output = CellOutput::new_builder()
+        .capacity(Capacity::shannons(100_000_000_000).pack())
+        .type_(Some(dao_script.clone()).pack())
+        .build();
+    let occupied = output.occupied_capacity(Capacity::bytes(8).unwrap()).unwrap().as_u64();
+    let maximum = (100_000_000_000 - occupied) * 11 / 10 + occupied;
+    let witness = WitnessArgs::new_builder()
+        .input_type(ckb_types::packed::BytesOpt::new_builder().set(Some(0u64.to_le_bytes().to_vec().pack())).build())
+        .build();
+    let build = |deposit_header: &HeaderView, since: u64, capacity: u64| {
+        let tx = TransactionBuilder::default()
+            .input(CellInput::new(OutPoint::default(), since))
+            .output(CellOutput::new_builder().capacity(Capacity::shannons(capacity).pack()).build())
+            .output_data(Default::default())
+            .header_dep(deposit_header.hash())
+            .header_dep(withdrawing_header.hash())
+            .witness(witness.as_bytes().pack())
+            .build();
+        let mut mock_tx = MockTransaction::default();
+        mock_tx.tx = tx.data();
+        mock_tx.mock_info.inputs.push(ckb_mock_tx_types::MockInput {
+            input: tx.inputs().get(0).unwrap(),
+            output: output.clone(),
+            data: 1000u64.to_le_bytes().to_vec().into(),
+            header: Some(withdrawing_header.hash()),
+        });
+        mock_tx.mock_info.header_deps = vec![deposit_header.clone(), withdrawing_header.clone()];
+        mock_tx
+    };
+    let mut env = ckb_debugger::ChainEnv::default();
+    env.epoch = 200;
+    let since = 0x2000_0000_0000_0000u64 | EpochNumberWithFraction::new(190, 0, 1000).full_value();
+    assert!(ckb_debugger::check_transaction(&build(&deposit_header, since, maximum), &env).is_empty());
+    let issues = ckb_debugger::check_transaction(&build(&deposit_header, since, maximum + 1), &env);
+    assert_eq!(issues.len(), 1);
+    assert_eq!(issues[0].path, "$.tx.outputs");
+    // Deposit headers with a zero accumulate rate or epoch, such as mock headers of default values, are reported.
+    for header in [
+        dao_header(1000, 10, 0),
+        deposit_header
+            .data()
+            .as_builder()
+            .raw(deposit_header.data().raw().as_builder().epoch(0.pack()).build())
+            .build()
+            .into_view(),
+    ] {
+        let issues = ckb_debugger::check_transaction(&build(&header, since, maximum), &env);
+        assert!(!issues.is_empty());
+        assert!(issues.iter().any(|e| e.path == "$.tx.header_deps[0]"));
+    }
+    let since = 0x2000_0000_0000_0000u64 | EpochNumberWithFraction::new(189, 999, 1000).full_value();
+    let issues = ckb_debugger::check_transaction(&build(&deposit_header, since, maximum), &env);
+    assert_eq!(issues.len(), 1);
+    assert_eq!(issues[0].path, "$.tx.inputs[0].since");
+    let since = 0x2000_0000_0000_0000u64 | EpochNumberWithFraction::new_unchecked(190, 1, 0).full_value();
+    let issues = ckb_debugger::check_transaction(&build(&deposit_header, since, maximum), &env);
+    assert!(!issues.is_empty());
+    assert!(issues.iter().all(|e| e.path == "$.tx.inputs[0].since"));
+}
+
+fn spawn_verifier() -> (
+    ckb_script::TransactionScriptsVerifier<ckb_mock_tx_types::Resource>,
+    ckb_script::ScriptGroup,
+    ckb_script::ScriptVersion,
+) {
+    tx_verifier("examples/spawn.json")
+}
+
+// Analyzer of all vms of a script group, booted and ready to run.
+fn boot_analyzer(
+    verifier: &ckb_script::TransactionScriptsVerifier<ckb_mock_tx_types::Resource>,
+    script_group: &ckb_script::ScriptGroup,
+) -> ckb_debugger::MachineAnalyzer<ckb_mock_tx_types::Resource> {
+    let scheduler = verifier.create_scheduler(script_group).unwrap();
+    ckb_debugger::MachineAnalyzer::boot(scheduler, &[], 70000000).unwrap()
+}
+
+// Verifier of the lock of the first input of a mock tx.
+fn tx_verifier(
+    path: &str,
+) -> (
+    ckb_script::TransactionScriptsVerifier<ckb_mock_tx_types::Resource>,
+    ckb_script::ScriptGroup,
+    ckb_script::ScriptVersion,
+) {
+    let mock_tx_repr_str = std::fs::read_to_string(path).unwrap();
+    let mock_tx_repr_str = ckb_debugger::Embed::new(std::path::PathBuf::from(path), mock_tx_repr_str).replace_all();
+    let mock_tx_repr: ReprMockTransaction = serde_json::from_str(&mock_tx_repr_str).unwrap();
+    let mock_tx: MockTransaction = mock_tx_repr.into();
+    let script_group_type = ckb_script::ScriptGroupType::Lock;
+    let script_hash = ckb_debugger::get_script_hash_by_index(&mock_tx, &script_group_type, "input", 0);
+    let resource = ckb_mock_tx_types::Resource::from_mock_tx(&mock_tx).unwrap();
+    let rtx = ckb_types::core::cell::resolve_transaction(
+        mock_tx.core_transaction(),
+        &mut std::collections::HashSet::new(),
+        &resource,
+        &resource,
+    )
+    .unwrap();
+    let env = ckb_debugger::ChainEnv::default();
+    let verifier = ckb_script::TransactionScriptsVerifier::new(
+        std::sync::Arc::new(rtx),
+        resource,
+        std::sync::Arc::new(env.consensus().unwrap()),
+        std::sync::Arc::new(env.tx_verify_env()),
+    );
+    let script_group = verifier.find_script_group(script_group_type, &script_hash).unwrap().clone();
+    (verifier, script_group, env.script_version().unwrap())
+}
+
+#[test]
+pub fn test_snapshot() {
+    use ckb_debugger::{MachineAssign, Snapshot};
+    use ckb_vm::{CoreMachine, SupportMachine};
+
+    // Run the vm to the end, or until the given cycles and take a snapshot.
+    let run = |machine: &mut MachineAssign<_>, cycles: u64| {
+        let mut decoder = ckb_vm::decoder::build_decoder::<u64>(machine.isa(), machine.version());
+        machine.set_running(true);
+        while machine.running() && machine.total_cycles() < cycles {
+            if machine.reset_signal() {
+                decoder.reset_instructions_cache();
+            }
+            machine.step(&mut decoder).unwrap();
+        }
+        if machine.running() {
+            return Err(Snapshot::new(machine));
+        }
+        if machine.id != ckb_script::ROOT_VM_ID {
+            machine.done().unwrap();
+        }
+        Ok((machine.exit_code(), machine.scheduler.consumed_cycles()))
+    };
+    let (verifier, script_group, _) = tx_verifier("examples/exec.json");
+    let boot = || {
+        let scheduler = verifier.create_scheduler(&script_group).unwrap();
+        let mut machine = MachineAssign::new(0, &[], scheduler).unwrap();
+        machine.wait().unwrap();
+        machine
+    };
+    let expect = run(&mut boot(), u64::MAX).unwrap();
+    assert_eq!(expect.0, 0);
+    for cycles in [0, 2000, 4000, 8000] {
+        let snapshot = match run(&mut boot(), cycles) {
+            Ok(_) => continue,
+            Err(snapshot) => snapshot.unwrap(),
+        };
+        let snapshot: Snapshot = serde_json::from_str(&serde_json::to_string(&snapshot).unwrap()).unwrap();
+        let mut machine = snapshot.resume(verifier.create_scheduler(&script_group).unwrap()).unwrap();
+        assert_eq!(run(&mut machine, u64::MAX).unwrap(), expect);
+    }
+
+    // Vms with open fds can not be saved.
+    let (verifier, script_group, _) = spawn_verifier();
+    for pid in [0, 1] {
+        let scheduler = verifier.create_scheduler(&script_group).unwrap();
+        let mut machine = MachineAssign::new(pid, &[], scheduler).unwrap();
+        machine.wait().unwrap();
+        let e = run(&mut machine, 5000).unwrap_err().unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            ckb_vm::Error::External(String::from("Vms with open fds can not be saved")).to_string()
+        );
+    }
+
+    let state = ckb_debugger::ReprVmState::Wait { target_vm_id: 1, exit_code_addr: 0x3000 };
+    assert_eq!(ckb_debugger::ReprVmState::new(0, &state.to_vm_state()).unwrap(), state);
+}
+
+#[test]
+pub fn test_all_vms() {
+    let (verifier, script_group, _) = spawn_verifier();
+    let mut machine = boot_analyzer(&verifier, &script_group);
+    assert_eq!(machine.run().unwrap(), 0);
+    assert_eq!(machine.machine.scheduler.consumed_cycles(), 119776);
+    assert_eq!(machine.processes.len(), 2);
+    assert_eq!(machine.processes[&1].parent, Some(0));
+    assert_eq!(machine.exit_code_of(1), Some(0));
+    assert_eq!(machine.machine.vm_cycles.values().sum::<u64>(), 119776);
+
+    let mut flamegraph = vec![];
+    machine.display_flamegraph(&mut flamegraph);
+    let flamegraph = String::from_utf8(flamegraph).unwrap();
+    assert!(flamegraph.lines().any(|e| e.starts_with("[pid 1] ") && e.contains("spawn_callee_strcat.c:main")));
+    let mut spawn_tree = vec![];
+    machine.display_spawn_tree(&mut spawn_tree);
+    assert!(String::from_utf8(spawn_tree).unwrap().contains("\n  pid 1: exit code 0"));
+}
+
+// A RISC-V executable of the instructions, loaded at 0x10000 without debug info.
+fn riscv_elf(code: &[u32]) -> Vec<u8> {
+    let size = 64 + 56 + code.len() as u64 * 4;
+    let mut elf = vec![0x7f, b'E', b'L', b'F', 2, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    elf.extend(2u16.to_le_bytes()); // e_type: executable
+    elf.extend(243u16.to_le_bytes()); // e_machine: risc-v
+    elf.extend(1u32.to_le_bytes());
+    elf.extend((0x10000u64 + 64 + 56).to_le_bytes()); // e_entry
+    elf.extend(64u64.to_le_bytes()); // e_phoff
+    elf.extend(0u64.to_le_bytes());
+    elf.extend(0u32.to_le_bytes());
+    for e in [64u16, 56, 1, 64, 0, 0] {
+        elf.extend(e.to_le_bytes());
+    }
+    elf.extend(1u32.to_le_bytes()); // p_type: load
+    elf.extend(5u32.to_le_bytes()); // p_flags: read and execute
+    for e in [0, 0x10000, 0x10000, size, size, 0x1000] {
+        elf.extend(e.to_le_bytes());
+    }
+    elf.extend(code.iter().flat_map(|e| e.to_le_bytes()));
+    elf
+}
+
+#[test]
+pub fn test_exit_code_waited() {
+    let itype = |opcode: u32, funct3: u32, rd: u32, rs1: u32, imm: i32| {
+        ((imm as u32 & 0xfff) << 20) | (rs1 << 15) | (funct3 << 12) | (rd << 7) | opcode
+    };
+    let addi = |rd, rs1, imm| itype(0x13, 0, rd, rs1, imm);
+    let ld = |rd, rs1, imm| itype(0x03, 3, rd, rs1, imm);
+    let lb = |rd, rs1, imm| itype(0x03, 0, rd, rs1, imm);
+    let sd = |rs2: u32, rs1: u32, imm: u32| {
+        ((imm >> 5) << 25) | (rs2 << 20) | (rs1 << 15) | (3 << 12) | ((imm & 0x1f) << 7) | 0x23
+    };
+    let ecall = 0x73;
+    let (zero, sp, t0, a0, a1, a2, a3, a4, a7) = (0, 2, 5, 10, 11, 12, 13, 14, 17);
+    // Spawn the second cell dep, and wait for it after it exits, since the vm with the largest id runs first.
+    let parent = riscv_elf(&[
+        addi(sp, sp, -64),
+        sd(zero, sp, 0),
+        sd(zero, sp, 8),
+        addi(t0, sp, 32),
+        sd(t0, sp, 16),
+        addi(t0, sp, 40),
+        sd(zero, t0, 0),
+        sd(t0, sp, 24),
+        addi(a0, zero, 1),
+        addi(a1, zero, 3),
+        addi(a2, zero, 0),
+        addi(a3, zero, 0),
+        addi(a4, sp, 0),
+        addi(a7, zero, 2047),
+        addi(a7, a7, 2601 - 2047),
+        ecall,
+        ld(a0, sp, 32),
+        addi(a1, sp, 48),
+        addi(a7, zero, 2047),
+        addi(a7, a7, 2602 - 2047),
+        ecall,
+        lb(a0, sp, 48),
+        addi(a7, zero, 93),
+        ecall,
+    ]);
+    let child = riscv_elf(&[addi(a0, zero, 7), addi(a7, zero, 93), ecall]);
+    let dir = std::env::temp_dir().join(format!("ckb-debugger-test-{}-wait", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("parent"), parent).unwrap();
+    std::fs::write(dir.join("child"), child).unwrap();
+    let mock_tx = std::fs::read_to_string("examples/spawn.json").unwrap();
+    let mock_tx = mock_tx.replace("spawn_caller_strcat", "parent").replace("spawn_callee_strcat", "child");
+    std::fs::write(dir.join("wait.json"), mock_tx).unwrap();
+
+    let (verifier, script_group, _) = tx_verifier(dir.join("wait.json").to_str().unwrap());
+    let mut machine = boot_analyzer(&verifier, &script_group);
+    // The parent exits with the exit code it waits for.
+    assert_eq!(machine.run().unwrap(), 7);
+    assert!(machine.machine.scheduler.terminated_vms.get(&1).is_none());
+    assert_eq!(machine.exit_code_of(1), Some(7));
+    let mut spawn_tree = vec![];
+    machine.display_spawn_tree(&mut spawn_tree);
+    assert!(String::from_utf8(spawn_tree).unwrap().contains("\n  pid 1: exit code 7"));
+}
+
+#[test]
+pub fn test_profile_lines() {
+    let (verifier, script_group, _) = spawn_verifier();
+    let mut machine = boot_analyzer(&verifier, &script_group);
+    assert_eq!(machine.run().unwrap(), 0);
+
+    let profile = &mut machine.processes.get_mut(&0).unwrap().profile;
+    let lines = profile.lines();
+    assert!(lines.windows(2).all(|e| e[0].cycles >= e[1].cycles));
+    let main: Vec<_> = lines.iter().filter(|e| e.func == "main").collect();
+    assert!(main.iter().all(|e| e.file.ends_with("spawn_caller_strcat.c") && e.count == 1));
+    assert_eq!(main.iter().map(|e| e.cycles).sum::<u64>(), 158);
+
+    let mut annotate = vec![];
+    assert!(profile.display_annotate("main", &mut annotate));
+    let annotate = String::from_utf8(annotate).unwrap();
+    assert!(annotate.starts_with("Function main: 158 cycles, "));
+    assert!(annotate.contains("\n   7.59%         12          1     28: \n"));
+    assert!(!profile.display_annotate("unknown", &mut vec![]));
+
+    let mut flamegraph = vec![];
+    machine.display_flamegraph_lines(&mut flamegraph);
+    let flamegraph = String::from_utf8(flamegraph).unwrap();
+    assert!(flamegraph.lines().any(|e| e.contains("; ") && e.contains("spawn_caller_strcat.c:28:main ")));
+    let sum = flamegraph.lines().map(|e| e.rsplit_once(' ').unwrap().1.parse::<u64>().unwrap()).sum::<u64>();
+    let mut folded = vec![];
+    machine.display_flamegraph(&mut folded);
+    let folded = String::from_utf8(folded).unwrap();
+    assert_eq!(sum, folded.lines().map(|e| e.rsplit_once(' ').unwrap().1.parse::<u64>().unwrap()).sum::<u64>());
+}
+
+#[test]
+pub fn test_pprof() {
+    use protobuf::Message;
+
+    let (verifier, script_group, _) = spawn_verifier();
+    let mut machine = boot_analyzer(&verifier, &script_group);
+    assert_eq!(machine.run().unwrap(), 0);
+
+    let data = machine.pprof().unwrap().write_to_bytes().unwrap();
+    let profile = ckb_vm_pprof_protos::profile::Profile::parse_from_bytes(&data).unwrap();
+    let string = |i: i64| profile.string_table[i as usize].as_str();
+    assert_eq!(string(0), "");
+    assert_eq!(profile.mapping.len(), 2);
+    assert!(profile.mapping.iter().all(|e| e.memory_start < e.memory_limit && string(e.build_id).starts_with("0x")));
+    let mut folded = vec![];
+    machine.display_flamegraph(&mut folded);
+    let folded = String::from_utf8(folded).unwrap();
+    let sum = folded.lines().map(|e| e.rsplit_once(' ').unwrap().1.parse::<i64>().unwrap()).sum::<i64>();
+    assert_eq!(profile.sample.iter().map(|e| e.value[0]).sum::<i64>(), sum);
+    let pids: std::collections::BTreeSet<i64> = profile.sample.iter().map(|e| e.label[0].num).collect();
+    assert_eq!(pids.into_iter().collect::<Vec<_>>(), vec![0, 1]);
+    assert!(profile.sample.iter().all(|e| string(e.label[0].key) == "pid"));
+    let main: Vec<_> = profile.function.iter().filter(|e| string(e.name) == "main").collect();
+    assert_eq!(main.len(), 2);
+    assert!(main.iter().all(|e| e.start_line > 0 && string(e.filename).ends_with("_strcat.c")));
+    assert!(profile.location.iter().all(|e| e.address != 0 && e.mapping_id != 0 && !e.line.is_empty()));
+    assert!(profile
+        .location
+        .iter()
+        .any(|e| e.line[0].function_id == main[0].id && e.line[0].line > main[0].start_line));
+}
+
+#[test]
+pub fn test_profile_diff() {
+    use ckb_debugger::{load_folded, load_pprof, ProfileDiff};
+
+    let base = load_folded("a.c:main 10\na.c:main; a.c:foo 100\na.c:main; a.c:bar 50\na.c:main; a.c:foo 20\n").unwrap();
+    let new = load_folded("a.c:main 10\na.c:main; a.c:foo 60\na.c:main; a.c:bar 60\na.c:main; a.c:baz 5\n").unwrap();
+    assert_eq!(base["a.c:main; a.c:foo"], 120);
+    let diff = ProfileDiff::new(base, new);
+    assert_eq!((diff.base_cycles(), diff.new_cycles()), (180, 135));
+    let functions = diff.functions(Some(10.0));
+    let names: Vec<&str> = functions.iter().map(|e| e.function.as_str()).collect();
+    assert_eq!(names, vec!["a.c:foo", "a.c:main", "a.c:bar", "a.c:baz"]);
+    assert_eq!((functions[1].base_self, functions[1].new_self), (10, 10));
+    assert_eq!((functions[1].base_total, functions[1].new_total), (180, 135));
+    assert_eq!(functions.iter().filter(|e| e.regressed).count(), 2);
+    assert!(!diff.functions(Some(20.0))[2].regressed);
+    assert!(diff.functions(None).iter().all(|e| !e.regressed));
+    let mut report = vec![];
+    diff.display_report(&functions, &mut report);
+    let report = String::from_utf8(report).unwrap();
+    assert!(report.starts_with("All cycles: 180 -> 135 (-45, -25.00%)\n"));
+    assert!(report.contains("Regression:         50         60        +10   +20.00%  a.c:bar\n"));
+    assert!(report.contains("Regression:          0          5         +5       new  a.c:baz\n"));
+    let mut folded = vec![];
+    diff.display_folded(&mut folded);
+    let folded = String::from_utf8(folded).unwrap();
+    assert!(folded.contains("a.c:main; a.c:baz 0 5\n"));
+    assert!(folded.contains("a.c:main; a.c:foo 120 60\n"));
+
+    // A pprof profile is loaded as the same folded stacks.
+    let (flamegraph, pprof) = {
+        use protobuf::Message;
+        let (verifier, script_group, _) = spawn_verifier();
+        let mut machine = boot_analyzer(&verifier, &script_group);
+        machine.run().unwrap();
+        let mut flamegraph = vec![];
+        machine.display_flamegraph(&mut flamegraph);
+        (flamegraph, machine.pprof().unwrap().write_to_bytes().unwrap())
+    };
+    let folded = load_folded(&String::from_utf8(flamegraph).unwrap()).unwrap();
+    assert_eq!(load_pprof(&pprof).unwrap(), folded.into_iter().filter(|e| e.1 != 0).collect());
+    assert!(load_pprof(&std::fs::read("examples/fib").unwrap()).is_err());
+}
+
+#[test]
+pub fn test_budget() {
+    use ckb_debugger::{check_budget, run_script_groups, CycleBudget};
+
+    let (verifier, script_group, _) = spawn_verifier();
+    let results = run_script_groups(&verifier, 70000000);
+    let script_hash = format!("{:#x}", script_group.script.calc_script_hash());
+    let budget: CycleBudget = serde_json::from_str(&format!(
+        r#"{{
+            "max_cycles": 200000,
+            "scripts": [
+                {{ "script_hash": "{}", "max_cycles": 100000 }},
+                {{
+                    "cell_type": "input",
+                    "cell_index": 0,
+                    "script_group_type": "lock",
+                    "functions": {{ "strcat": 1000 }}
+                }}
+            ]
+        }}"#,
+        script_hash
+    ))
+    .unwrap();
+    let checks = check_budget(&verifier, &results, &budget, 70000000).unwrap();
+    assert_eq!(checks.len(), 3);
+    assert_eq!((checks[0].cycles, checks[0].exceeded()), (119776, false));
+    assert_eq!(checks[0].to_string(), "ok: transaction, cycles 119776(117.0K) of budget 200000(195.3K)");
+    assert!(checks[1].exceeded());
+    assert_eq!(
+        checks[1].to_string(),
+        format!("exceeded: lock {}, cycles 119776(117.0K) of budget 100000(97.7K)", script_hash)
+    );
+    assert_eq!(checks[2].function.as_deref(), Some("strcat"));
+    assert_eq!((checks[2].cycles, checks[2].exceeded()), (666, false));
+
+    let budget: CycleBudget = serde_json::from_str(
+        r#"{ "scripts": [{ "cell_type": "output", "cell_index": 0, "script_group_type": "lock" }] }"#,
+    )
+    .unwrap();
+    assert!(check_budget(&verifier, &results, &budget, 70000000).is_err());
+    let budget: CycleBudget = serde_json::from_str(r#"{ "scripts": [{ "cell_index": 0 }] }"#).unwrap();
+    assert!(check_budget(&verifier, &results, &budget, 70000000).is_err());
+    let budget: CycleBudget = serde_json::from_str(&format!(
+        r#"{{ "scripts": [{{ "script_hash": "{}", "functions": {{ "unknown": 1000 }} }}] }}"#,
+        script_hash
+    ))
+    .unwrap();
+    let err = check_budget(&verifier, &results, &budget, 70000000).unwrap_err();
+    assert_eq!(err.to_string(), format!("Function unknown is not executed by lock {}", script_hash));
+}
+
+#[test]
+pub fn test_trace() {
+    use ckb_debugger::{MachineTrace, TraceFilter, TraceReader, TraceRecord, TraceSearch, TraceWriter};
+
+    let (verifier, script_group, _) = spawn_verifier();
+    let path = std::env::temp_dir().join(format!("ckb-debugger-test-{}.trace", std::process::id()));
+    let run = |filter: TraceFilter| {
+        let mut machine = boot_analyzer(&verifier, &script_group);
+        let output = Box::new(std::fs::File::create(&path).unwrap());
+        machine.trace = Some(MachineTrace::new(output, filter).unwrap());
+        machine.enable_trace = 1;
+        assert_eq!(machine.run().unwrap(), 0);
+        let count = machine.trace.take().unwrap().finish().unwrap();
+        let reader = TraceReader::new(std::fs::File::open(&path).unwrap()).unwrap();
+        let records: Vec<TraceRecord> = reader.map(|e| e.unwrap()).collect();
+        assert_eq!(records.len() as u64, count);
+        records
+    };
+
+    // Every instruction of every vm is recorded, the cycles of the last one end at the cycles of the transaction.
+    let records = run(TraceFilter::default());
+    assert!(records.windows(2).all(|e| e[0].cycles + e[0].cost == e[1].cycles));
+    assert_eq!(records.last().map(|e| e.cycles + e.cost), Some(119776));
+    assert!(records.iter().any(|e| e.pid == 1));
+    let exit = TraceSearch::new(&["op=ecall", "reg=a0=0"]).unwrap();
+    assert!(records.iter().filter(|e| exit.matches(e)).count() > 0);
+    let store = records.iter().find(|e| e.memory.len() == 1).unwrap();
+    assert!(TraceSearch::new(&[&format!("mem={:#x}", store.memory[0].0)]).unwrap().matches(store));
+    assert!(!TraceSearch::new(&["op=unknown"]).unwrap().matches(store));
+    assert!(TraceSearch::new(&["pc=0"]).is_err());
+
+    let records = run(TraceFilter { functions: vec![String::from("strcat")], ..Default::default() });
+    assert_eq!(records.len(), 38);
+    assert!(records.iter().all(|e| e.pid == 1));
+    let records = run(TraceFilter { pc: Some((0x13a60, 0x13a80)), cycles: Some((0, 112000)), ..Default::default() });
+    assert!(!records.is_empty());
+    assert!(records.iter().all(|e| e.pc >= 0x13a60 && e.pc < 0x13a80 && e.cycles < 112000));
+    std::fs::remove_file(&path).unwrap();
+
+    // Deltas going backwards are read back as well.
+    let expect = vec![
+        TraceRecord {
+            pid: 0,
+            pc: 0x1000,
+            cycles: 100,
+            cost: 3,
+            memory: vec![(0x3000, vec![1, 2])],
+            ..Default::default()
+        },
+        TraceRecord { pid: 1, pc: 0x800, cycles: 50, cost: 1, registers: vec![(10, u64::MAX)], ..Default::default() },
+        TraceRecord { pid: 1, pc: 0x804, cycles: 51, cost: 1, memory: vec![(0x10, vec![])], ..Default::default() },
+    ];
+    let mut writer = TraceWriter::new(vec![]).unwrap();
+    for e in &expect {
+        writer.write(e).unwrap();
+    }
+    let data = writer.into_inner();
+    let records: Vec<TraceRecord> = TraceReader::new(&data[..]).unwrap().map(|e| e.unwrap()).collect();
+    assert_eq!(records, expect);
+    assert!(TraceReader::new(&data[..data.len() - 1]).unwrap().any(|e| e.is_err()));
+    assert!(TraceReader::new(&b"CKBTRACE\x02"[..]).is_err());
+}
+
+#[test]
+pub fn test_compare() {
+    use ckb_debugger::{compare, MachineAnalyzer};
+
+    let (verifier, script_group, _) = spawn_verifier();
+    let init = |program: &ckb_vm::Bytes| {
+        let mut scheduler = verifier.create_scheduler(&script_group).unwrap();
+        scheduler.tx_data.program = program.clone();
+        MachineAnalyzer::boot(scheduler, &[], 70000000).unwrap()
+    };
+    let caller = verifier.extract_script(&script_group.script).unwrap();
+    let report = compare(init(&caller), init(&caller));
+    assert_eq!(report.divergence, None);
+    assert_eq!(report.syscalls, 8);
+    assert_eq!((report.a.exit_code, report.a.cycles), (Some(0), 119776));
+    assert_eq!(report.a, report.b);
+
+    let failure: ckb_vm::Bytes = std::fs::read("examples/always_failure").unwrap().into();
+    let report = compare(init(&caller), init(&failure));
+    let divergence = report.divergence.as_ref().unwrap();
+    assert_eq!(report.syscalls, 0);
+    assert_eq!(divergence.reason, "syscall 1 differs");
+    assert_eq!(divergence.a.as_ref().unwrap().to_string(), "pid 0 pipe() = 0");
+    assert_eq!(divergence.b.as_ref().unwrap().to_string(), "pid 0 exit(code=1) = ?");
+    assert!(divergence.backtrace_a.last().unwrap().ends_with(":__internal_syscall"));
+    assert!(divergence.backtrace_b.last().unwrap().ends_with(":_start"));
+    let mut output = vec![];
+    report.display(&mut output).unwrap();
+    assert!(String::from_utf8(output).unwrap().contains("\nDivergence: syscall 1 differs\n  a: pid 0 pipe() = 0\n"));
+}
+
+#[test]
+pub fn test_trace_syscalls() {
+    use ckb_debugger::{display_arg, error_name, field_name, source_name, SyscallTrace};
+
+    let (verifier, script_group, _) = spawn_verifier();
+    let path = std::env::temp_dir().join(format!("ckb-debugger-test-{}.syscalls", std::process::id()));
+    let mut machine = boot_analyzer(&verifier, &script_group);
+    machine.machine.syscall_trace = Some(SyscallTrace::new(Box::new(std::fs::File::create(&path).unwrap()), true));
+    assert_eq!(machine.run().unwrap(), 0);
+    assert_eq!(machine.machine.syscall_trace.take().unwrap().finish().unwrap(), 8);
+    let records: Vec<serde_json::Value> =
+        std::fs::read_to_string(&path).unwrap().lines().map(|e| serde_json::from_str(e).unwrap()).collect();
+    std::fs::remove_file(&path).unwrap();
+
+    // Syscalls are in the order of calls, though spawn and read are done after the other vm runs.
+    let calls: Vec<(u64, &str)> =
+        records.iter().map(|e| (e["pid"].as_u64().unwrap(), e["name"].as_str().unwrap())).collect();
+    assert_eq!(
+        calls,
+        vec![
+            (0, "pipe"),
+            (0, "pipe"),
+            (0, "spawn"),
+            (1, "inherited_fd"),
+            (1, "write"),
+            (0, "read"),
+            (1, "exit"),
+            (0, "exit")
+        ]
+    );
+    assert_eq!(records[2]["args"][1], serde_json::json!(["source", "cell_dep"]));
+    assert_eq!(records[2]["ret"], 0);
+    assert!(records[2]["backtrace"]
+        .as_array()
+        .unwrap()
+        .last()
+        .unwrap()
+        .as_str()
+        .unwrap()
+        .ends_with(":__internal_syscall"));
+    assert_eq!((records[5]["size"].as_u64(), records[5]["length"].as_u64()), (Some(1024), Some(10)));
+    assert!(records[7]["ret"].is_null());
+    assert!(records.windows(2).all(|e| e[0]["cycles"].as_u64() <= e[1]["cycles"].as_u64()));
+
+    assert_eq!(source_name(0x0100000000000001), Some("group_input"));
+    assert_eq!(field_name(2083, 1), Some("since"));
+    assert_eq!(field_name(2071, 1), None);
+    assert_eq!(error_name(3), Some("SLICE_OUT_OF_BOUND"));
+    assert_eq!(display_arg(2081, "field", 3), "lock_hash");
+    assert_eq!(display_arg(2081, "offset", 0x10000), "0x10000");
+}
+
+#[test]
+pub fn test_faults() {
+    use ckb_debugger::{FaultConfig, FaultInjector, SyscallTrace};
+
+    let (verifier, script_group, _) = spawn_verifier();
+    let path = std::env::temp_dir().join(format!("ckb-debugger-test-{}.faults", std::process::id()));
+    let run = |config: &str| {
+        let config: FaultConfig = serde_json::from_str(config).unwrap();
+        let mut machine = boot_analyzer(&verifier, &script_group);
+        machine.machine.faults = Some(FaultInjector::new(config).unwrap());
+        machine.machine.syscall_trace = Some(SyscallTrace::new(Box::new(std::fs::File::create(&path).unwrap()), true));
+        let exit_code = machine.run().unwrap();
+        machine.machine.syscall_trace.take().unwrap().finish().unwrap();
+        let records: Vec<serde_json::Value> =
+            std::fs::read_to_string(&path).unwrap().lines().map(|e| serde_json::from_str(e).unwrap()).collect();
+        (exit_code, machine.machine.faults.take().unwrap().injected, records)
+    };
+
+    // The spawn fails without spawning a vm.
+    let (exit_code, injected, records) = run(r#"{"faults": [{"syscall": "spawn", "error": "MAX_VMS_SPAWNED"}]}"#);
+    assert_eq!(exit_code, 8);
+    assert_eq!(
+        injected,
+        vec!["pid 0 spawn(index=1, source=cell_dep, place=cell_data, bounds=0): error MAX_VMS_SPAWNED"]
+    );
+    assert!(records.iter().all(|e| e["pid"] == 0));
+    // Only the second pipe fails.
+    let (exit_code, _, records) = run(r#"{"faults": [{"syscall": 2604, "occurrence": 2, "error": 9}]}"#);
+    assert_eq!(exit_code, 9);
+    assert_eq!((records[0]["ret"].as_u64(), records[1]["ret"].as_u64()), (Some(0), Some(9)));
+    assert_eq!(records[1]["error"], "MAX_FDS_CREATED");
+    // A short read is seen by the script, and so is corrupted data.
+    let (exit_code, _, records) = run(r#"{"faults": [{"syscall": "read", "args": {"fd": 4}, "truncate": 3}]}"#);
+    assert_eq!(exit_code, 0);
+    assert_eq!(records.iter().find(|e| e["name"] == "read").unwrap()["length"], 3);
+    let (exit_code, _, _) = run(r#"{"faults": [{"syscall": "read", "corrupt": [0]}]}"#);
+    assert_ne!(exit_code, 0);
+    // Faults not matched are not injected.
+    let (exit_code, injected, _) = run(r#"{"faults": [{"syscall": "read", "pid": 1, "truncate": 3}]}"#);
+    assert_eq!((exit_code, injected.len()), (0, 0));
+    std::fs::remove_file(&path).unwrap();
+
+    let invalid = |config: &str| FaultInjector::new(serde_json::from_str(config).unwrap()).err().unwrap();
+    assert_eq!(invalid(r#"{"faults": [{"syscall": "unknown", "error": 1}]}"#), "Unknown syscall unknown");
+    assert_eq!(invalid(r#"{"faults": [{"syscall": "spawn", "error": "UNKNOWN"}]}"#), "Unknown error UNKNOWN");
+    assert_eq!(
+        invalid(r#"{"faults": [{"syscall": "spawn", "args": {"fd": 1}, "error": 1}]}"#),
+        "Unknown argument fd of spawn"
+    );
+    assert!(invalid(r#"{"faults": [{"syscall": "spawn", "truncate": 1}]}"#).contains("can not change data"));
+    assert!(invalid(r#"{"faults": [{"syscall": "read"}]}"#).contains("requires error, truncate or corrupt"));
+}
+
+#[test]
+pub fn test_coverage() {
+    use ckb_debugger::Coverage;
+
+    let (verifier, script_group, _) = spawn_verifier();
+    let mut machine = boot_analyzer(&verifier, &script_group);
+    machine.coverage = Some(Coverage::default());
+    assert_eq!(machine.run().unwrap(), 0);
+    let coverage = machine.coverage().unwrap();
+
+    // Both the caller and the spawned callee are covered.
+    let file = |name: &str| coverage.files.iter().find(|e| e.0.ends_with(name)).map(|e| e.1.clone()).unwrap();
+    let caller = file("spawn_caller_strcat.c");
+    assert!(file("spawn_callee_strcat.c").lines_hit() > 0);
+    // if (err != 0) in main is run but never true, and return err is never run.
+    assert_eq!(caller.lines[&35], 1);
+    assert_eq!(caller.lines[&36], 0);
+    assert_eq!(caller.branches[&(35, 0, 0)], Some(0));
+    assert_eq!(caller.branches[&(35, 0, 1)], Some(1));
+    assert!(coverage.branches_hit() > 0 && coverage.branches_hit() < coverage.branches_found());
+
+    // Written and loaded in lcov, and merged with another run.
+    let mut lcov = vec![];
+    coverage.display_lcov(&mut lcov).unwrap();
+    let loaded = Coverage::load_lcov(&String::from_utf8(lcov).unwrap()).unwrap();
+    assert_eq!(loaded, coverage);
+    let mut merged = loaded.clone();
+    merged.merge(coverage.clone());
+    let caller = merged.files.iter().find(|e| e.0.ends_with("spawn_caller_strcat.c")).unwrap().1;
+    assert_eq!(caller.lines[&35], 2);
+    assert_eq!(caller.branches[&(35, 0, 1)], Some(2));
+    assert_eq!((merged.lines_found(), merged.lines_hit()), (coverage.lines_found(), coverage.lines_hit()));
+    let never = coverage.files.values().flat_map(|e| e.branches.values()).filter(|e| e.is_none()).count();
+    assert_eq!(merged.files.values().flat_map(|e| e.branches.values()).filter(|e| e.is_none()).count(), never);
+    assert!(Coverage::load_lcov("SF:a.c\nDA:1,1\n").is_err());
+
+    let mut cobertura = vec![];
+    coverage.display_cobertura(&mut cobertura).unwrap();
+    let cobertura = String::from_utf8(cobertura).unwrap();
+    assert!(cobertura.contains(r#"<line number="35" hits="1" branch="true" condition-coverage="50% (1/2)"/>"#));
+}
+
+#[test]
+pub fn test_gdb_threads() {
+    use ckb_debugger::{GdbStubHandler, MachineAssign};
+    use ckb_vm::decoder::build_decoder;
+    use ckb_vm::{CoreMachine, SupportMachine};
+    use gdbstub::common::Tid;
+    use gdbstub::target::ext::base::multithread::MultiThreadBase;
+    use gdbstub::target::ext::thread_extra_info::ThreadExtraInfo;
+
+    let (verifier, script_group, _) = spawn_verifier();
+    let scheduler = verifier.create_scheduler(&script_group).unwrap();
+    let mut machine = MachineAssign::new(0, &[], scheduler).unwrap();
+    machine.expand_cycles = 70000000;
+    machine.all_vms = true;
+    machine.wait().unwrap();
+    machine.set_running(true);
+    let mut decoder = build_decoder::<u64>(machine.isa(), machine.version());
+    while machine.scheduler.next_vm_id == 1 {
+        machine.step(&mut decoder).unwrap();
+    }
+    // The spawned vm runs first.
+    assert_eq!(machine.id, 1);
+    let entry = *machine.pc();
+
+    let mut h = GdbStubHandler::new(machine);
+    let mut threads = vec![];
+    h.list_active_threads(&mut |tid| threads.push(tid.get())).unwrap();
+    assert_eq!(threads, vec![1, 2]);
+    let mut regs = Default::default();
+    assert!(h.read_registers(&mut regs, Tid::new(2).unwrap()).is_ok());
+    assert_eq!(regs.pc, entry);
+    assert!(h.read_registers(&mut regs, Tid::new(1).unwrap()).is_ok());
+    assert_ne!(regs.pc, entry);
+    let mut buf = [0u8; 64];
+    let size = h.thread_extra_info(Tid::new(2).unwrap(), &mut buf).unwrap();
+    assert_eq!(&buf[..size], b"pid 1, running");
+    let size = h.thread_extra_info(Tid::new(1).unwrap(), &mut buf).unwrap();
+    assert_eq!(&buf[..size], b"pid 0, runnable");
+    assert_eq!(h.run_till_exited().unwrap(), (0, 119776));
+}
+
+// A gdb client sending the packets one at a time, each one once the stub is waiting for it.
+struct ScriptedConnection {
+    packets: std::collections::VecDeque<String>,
+    pending: std::collections::VecDeque<u8>,
+    output: std::rc::Rc<std::cell::RefCell<Vec<u8>>>,
+}
+
+impl gdbstub::conn::Connection for ScriptedConnection {
+    type Error = std::io::Error;
+
+    fn write(&mut self, byte: u8) -> Result<(), Self::Error> {
+        self.output.borrow_mut().push(byte);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl gdbstub::conn::ConnectionExt for ScriptedConnection {
+    fn read(&mut self) -> Result<u8, Self::Error> {
+        if self.pending.is_empty() {
+            let packet = self.packets.pop_front().ok_or(std::io::Error::from(std::io::ErrorKind::UnexpectedEof))?;
+            let checksum = packet.bytes().fold(0u8, |acc, e| acc.wrapping_add(e));
+            self.pending.extend(format!("+${}#{:02x}", packet, checksum).bytes());
+        }
+        Ok(self.pending.pop_front().unwrap())
+    }
+
+    fn peek(&mut self) -> Result<Option<u8>, Self::Error> {
+        Ok(self.pending.front().copied())
+    }
+}
+
+#[test]
+pub fn test_gdb_reverse() {
+    use ckb_debugger::{GdbStubHandler, GdbStubHandlerEventLoop, History, MachineAssign};
+    use ckb_vm::SupportMachine;
+    use gdbstub::stub::{DisconnectReason, GdbStub};
+
+    let (verifier, script_group, _) = spawn_verifier();
+    let scheduler = verifier.create_scheduler(&script_group).unwrap();
+    let mut machine = MachineAssign::new(0, &[], scheduler).unwrap();
+    machine.expand_cycles = 70000000;
+    machine.all_vms = true;
+    machine.wait().unwrap();
+    machine.set_running(true);
+    let mut h = GdbStubHandler::new(machine);
+    h.history = History::new(64 << 20);
+
+    let packets = [
+        "vCont;c",
+        "p20",
+        "vCont;s:2",
+        "p20",
+        "vCont;s:2",
+        "bs",
+        "p20",
+        "bs",
+        "p20",
+        "bs",
+        "qfThreadInfo",
+        "bc",
+        "vCont;c",
+        "p20",
+        "vCont;c",
+    ];
+    let output = std::rc::Rc::new(std::cell::RefCell::new(vec![]));
+    let connection: Box<dyn gdbstub::conn::ConnectionExt<Error = std::io::Error>> = Box::new(ScriptedConnection {
+        packets: packets.iter().map(|e| e.to_string()).collect(),
+        pending: Default::default(),
+        output: output.clone(),
+    });
+    let reason = GdbStub::new(connection).run_blocking::<GdbStubHandlerEventLoop<_>>(&mut h).unwrap();
+    assert!(matches!(reason, DisconnectReason::TargetExited(0)));
+
+    let output = String::from_utf8(output.borrow().clone()).unwrap();
+    let replies: Vec<&str> = output.split('$').skip(1).map(|e| e.split('#').next().unwrap()).collect();
+    assert_eq!(replies.len(), packets.len());
+    // Stop at the first instruction of the spawned vm, step twice and back.
+    assert_eq!(replies[0], "T05thread:02;");
+    let pc0 = replies[1];
+    let pc1 = replies[3];
+    assert_eq!(replies[4], "T05thread:02;");
+    assert_eq!(replies[5], "T05thread:02;");
+    assert_eq!(replies[6], pc1);
+    assert_eq!(replies[8], pc0);
+    // Going back over the spawn syscall restores the session before the child is created.
+    assert_eq!(replies[9], "T05thread:01;");
+    assert_eq!(replies[10], "m01");
+    assert_eq!(replies[11], "T05thread:01;replaylog:begin;");
+    // Running forward again reaches the same states.
+    assert_eq!(replies[12], "T05thread:02;");
+    assert_eq!(replies[13], pc0);
+    assert_eq!(replies[14], "W00");
+    assert_eq!(h.run_till_exited().unwrap(), (0, 119776));
+}
+
+#[test]
+pub fn test_gdb_watch() {
+    use ckb_debugger::{GdbStubHandler, GdbStubHandlerEventLoop, MachineAssign};
+    use ckb_vm::SupportMachine;
+    use gdbstub::stub::{DisconnectReason, GdbStub};
+
+    let (verifier, script_group, _) = spawn_verifier();
+    let scheduler = verifier.create_scheduler(&script_group).unwrap();
+    let mut machine = MachineAssign::new(0, &[], scheduler).unwrap();
+    machine.expand_cycles = 70000000;
+    machine.all_vms = true;
+    machine.wait().unwrap();
+    machine.set_running(true);
+    let mut h = GdbStubHandler::new(machine);
+
+    let packets = [
+        "vCont;c",
+        "Z2,3fffc0,10",
+        "vCont;c",
+        "z2,3fffc0,10",
+        "Z4,3fffc0,10",
+        "vCont;c",
+        "vCont;c",
+        "z4,3fffc0,10",
+        "vCont;c",
+    ];
+    let output = std::rc::Rc::new(std::cell::RefCell::new(vec![]));
+    let connection: Box<dyn gdbstub::conn::ConnectionExt<Error = std::io::Error>> = Box::new(ScriptedConnection {
+        packets: packets.iter().map(|e| e.to_string()).collect(),
+        pending: Default::default(),
+        output: output.clone(),
+    });
+    let reason = GdbStub::new(connection).run_blocking::<GdbStubHandlerEventLoop<_>>(&mut h).unwrap();
+    assert!(matches!(reason, DisconnectReason::TargetExited(0)));
+
+    let output = String::from_utf8(output.borrow().clone()).unwrap();
+    let replies: Vec<&str> = output.split('$').skip(1).map(|e| e.split('#').next().unwrap()).collect();
+    assert_eq!(replies.len(), packets.len());
+    // The watchpoint covers 16 bytes, so a store in the middle of it is found.
+    assert_eq!(replies[2], "T05thread:02;watch:3fffc8;");
+    // An access watchpoint tells whether it is a write or a read.
+    assert_eq!(replies[5], "T05thread:02;watch:3fffc0;");
+    assert_eq!(replies[6], "T05thread:02;rwatch:3fffc8;");
+    assert_eq!(replies[8], "W00");
+}
+
+// Output of a test, shared with the writer given to the code under test.
+#[derive(Clone, Default)]
+struct SharedWriter(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+impl std::io::Write for SharedWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+pub fn test_dap() {
+    use ckb_debugger::{read_dap_message, DapServer, MachineAssign};
+    use serde_json::json;
+
+    let (verifier, script_group, _) = spawn_verifier();
+    let scheduler = verifier.create_scheduler(&script_group).unwrap();
+    let mut machine = MachineAssign::new(0, &[], scheduler).unwrap();
+    machine.expand_cycles = 70000000;
+    machine.wait().unwrap();
+    let code = verifier.extract_script(&script_group.script).unwrap();
+    let output = SharedWriter::default();
+    let mut server = DapServer::new(machine, &code, Default::default(), Box::new(output.clone())).unwrap();
+
+    let source = json!({"path": "examples/spawn_caller_strcat.c"});
+    let requests = [
+        ("initialize", json!({})),
+        ("launch", json!({})),
+        ("setBreakpoints", json!({"source": source, "breakpoints": [{"line": 12}, {"line": 60}]})),
+        ("configurationDone", json!({})),
+        ("stackTrace", json!({"threadId": 1})),
+        ("stepOut", json!({"threadId": 1})),
+        ("stackTrace", json!({"threadId": 1})),
+        ("variables", json!({"variablesReference": 1})),
+        ("continue", json!({"threadId": 1})),
+    ];
+    let (sender, receiver) = std::sync::mpsc::channel();
+    for (i, (command, arguments)) in requests.iter().enumerate() {
+        sender.send(json!({"seq": i + 1, "type": "request", "command": command, "arguments": arguments})).unwrap();
+    }
+    drop(sender);
+    server.serve(receiver).unwrap();
+
+    let output = output.0.borrow().clone();
+    let mut reader = std::io::Cursor::new(output);
+    let mut messages = vec![];
+    while let Some(message) = read_dap_message(&mut reader).unwrap() {
+        messages.push(message);
+    }
+    let response = |command: &str, n: usize| {
+        messages.iter().filter(|e| e["type"] == "response" && e["command"] == command).nth(n).unwrap().clone()
+    };
+    let events: Vec<&str> =
+        messages.iter().filter(|e| e["type"] == "event").map(|e| e["event"].as_str().unwrap()).collect();
+    assert_eq!(events, vec!["initialized", "stopped", "stopped", "output", "exited", "terminated"]);
+    // Lines without code are moved to the next line, and a line after the end of the file is not verified.
+    let breakpoints = &response("setBreakpoints", 0)["body"]["breakpoints"];
+    assert_eq!(breakpoints[0], json!({"verified": true, "line": 12}));
+    assert_eq!(breakpoints[1]["verified"], false);
+    let frames = &response("stackTrace", 0)["body"]["stackFrames"];
+    assert_eq!(frames[0]["name"], "create_std_fds");
+    assert_eq!(frames[0]["line"], 12);
+    assert_eq!(frames[0]["source"]["path"], "examples/spawn_caller_strcat.c");
+    assert_eq!(frames[1]["name"], "main");
+    assert_eq!(frames[1]["line"], 34);
+    let frames = &response("stackTrace", 1)["body"]["stackFrames"];
+    assert_eq!(frames[0]["name"], "main");
+    assert_eq!(frames[0]["line"], 34);
+    let registers = &response("variables", 0)["body"]["variables"];
+    assert_eq!(registers[0]["name"], "pc");
+    assert_eq!(registers[0]["value"], frames[0]["instructionPointerReference"]);
+    assert_eq!(messages.iter().find(|e| e["event"] == "exited").unwrap()["body"]["exitCode"], 0);
+}
+
+#[test]
+pub fn test_repl() {
+    use ckb_debugger::{GdbStubHandler, MachineAssign, Repl};
+
+    let (verifier, script_group, _) = spawn_verifier();
+    let scheduler = verifier.create_scheduler(&script_group).unwrap();
+    let mut machine = MachineAssign::new(0, &[], scheduler).unwrap();
+    machine.expand_cycles = 70000000;
+    machine.wait().unwrap();
+    let code = verifier.extract_script(&script_group.script).unwrap();
+    let mut repl = Repl::new(GdbStubHandler::new(machine), &code).unwrap();
+    let input = "b spawn_caller_strcat.c:12\nc\nbt\nfinish\nd\nx $sp 8\ndisas $pc 1\nc\nc\nq\n";
+    let mut output = vec![];
+    repl.run(std::io::Cursor::new(input), &mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let replies: Vec<&str> = output.split("(ckb-debugger) ").skip(1).collect();
+    assert_eq!(replies.len(), 10);
+    assert!(replies[0].starts_with("Breakpoint at 0x") && replies[0].ends_with("spawn_caller_strcat.c:12\n"));
+    assert!(replies[1].starts_with("Breakpoint hit\n"));
+    let frames: Vec<&str> = replies[2].lines().collect();
+    assert!(frames[0].ends_with("spawn_caller_strcat.c:12:create_std_fds"));
+    assert!(frames[1].ends_with("spawn_caller_strcat.c:34:main"));
+    assert!(replies[3].contains(" in main at ") && replies[3].contains("spawn_caller_strcat.c:34"));
+    assert_eq!(replies[5].split_whitespace().count(), 9);
+    assert!(replies[6].starts_with("=> 0x"));
+    assert_eq!(replies[7], "Program exited with code 0\nAll cycles: 119776\n");
+    assert_eq!(replies[8], "The program is not being run\n");
+}
+
+#[test]
+pub fn test_disasm() {
+    use ckb_vm::machine::{VERSION1, VERSION2};
+    use ckb_vm::{ISA_B, ISA_IMC, ISA_MOP};
+
+    let program: ckb_vm::Bytes = std::fs::read("examples/fib").unwrap().into();
+    let mut output = vec![];
+    ckb_debugger::disasm(&program, ISA_IMC | ISA_B | ISA_MOP, VERSION2, &mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("<fib>:\n/code/fib.c:3\n"));
+    assert!(output.lines().any(|e| e.contains("\tecall") && e.ends_with("\t# 500 cycles")));
+    assert!(output.contains(", fused into custom_load_imm of 8 bytes"));
+    assert!(!output.contains("<invalid>"));
+    assert!(output.ends_with("macro-op fusions: 2\n"));
+
+    let mut output = vec![];
+    ckb_debugger::disasm(&program, ISA_IMC | ISA_B, VERSION1, &mut output).unwrap();
+    assert!(String::from_utf8(output).unwrap().ends_with("macro-op fusions: 0\n"));
+}
+
+#[test]
+pub fn test_agent_expr() {
+    use ckb_debugger::{eval_agent_expr, parse_conditions, GdbConnection};
+    use ckb_vm::machine::VERSION2;
+    use ckb_vm::{CoreMachine, DefaultCoreMachine, Memory, SparseMemory, ISA_B, ISA_IMC, ISA_MOP};
+    use gdbstub::conn::{Connection, ConnectionExt};
+
+    let mut machine = DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC | ISA_B | ISA_MOP, VERSION2, u64::MAX);
+    machine.set_register(2, 0x1000);
+    machine.set_register(10, 7);
+    machine.memory_mut().store32(&0x1008, &(-100i32 as u32 as u64)).unwrap();
+    // n == -100, where n is an int at sp + 8.
+    let code = [0x26, 0x00, 0x02, 0x22, 0x08, 0x02, 0x19, 0x16, 0x20, 0x22, 0x64, 0x12, 0x22, 0x01, 0x02, 0x13, 0x27];
+    assert_eq!(eval_agent_expr(&code, &mut machine).unwrap(), 1);
+    // a0 < 5 || a0 == 7
+    let code = [
+        0x26, 0x00, 0x0a, 0x22, 0x05, 0x14, 0x20, 0x00, 0x10, 0x26, 0x00, 0x0a, 0x22, 0x07, 0x13, 0x27, 0x22, 0x01,
+        0x27,
+    ];
+    assert_eq!(eval_agent_expr(&code, &mut machine).unwrap(), 1);
+    machine.set_register(10, 6);
+    assert_eq!(eval_agent_expr(&code, &mut machine).unwrap(), 0);
+    assert!(eval_agent_expr(&[0x22, 0x01, 0x22, 0x00, 0x05, 0x27], &mut machine).is_err());
+    assert!(eval_agent_expr(&[0x22, 0x01], &mut machine).is_err());
+
+    assert_eq!(parse_conditions(b"X3,220127;X2,2827").unwrap(), vec![vec![0x22, 0x01, 0x27], vec![0x28, 0x27]]);
+    assert!(parse_conditions(b"X4,220127").is_none());
+
+    // Conditions are taken out of the breakpoint packets, and conditional breakpoints are advertised to gdb.
+    let output = std::rc::Rc::new(std::cell::RefCell::new(vec![]));
+    let inner = Box::new(ScriptedConnection {
+        packets: ["Z0,13df8,2;X3,220127", "z0,13df8,2"].iter().map(|e| e.to_string()).collect(),
+        pending: Default::default(),
+        output: output.clone(),
+    });
+    let conditions = ckb_debugger::BreakpointConditions::default();
+    let mut connection = GdbConnection::new(inner, conditions.clone());
+    let mut incoming = vec![];
+    for _ in 0..30 {
+        incoming.push(connection.read().unwrap());
+    }
+    assert_eq!(String::from_utf8(incoming).unwrap(), "+$Z0,13df8,2#7a+$z0,13df8,2#9a");
+    assert_eq!(conditions.lock().unwrap()[&0x13df8], vec![vec![0x22, 0x01, 0x27]]);
+    connection.write_all(b"+$PacketSize=1000#f1").unwrap();
+    assert_eq!(String::from_utf8(output.borrow().clone()).unwrap(), "+$PacketSize=1000;ConditionalBreakpoints+#4d");
+}
@@ -0,0 +1,141 @@
+use ckb_debugger::RpcResourceLoader;
+use ckb_mock_tx_types::{MockInfo, MockResourceLoader, MockTransaction, ReprMockTransaction};
+use ckb_types::core::HeaderView;
+use ckb_types::packed::{CellOutput, OutPoint};
+use ckb_types::prelude::*;
+use ckb_types::H256;
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+
+// A json-rpc server that serves the cells of a mock transaction. Input cells are served by get_transaction, cell deps
+// by get_live_cell.
+fn stub_server(mock_tx: MockTransaction) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                if let Some(v) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                    content_length = v.trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            let request: Value = serde_json::from_slice(&body).unwrap();
+            let result = handle(&mock_tx, request["method"].as_str().unwrap(), &request["params"]);
+            let response = json!({"id": request["id"], "jsonrpc": "2.0", "result": result}).to_string();
+            write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}", response.len(), response).unwrap();
+        }
+    });
+    format!("http://{}", addr)
+}
+
+fn handle(mock_tx: &MockTransaction, method: &str, params: &Value) -> Value {
+    match method {
+        "get_transaction" => {
+            let tx_hash: H256 = serde_json::from_value(params[0].clone()).unwrap();
+            for e in &mock_tx.mock_info.inputs {
+                let out_point = e.input.previous_output();
+                if out_point.tx_hash() != tx_hash.pack() {
+                    continue;
+                }
+                let index: u32 = out_point.index().unpack();
+                let mut outputs = vec![CellOutput::default(); index as usize + 1];
+                let mut outputs_data = vec![ckb_types::bytes::Bytes::new(); index as usize + 1];
+                outputs[index as usize] = e.output.clone();
+                outputs_data[index as usize] = e.data.clone();
+                let tx = ckb_types::core::TransactionBuilder::default()
+                    .outputs(outputs)
+                    .outputs_data(outputs_data.into_iter().map(|d| d.pack()))
+                    .build();
+                let block_hash: Option<H256> = e.header.clone().map(|h| h.unpack());
+                return json!({
+                    "transaction": ckb_jsonrpc_types::TransactionView::from(tx),
+                    "cycles": null,
+                    "time_added_to_pool": null,
+                    "tx_status": {
+                        "status": "committed",
+                        "block_number": "0x1",
+                        "block_hash": block_hash,
+                        "reason": null,
+                    },
+                });
+            }
+            Value::Null
+        }
+        "get_live_cell" => {
+            let out_point: ckb_jsonrpc_types::OutPoint = serde_json::from_value(params[0].clone()).unwrap();
+            let out_point: OutPoint = out_point.into();
+            for e in &mock_tx.mock_info.cell_deps {
+                if e.cell_dep.out_point() == out_point {
+                    return json!({
+                        "cell": {
+                            "output": ckb_jsonrpc_types::CellOutput::from(e.output.clone()),
+                            "data": {
+                                "content": ckb_jsonrpc_types::JsonBytes::from_bytes(e.data.clone()),
+                                "hash": H256::from(ckb_hash::blake2b_256(&e.data)),
+                            },
+                        },
+                        "status": "live",
+                    });
+                }
+            }
+            json!({"cell": null, "status": "unknown"})
+        }
+        "get_header" => {
+            let block_hash: H256 = serde_json::from_value(params[0].clone()).unwrap();
+            for e in &mock_tx.mock_info.header_deps {
+                if e.hash() == block_hash.pack() {
+                    return json!(ckb_jsonrpc_types::HeaderView::from(e.clone()));
+                }
+            }
+            Value::Null
+        }
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+pub fn test_rpc_loader() {
+    let mock_tx_repr_str = std::fs::read_to_string("examples/mock_tx.json").unwrap();
+    let mock_tx_repr: ReprMockTransaction = serde_json::from_str(&mock_tx_repr_str).unwrap();
+    let mut mock_tx: MockTransaction = mock_tx_repr.into();
+    let epoch = ckb_types::core::EpochNumberWithFraction::new(1, 0, 1);
+    let header = HeaderView::new_advanced_builder().number(42.pack()).epoch(epoch.pack()).build();
+    mock_tx.mock_info.header_deps.push(header.clone());
+    let url = stub_server(mock_tx.clone());
+
+    let script_group_type = ckb_script::ScriptGroupType::Lock;
+    let script_hash = ckb_debugger::get_script_hash_by_index(&mock_tx, &script_group_type, "input", 0);
+    let cache = std::env::temp_dir().join(format!("ckb-debugger-test-rpc-loader-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&cache);
+
+    let mut online = MockTransaction { mock_info: MockInfo::default(), tx: mock_tx.tx.clone() };
+    online.fill_mock_info(&mut RpcResourceLoader::new(Some(url.clone()), Some(cache.clone()))).unwrap();
+    assert!(ckb_debugger::pre_check(&online.clone().into()).is_ok());
+    assert_eq!(online.mock_info.inputs[0].header, mock_tx.mock_info.inputs[0].header);
+    assert_eq!(online.mock_info.cell_deps.len(), mock_tx.mock_info.cell_deps.len());
+    let result = ckb_debugger::run(&online, &script_group_type, &script_hash, 70000000).unwrap();
+    assert_eq!(result, 1641938);
+
+    let mut loader = RpcResourceLoader::new(Some(url), Some(cache.clone()));
+    assert_eq!(loader.get_header(header.hash().unpack()).unwrap().unwrap().number(), 42);
+    assert!(loader.get_header(H256::default()).unwrap().is_none());
+
+    let mut offline = MockTransaction { mock_info: MockInfo::default(), tx: mock_tx.tx.clone() };
+    offline.fill_mock_info(&mut RpcResourceLoader::new(None, Some(cache.clone()))).unwrap();
+    let result = ckb_debugger::run(&offline, &script_group_type, &script_hash, 70000000).unwrap();
+    assert_eq!(result, 1641938);
+    let mut loader = RpcResourceLoader::new(None, Some(cache.clone()));
+    assert_eq!(loader.get_header(header.hash().unpack()).unwrap().unwrap().number(), 42);
+    std::fs::remove_dir_all(&cache).unwrap();
+}
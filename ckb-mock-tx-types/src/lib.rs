@@ -17,6 +17,7 @@ use ckb_types::{
 };
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 
 /// Represents a cell dependency with its associated data and optional header.
 #[derive(Clone, Default)]
@@ -99,6 +100,105 @@ impl MockTransaction {
     pub fn core_transaction(&self) -> TransactionView {
         self.tx.clone().into_view()
     }
+
+    /// Fill the inputs, cell deps (including cells referenced by dep groups) and header deps which are missing in the
+    /// mock info with a resource loader. The mock info is rebuilt in the order of the transaction, and anything not
+    /// referenced by the transaction is dropped.
+    pub fn fill_mock_info<L: MockResourceLoader>(&mut self, loader: &mut L) -> Result<(), String> {
+        let unresolved = self.try_fill_mock_info(loader)?;
+        if !unresolved.is_empty() {
+            let unresolved: Vec<String> = unresolved.iter().map(|e| e.to_string()).collect();
+            return Err(format!("Unresolved: {}", unresolved.join(", ")));
+        }
+        Ok(())
+    }
+
+    /// Same as fill_mock_info, but instead of failing at the first cell or header that the loader can not find, fill
+    /// everything that can be found and return the rest.
+    pub fn try_fill_mock_info<L: MockResourceLoader>(&mut self, loader: &mut L) -> Result<Vec<Unresolved>, String> {
+        let tx = self.core_transaction();
+        let mut mock_info = MockInfo { extensions: self.mock_info.extensions.clone(), ..Default::default() };
+        let mut unresolved = vec![];
+
+        for input in tx.inputs().into_iter() {
+            match self.get_input_cell(&input, |out_point| loader.get_live_cell(out_point))? {
+                Some((output, data, header)) => mock_info.inputs.push(MockInput { input, output, data, header }),
+                None => unresolved.push(Unresolved::Input(input.previous_output())),
+            }
+        }
+
+        let mut push_cell_dep = |mock_info: &mut MockInfo, cell_dep: CellDep| -> Result<Option<Bytes>, String> {
+            let (output, data, header) =
+                match self.get_dep_cell(&cell_dep.out_point(), |out_point| loader.get_live_cell(out_point))? {
+                    Some(cell) => cell,
+                    None => return Ok(None),
+                };
+            if !mock_info.cell_deps.iter().any(|e| e.cell_dep == cell_dep) {
+                mock_info.cell_deps.push(MockCellDep { cell_dep, output, data: data.clone(), header });
+            }
+            Ok(Some(data))
+        };
+        for cell_dep in tx.cell_deps().into_iter() {
+            let data = match push_cell_dep(&mut mock_info, cell_dep.clone())? {
+                Some(data) => data,
+                None => {
+                    unresolved.push(Unresolved::CellDep(cell_dep.out_point()));
+                    continue;
+                }
+            };
+            if cell_dep.dep_type() == DepType::DepGroup.into() {
+                for sub_out_point in OutPointVec::from_slice(&data)
+                    .map_err(|err| format!("Parse dep group data error: {}", err))?
+                    .into_iter()
+                {
+                    let sub_cell_dep =
+                        CellDep::new_builder().out_point(sub_out_point.clone()).dep_type(DepType::Code.into()).build();
+                    if push_cell_dep(&mut mock_info, sub_cell_dep)?.is_none() {
+                        unresolved.push(Unresolved::CellDep(sub_out_point));
+                    }
+                }
+            }
+        }
+
+        for block_hash in tx.header_deps().into_iter() {
+            match self.get_header(&block_hash.unpack(), |block_hash| loader.get_header(block_hash))? {
+                Some(header) => mock_info.header_deps.push(header),
+                None => unresolved.push(Unresolved::Header(block_hash.unpack())),
+            }
+        }
+
+        self.mock_info = mock_info;
+        Ok(unresolved)
+    }
+}
+
+/// A cell or header which can not be resolved when filling the mock info.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Unresolved {
+    Input(OutPoint),
+    CellDep(OutPoint),
+    Header(H256),
+}
+
+impl std::fmt::Display for Unresolved {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Unresolved::Input(out_point) => write!(f, "input {}", FixtureResourceLoader::cell_name(out_point)),
+            Unresolved::CellDep(out_point) => write!(f, "cell dep {}", FixtureResourceLoader::cell_name(out_point)),
+            Unresolved::Header(hash) => write!(f, "header {:#x}", hash),
+        }
+    }
+}
+
+/// Build a mock transaction from a bare transaction, with the mock info filled by a resource loader. Everything that
+/// can not be resolved is returned along with the mock transaction.
+pub fn build_mock_tx<L: MockResourceLoader>(
+    tx: json_types::Transaction,
+    loader: &mut L,
+) -> Result<(MockTransaction, Vec<Unresolved>), String> {
+    let mut mock_tx = MockTransaction { mock_info: MockInfo::default(), tx: tx.into() };
+    let unresolved = mock_tx.try_fill_mock_info(loader)?;
+    Ok((mock_tx, unresolved))
 }
 
 /// The trait defines methods for loading headers and live cells.
@@ -361,6 +461,108 @@ impl From<ReprMockTransaction> for MockTransaction {
     }
 }
 
+/// A cell stored in a fixture directory.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ReprCellFixture {
+    pub output: json_types::CellOutput,
+    #[serde(default)]
+    pub data: json_types::JsonBytes,
+    #[serde(default)]
+    pub header: Option<H256>,
+}
+
+/// Resource loader that reads cells and headers from local fixture directories.
+///
+/// A cell is stored in the cells directory as `<tx_hash>-<index>.json`, which contains a `ReprCellFixture`. The tx
+/// hash is in hex without the 0x prefix, and the index is in decimal. If `<tx_hash>-<index>.data` exists, its raw
+/// content is used as the cell data instead. A header is stored in the headers directory as `<block_hash>.json`,
+/// which contains a `json_types::HeaderView`.
+pub struct FixtureResourceLoader {
+    pub cells: PathBuf,
+    pub headers: PathBuf,
+}
+
+impl FixtureResourceLoader {
+    pub fn new<P: AsRef<Path>, Q: AsRef<Path>>(cells: P, headers: Q) -> Self {
+        Self { cells: cells.as_ref().to_path_buf(), headers: headers.as_ref().to_path_buf() }
+    }
+
+    /// File name of a cell fixture, without extension.
+    pub fn cell_name(out_point: &OutPoint) -> String {
+        let tx_hash: H256 = out_point.tx_hash().unpack();
+        let index: u32 = out_point.index().unpack();
+        format!("{:x}-{}", tx_hash, index)
+    }
+
+    /// Save a cell to the cells directory.
+    pub fn save_cell(
+        &self,
+        out_point: &OutPoint,
+        output: &CellOutput,
+        data: &Bytes,
+        header: Option<&Byte32>,
+    ) -> Result<(), String> {
+        let fixture = ReprCellFixture {
+            output: output.clone().into(),
+            data: json_types::JsonBytes::from_bytes(data.clone()),
+            header: header.map(|h| h.unpack()),
+        };
+        let path = self.cells.join(format!("{}.json", Self::cell_name(out_point)));
+        Self::save(&path, &fixture)
+    }
+
+    /// Save a header to the headers directory.
+    pub fn save_header(&self, header: &HeaderView) -> Result<(), String> {
+        let hash: H256 = header.hash().unpack();
+        let path = self.headers.join(format!("{:x}.json", hash));
+        Self::save(&path, &json_types::HeaderView::from(header.clone()))
+    }
+
+    fn save<T: Serialize>(path: &Path, data: &T) -> Result<(), String> {
+        let dir = path.parent().unwrap();
+        std::fs::create_dir_all(dir).map_err(|e| format!("Create {:?} failed: {}", dir, e))?;
+        let data = serde_json::to_string_pretty(data).unwrap();
+        std::fs::write(path, data).map_err(|e| format!("Write {:?} failed: {}", path, e))
+    }
+
+    fn load<T: for<'de> Deserialize<'de>>(path: &Path) -> Result<Option<T>, String> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let data = std::fs::read_to_string(path).map_err(|e| format!("Read {:?} failed: {}", path, e))?;
+        serde_json::from_str(&data).map(Some).map_err(|e| format!("Parse {:?} failed: {}", path, e))
+    }
+}
+
+impl MockResourceLoader for FixtureResourceLoader {
+    fn get_header(&mut self, hash: H256) -> Result<Option<HeaderView>, String> {
+        let path = self.headers.join(format!("{:x}.json", hash));
+        let header: Option<json_types::HeaderView> = Self::load(&path)?;
+        match header.map(HeaderView::from) {
+            Some(header) if header.hash() != hash.pack() => {
+                Err(format!("Header {:?} has a mismatched hash {:#x}", path, header.hash()))
+            }
+            header => Ok(header),
+        }
+    }
+
+    fn get_live_cell(&mut self, out_point: OutPoint) -> Result<Option<(CellOutput, Bytes, Option<Byte32>)>, String> {
+        let name = Self::cell_name(&out_point);
+        let fixture: Option<ReprCellFixture> = Self::load(&self.cells.join(format!("{}.json", name)))?;
+        let fixture = match fixture {
+            Some(fixture) => fixture,
+            None => return Ok(None),
+        };
+        let data_path = self.cells.join(format!("{}.data", name));
+        let data = if data_path.exists() {
+            Bytes::from(std::fs::read(&data_path).map_err(|e| format!("Read {:?} failed: {}", data_path, e))?)
+        } else {
+            fixture.data.into_bytes()
+        };
+        Ok(Some((fixture.output.into(), data, fixture.header.map(|h| h.pack()))))
+    }
+}
+
 /// Dummy resource loader used for testing purposes.
 pub struct DummyResourceLoader {}
 
@@ -10,6 +10,7 @@ use std::str::FromStr;
 pub struct Symbol {
     pub name: Option<String>,
     pub file: Option<String>,
+    pub line: Option<u32>,
 }
 
 impl Symbol {
@@ -61,9 +62,10 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             .split("; ")
             .map(|s| match s.find(":") {
                 Some(j) => {
-                    Symbol { file: Some(s[0..j].to_string()), name: Some(normalize_function_name(&s[j + 1..s.len()])) }
+                    let (line, name) = split_line(&s[j + 1..s.len()]);
+                    Symbol { file: Some(s[0..j].to_string()), name: Some(normalize_function_name(name)), line }
                 }
-                None => Symbol { name: Some(normalize_function_name(s)), file: None },
+                None => Symbol { name: Some(normalize_function_name(s)), file: None, line: None },
             })
             .collect();
         stack.reverse();
@@ -98,31 +100,41 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut loc_tbl = vec![];
     let mut fn_tbl = vec![];
     let mut functions = HashMap::new();
+    let mut locations = HashMap::new();
     for Frame { stack, cycles } in &frames {
         let mut locs = vec![];
         for symbol in stack {
             let name = symbol.name();
-            if let Some(loc_idx) = functions.get(&name) {
+            // a location for every line of a function, or a single one if lines are unknown
+            let line = symbol.line.unwrap_or(0);
+            if let Some(loc_idx) = locations.get(&(name.clone(), line)) {
                 locs.push(*loc_idx);
                 continue;
             }
-            let function_id = fn_tbl.len() as u64 + 1;
-            let function = profile::Function {
-                id: function_id,
-                name: strings[&name] as i64,
-                // TODO: distinguish between C++ mangled & unmangled names
-                system_name: strings[&name] as i64,
-                filename: strings[&symbol.file()] as i64,
-                ..Default::default()
+            let function_id = match functions.get(&name) {
+                Some(function_id) => *function_id,
+                None => {
+                    let function_id = fn_tbl.len() as u64 + 1;
+                    let function = profile::Function {
+                        id: function_id,
+                        name: strings[&name] as i64,
+                        // TODO: distinguish between C++ mangled & unmangled names
+                        system_name: strings[&name] as i64,
+                        filename: strings[&symbol.file()] as i64,
+                        ..Default::default()
+                    };
+                    functions.insert(name.clone(), function_id);
+                    fn_tbl.push(function);
+                    function_id
+                }
             };
-            functions.insert(name, function_id);
-            let line = profile::Line { function_id, line: 0, ..Default::default() };
-            let loc = profile::Location { id: function_id, line: vec![line].into(), ..Default::default() };
-            // the fn_tbl has the same length with loc_tbl
-            fn_tbl.push(function);
+            let location_id = loc_tbl.len() as u64 + 1;
+            let loc_line = profile::Line { function_id, line: line as i64, ..Default::default() };
+            let loc = profile::Location { id: location_id, line: vec![loc_line].into(), ..Default::default() };
+            locations.insert((name, line), location_id);
             loc_tbl.push(loc);
             // current frame locations
-            locs.push(function_id);
+            locs.push(location_id);
         }
         let sample = profile::Sample {
             location_id: locs,
@@ -152,6 +164,18 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+// Frames written by `ckb-debugger --pprof-lines` are in the form of file:line:func, where the line is ?? if unknown.
+fn split_line(s: &str) -> (Option<u32>, &str) {
+    match s.split_once(":") {
+        Some(("??", name)) => (Some(0), name),
+        Some((line, name)) => match u32::from_str(line) {
+            Ok(line) => (Some(line), name),
+            Err(_) => (None, s),
+        },
+        None => (None, s),
+    }
+}
+
 fn normalize_function_name(name: &str) -> String {
     name.replace("<", "{").replace(">", "}").to_string()
 }
@@ -1 +1,4 @@
+// The generated code allows lints that are removed from recent compilers.
+#![allow(renamed_and_removed_lints)]
+
 pub mod profile;